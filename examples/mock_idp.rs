@@ -0,0 +1,195 @@
+//! A minimal OpenID Connect provider for local development and for exercising
+//! the SSO flow without a real identity provider.
+//!
+//! It approves every authorization request immediately, signing in as the
+//! `login_hint` query parameter (default `alice`), and signs ID tokens with an
+//! Ed25519 key generated at startup.
+//!
+//! ```sh
+//! cargo run --example mock_idp
+//! OIDC_ISSUER=http://127.0.0.1:9090 OIDC_CLIENT_ID=actix-doc \
+//!   OIDC_REDIRECT_URI=http://localhost:3000/auth/callback cargo run
+//! ```
+
+use actix_web::{get, post, web, App, HttpResponse, HttpServer};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Mutex};
+
+const KEY_ID: &str = "mock-key";
+
+struct Provider {
+    issuer: String,
+    pkcs8: Vec<u8>,
+    public_key: Vec<u8>,
+    codes: Mutex<HashMap<String, PendingCode>>,
+}
+
+struct PendingCode {
+    client_id: String,
+    redirect_uri: String,
+    nonce: Option<String>,
+    code_challenge: String,
+    subject: String,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    nonce: Option<String>,
+    code_challenge: String,
+    code_challenge_method: String,
+    login_hint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+fn oauth_error(error: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({ "error": error }))
+}
+
+#[get("/.well-known/openid-configuration")]
+async fn discovery(provider: web::Data<Provider>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": format!("{}/authorize", provider.issuer),
+        "token_endpoint": format!("{}/token", provider.issuer),
+        "jwks_uri": format!("{}/jwks", provider.issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["EdDSA"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+#[get("/jwks")]
+async fn jwks(provider: web::Data<Provider>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": URL_SAFE_NO_PAD.encode(&provider.public_key),
+            "kid": KEY_ID,
+            "alg": "EdDSA",
+            "use": "sig",
+        }]
+    }))
+}
+
+#[get("/authorize")]
+async fn authorize(
+    provider: web::Data<Provider>,
+    query: web::Query<AuthorizeQuery>,
+) -> HttpResponse {
+    if query.code_challenge_method != "S256" {
+        return oauth_error("invalid_request");
+    }
+
+    let code = hex::encode(rand::random::<[u8; 16]>());
+    provider.codes.lock().unwrap().insert(
+        code.clone(),
+        PendingCode {
+            client_id: query.client_id.clone(),
+            redirect_uri: query.redirect_uri.clone(),
+            nonce: query.nonce.clone(),
+            code_challenge: query.code_challenge.clone(),
+            subject: query.login_hint.clone().unwrap_or_else(|| "alice".into()),
+        },
+    );
+
+    let separator = if query.redirect_uri.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    let location = format!(
+        "{}{}code={}&state={}",
+        query.redirect_uri, separator, code, query.state
+    );
+    HttpResponse::Found()
+        .insert_header(("Location", location))
+        .finish()
+}
+
+#[post("/token")]
+async fn token(provider: web::Data<Provider>, form: web::Form<TokenForm>) -> HttpResponse {
+    if form.grant_type != "authorization_code" {
+        return oauth_error("unsupported_grant_type");
+    }
+    let pending = match provider.codes.lock().unwrap().remove(&form.code) {
+        Some(pending) => pending,
+        None => return oauth_error("invalid_grant"),
+    };
+    if pending.client_id != form.client_id || pending.redirect_uri != form.redirect_uri {
+        return oauth_error("invalid_grant");
+    }
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form.code_verifier.as_bytes()));
+    if challenge != pending.code_challenge {
+        return oauth_error("invalid_grant");
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = serde_json::json!({
+        "iss": provider.issuer,
+        "aud": pending.client_id,
+        "sub": pending.subject,
+        "nonce": pending.nonce,
+        "iat": now,
+        "exp": now + 300,
+        "preferred_username": pending.subject,
+        "email": format!("{}@example.com", pending.subject),
+    });
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = encode(&header, &claims, &EncodingKey::from_ed_der(&provider.pkcs8))
+        .expect("sign id token");
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "access_token": hex::encode(rand::random::<[u8; 16]>()),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    }))
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let addr = std::env::var("MOCK_IDP_ADDR").unwrap_or_else(|_| "127.0.0.1:9090".to_string());
+
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).expect("generate key");
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("parse key");
+
+    let provider = web::Data::new(Provider {
+        issuer: format!("http://{}", addr),
+        pkcs8: pkcs8.as_ref().to_vec(),
+        public_key: key_pair.public_key().as_ref().to_vec(),
+        codes: Mutex::new(HashMap::new()),
+    });
+
+    println!("Mock OpenID provider listening on {}", provider.issuer);
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(provider.clone())
+            .service(discovery)
+            .service(jwks)
+            .service(authorize)
+            .service(token)
+    })
+    .bind(addr)?
+    .run()
+    .await
+}
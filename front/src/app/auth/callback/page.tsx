@@ -0,0 +1,44 @@
+"use client";
+
+import { useAuth } from "@/lib/auth";
+import { finishSso } from "@/lib/api";
+import { useEffect, useRef, useState } from "react";
+import Link from "next/link";
+
+export default function SsoCallbackPage() {
+  const { login } = useAuth();
+  const [error, setError] = useState("");
+  const started = useRef(false);
+
+  useEffect(() => {
+    if (started.current) return;
+    started.current = true;
+
+    const params = new URLSearchParams(window.location.search);
+    const code = params.get("code");
+    const state = params.get("state");
+    if (!code || !state) {
+      setError(params.get("error_description") ?? "Single sign-on failed");
+      return;
+    }
+
+    finishSso(code, state)
+      .then((data) => login(data.token, data.username, data.refresh_token))
+      .catch(() => setError("Single sign-on failed"));
+  }, [login]);
+
+  return (
+    <div className="flex min-h-screen items-center justify-center bg-gray-50 px-4">
+      {error ? (
+        <div className="text-center space-y-2">
+          <p className="text-sm text-red-500">{error}</p>
+          <Link href="/login" className="text-sm font-medium text-primary">
+            Back to sign in
+          </Link>
+        </div>
+      ) : (
+        <p className="text-sm text-gray-600">Signing you in...</p>
+      )}
+    </div>
+  );
+}
@@ -3,8 +3,8 @@
 import { Editor } from "@/components/editor/Editor";
 import { useAuth } from "@/lib/auth";
 import { useSearchParams } from "next/navigation";
-import { useEffect, useState, Suspense, useCallback } from "react";
-import { Document, getDoc, updateDoc } from "@/lib/api";
+import { useEffect, useRef, useState, Suspense, useCallback } from "react";
+import { ConflictError, Document, getDoc, updateDoc } from "@/lib/api";
 import { useDebounce } from "@/hooks/use-debounce";
 
 function DocumentEditor() {
@@ -13,6 +13,8 @@ function DocumentEditor() {
   const { user } = useAuth();
   const [doc, setDoc] = useState<Document | null>(null);
   const [isLoading, setIsLoading] = useState(true);
+  // Version the unsaved edits are based on, sent as If-Match.
+  const version = useRef<number | undefined>(undefined);
 
   useEffect(() => {
     if (id) {
@@ -23,6 +25,7 @@ function DocumentEditor() {
   const loadDoc = async (id: string) => {
     try {
       const data = await getDoc(id);
+      version.current = data.version;
       setDoc(data);
     } catch (error) {
       console.error("Failed to load document:", error);
@@ -33,9 +36,23 @@ function DocumentEditor() {
 
   const handleSave = useCallback(async (docId: string, content: string) => {
     try {
-      await updateDoc(docId, { content });
+      const saved = await updateDoc(docId, { content, autosave: true }, version.current);
+      version.current = saved.version;
       console.log("Document saved");
     } catch (error) {
+      if (error instanceof ConflictError) {
+        if (
+          confirm(
+            "This document was changed elsewhere. Overwrite those changes with yours? Cancel reloads the latest version.",
+          )
+        ) {
+          const saved = await updateDoc(docId, { content, autosave: true });
+          version.current = saved.version;
+        } else {
+          await loadDoc(docId);
+        }
+        return;
+      }
       console.error("Failed to save document:", error);
     }
   }, []);
@@ -66,7 +83,7 @@ function DocumentEditor() {
         <Editor
           content={doc.content || ""}
           onChange={handleChange}
-          editable={true}
+          editable={doc.access === "owner" || doc.access === "editor"}
         />
       </div>
     </div>
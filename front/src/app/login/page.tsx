@@ -3,7 +3,7 @@
 import { Button } from "@/components/ui/button";
 import { Input } from "@/components/ui/input";
 import { useAuth } from "@/lib/auth";
-import { login as apiLogin } from "@/lib/api";
+import { login as apiLogin, loginTwoFactor, startSso } from "@/lib/api";
 import { useState } from "react";
 import Link from "next/link";
 
@@ -11,6 +11,8 @@ export default function LoginPage() {
   const { login } = useAuth();
   const [username, setUsername] = useState("");
   const [password, setPassword] = useState("");
+  const [code, setCode] = useState("");
+  const [challengeToken, setChallengeToken] = useState<string | null>(null);
   const [error, setError] = useState("");
   const [loading, setLoading] = useState(false);
 
@@ -20,10 +22,23 @@ export default function LoginPage() {
     setLoading(true);
 
     try {
+      if (challengeToken) {
+        const data = await loginTwoFactor(challengeToken, code);
+        login(data.token, data.username, data.refresh_token);
+        return;
+      }
       const data = await apiLogin({ username, password });
-      login(data.token, data.username);
+      if (data.two_factor_required) {
+        setChallengeToken(data.challenge_token);
+        return;
+      }
+      login(data.token, data.username, data.refresh_token);
     } catch (err) {
-      setError("Invalid username or password");
+      setError(
+        challengeToken
+          ? "Invalid authentication code"
+          : "Invalid username or password",
+      );
     } finally {
       setLoading(false);
     }
@@ -72,6 +87,21 @@ export default function LoginPage() {
                 autoComplete="current-password"
               />
             </div>
+            {challengeToken && (
+              <div className="space-y-2 mt-4">
+                <Input
+                  id="code"
+                  name="code"
+                  type="text"
+                  required
+                  placeholder="Authentication or recovery code"
+                  value={code}
+                  onChange={(e) => setCode(e.target.value)}
+                  autoComplete="one-time-code"
+                  autoFocus
+                />
+              </div>
+            )}
           </div>
 
           {error && (
@@ -84,10 +114,18 @@ export default function LoginPage() {
             </Button>
           </div>
 
-          <div className="text-center text-xs text-muted-foreground">
-            <p>Default admin: admin / admin</p>
-            <p>Default demo: demo / demo</p>
-          </div>
+          <Button
+            type="button"
+            variant="outline"
+            className="w-full"
+            onClick={() =>
+              startSso().catch(() =>
+                setError("Single sign-on is not available"),
+              )
+            }
+          >
+            Sign in with SSO
+          </Button>
         </form>
       </div>
     </div>
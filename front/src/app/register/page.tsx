@@ -2,8 +2,12 @@
 
 import { Button } from "@/components/ui/button";
 import { Input } from "@/components/ui/input";
-import { register as apiRegister } from "@/lib/api";
-import { useState } from "react";
+import {
+  register as apiRegister,
+  fetchRegistrationInfo,
+  RegistrationError,
+} from "@/lib/api";
+import { useEffect, useState } from "react";
 import Link from "next/link";
 import { useRouter } from "next/navigation";
 
@@ -11,19 +15,38 @@ export default function RegisterPage() {
   const router = useRouter();
   const [username, setUsername] = useState("");
   const [password, setPassword] = useState("");
+  const [inviteCode, setInviteCode] = useState("");
+  const [mode, setMode] = useState("open");
   const [error, setError] = useState("");
+  const [fieldErrors, setFieldErrors] = useState<Record<string, string[]>>({});
   const [loading, setLoading] = useState(false);
 
+  useEffect(() => {
+    fetchRegistrationInfo()
+      .then((info) => setMode(info.mode))
+      .catch(() => {});
+  }, []);
+
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
     setError("");
+    setFieldErrors({});
     setLoading(true);
 
     try {
-      await apiRegister({ username, password });
+      await apiRegister({
+        username,
+        password,
+        invite_code: inviteCode || undefined,
+      });
       router.push("/login?registered=true");
     } catch (err) {
-      setError("Registration failed. Username might be taken.");
+      if (err instanceof RegistrationError) {
+        setError(err.message);
+        setFieldErrors(err.fields);
+      } else {
+        setError("Registration failed.");
+      }
     } finally {
       setLoading(false);
     }
@@ -59,6 +82,11 @@ export default function RegisterPage() {
                 onChange={(e) => setUsername(e.target.value)}
                 autoComplete="username"
               />
+              {fieldErrors.username?.map((msg) => (
+                <p key={msg} className="text-xs text-red-500">
+                  {msg}
+                </p>
+              ))}
             </div>
             <div className="space-y-2 mt-4">
               <Input
@@ -71,15 +99,48 @@ export default function RegisterPage() {
                 onChange={(e) => setPassword(e.target.value)}
                 autoComplete="new-password"
               />
+              {fieldErrors.password?.map((msg) => (
+                <p key={msg} className="text-xs text-red-500">
+                  {msg}
+                </p>
+              ))}
             </div>
+            {mode === "invite_only" && (
+              <div className="space-y-2 mt-4">
+                <Input
+                  id="invite_code"
+                  name="invite_code"
+                  type="text"
+                  required
+                  placeholder="Invite code"
+                  value={inviteCode}
+                  onChange={(e) => setInviteCode(e.target.value)}
+                />
+                {fieldErrors.invite_code?.map((msg) => (
+                  <p key={msg} className="text-xs text-red-500">
+                    {msg}
+                  </p>
+                ))}
+              </div>
+            )}
           </div>
 
+          {mode === "closed" && (
+            <div className="text-sm text-gray-600 text-center">
+              Registration is currently closed.
+            </div>
+          )}
+
           {error && (
             <div className="text-sm text-red-500 text-center">{error}</div>
           )}
 
           <div>
-            <Button type="submit" className="w-full" disabled={loading}>
+            <Button
+              type="submit"
+              className="w-full"
+              disabled={loading || mode === "closed"}
+            >
               {loading ? "Creating account..." : "Sign up"}
             </Button>
           </div>
@@ -1,9 +1,23 @@
 "use client";
 
 import { Button } from "@/components/ui/button";
+import { Input } from "@/components/ui/input";
 import { useAuth } from "@/lib/auth";
+import { Avatar, AvatarFallback, AvatarImage } from "@/components/ui/avatar";
+import {
+  Profile,
+  ProfileError,
+  avatarSrc,
+  deleteAccount,
+  deleteAvatar,
+  exportAccount,
+  fetchProfile,
+  updateProfile,
+  uploadAvatar,
+} from "@/lib/api";
 import { useRouter } from "next/navigation";
-import { LogOut, User } from "lucide-react";
+import { useEffect, useState } from "react";
+import { Download, LogOut, Trash2, User } from "lucide-react";
 import { ModeToggle } from "@/components/theme/ModeToggle";
 import { ThemeCustomizer } from "@/components/theme/ThemeCustomizer";
 
@@ -11,30 +25,144 @@ export default function SettingsPage() {
   const { user, logout } = useAuth();
   const router = useRouter();
 
+  const [password, setPassword] = useState("");
+  const [error, setError] = useState("");
+  const [profile, setProfile] = useState<Profile | null>(null);
+  const [form, setForm] = useState({ display_name: "", email: "", locale: "", timezone: "" });
+  const [fieldErrors, setFieldErrors] = useState<Record<string, string[]>>({});
+  const [saved, setSaved] = useState(false);
+
+  const showProfile = (p: Profile) => {
+    setProfile(p);
+    setForm({
+      display_name: p.display_name ?? "",
+      email: p.email ?? "",
+      locale: p.locale ?? "",
+      timezone: p.timezone ?? "",
+    });
+  };
+
+  useEffect(() => {
+    fetchProfile().then(showProfile).catch(() => {});
+  }, []);
+
+  const handleSave = async (e: React.FormEvent) => {
+    e.preventDefault();
+    setFieldErrors({});
+    setSaved(false);
+    try {
+      showProfile(
+        await updateProfile({
+          display_name: form.display_name || null,
+          email: form.email || null,
+          locale: form.locale || null,
+          timezone: form.timezone || Intl.DateTimeFormat().resolvedOptions().timeZone,
+        }),
+      );
+      setSaved(true);
+    } catch (err) {
+      if (err instanceof ProfileError) setFieldErrors(err.fields);
+    }
+  };
+
+  const handleAvatar = async (file?: File) => {
+    if (!file) return;
+    setError("");
+    try {
+      showProfile(await uploadAvatar(file));
+    } catch (err) {
+      setError(err instanceof Error ? err.message : "Failed to upload avatar");
+    }
+  };
+
   const handleLogout = () => {
     logout();
     router.push("/login");
   };
 
+  const handleDelete = async () => {
+    if (!confirm("Delete your account and all of your documents? This cannot be undone.")) return;
+    setError("");
+    try {
+      await deleteAccount(password);
+      logout();
+    } catch (err) {
+      setError(err instanceof Error ? err.message : "Failed to delete account");
+    }
+  };
+
   return (
     <div className="p-8 max-w-2xl mx-auto">
       <h1 className="text-2xl font-bold mb-8">Settings</h1>
 
       <div className="bg-card rounded-lg border p-6 space-y-6">
         <div className="flex items-center space-x-4">
-          <div className="h-16 w-16 bg-muted rounded-full flex items-center justify-center">
-            <User className="h-8 w-8 text-muted-foreground" />
-          </div>
+          <Avatar className="h-16 w-16">
+            <AvatarImage src={avatarSrc(profile)} alt="" />
+            <AvatarFallback>
+              <User className="h-8 w-8 text-muted-foreground" />
+            </AvatarFallback>
+          </Avatar>
           <div>
             <h2 className="text-xl font-semibold">
-              {user?.username || "Guest User"}
+              {profile?.display_name || user?.username || "Guest User"}
             </h2>
             <p className="text-sm text-muted-foreground">
-              Member since {new Date().getFullYear()}
+              Member since{" "}
+              {profile ? new Date(profile.created_at).getFullYear() : new Date().getFullYear()}
             </p>
+            <div className="mt-2 flex gap-2">
+              <label className="text-sm underline cursor-pointer">
+                Change avatar
+                <input
+                  type="file"
+                  accept="image/png,image/jpeg,image/gif,image/webp"
+                  className="hidden"
+                  onChange={(e) => handleAvatar(e.target.files?.[0])}
+                />
+              </label>
+              {profile?.avatar_url && (
+                <button
+                  className="text-sm underline text-muted-foreground"
+                  onClick={() => deleteAvatar().then(showProfile).catch(() => {})}
+                >
+                  Remove
+                </button>
+              )}
+            </div>
           </div>
         </div>
 
+        <form onSubmit={handleSave} className="border-t pt-6 space-y-3">
+          <h3 className="text-lg font-medium mb-1">Profile</h3>
+          {(
+            [
+              ["display_name", "Display name", "text"],
+              ["email", "Email", "email"],
+              ["locale", "Language (e.g. zh-CN)", "text"],
+              ["timezone", "Time zone (e.g. Asia/Shanghai)", "text"],
+            ] as const
+          ).map(([field, label, type]) => (
+            <div key={field} className="space-y-1">
+              <Input
+                type={type}
+                placeholder={label}
+                value={form[field]}
+                onChange={(e) => setForm({ ...form, [field]: e.target.value })}
+              />
+              {fieldErrors[field]?.map((message) => (
+                <p key={message} className="text-sm text-destructive">
+                  {message}
+                </p>
+              ))}
+            </div>
+          ))}
+          <div className="flex items-center gap-3">
+            <Button type="submit">Save</Button>
+            {saved && <span className="text-sm text-muted-foreground">Saved</span>}
+          </div>
+        </form>
+
         <div className="border-t pt-6">
           <div className="mb-6">
             <h3 className="text-lg font-medium mb-4">Appearance</h3>
@@ -56,14 +184,44 @@ export default function SettingsPage() {
           </div>
 
           <h3 className="text-lg font-medium mb-4">Account</h3>
-          <Button
-            variant="destructive"
-            onClick={handleLogout}
-            className="w-full sm:w-auto"
-          >
-            <LogOut className="mr-2 h-4 w-4" />
-            Log out
-          </Button>
+          <div className="flex flex-wrap gap-2">
+            <Button
+              variant="outline"
+              onClick={() => exportAccount().catch(() => setError("Failed to export data"))}
+              className="w-full sm:w-auto"
+            >
+              <Download className="mr-2 h-4 w-4" />
+              Download my data
+            </Button>
+            <Button
+              variant="destructive"
+              onClick={handleLogout}
+              className="w-full sm:w-auto"
+            >
+              <LogOut className="mr-2 h-4 w-4" />
+              Log out
+            </Button>
+          </div>
+
+          <div className="mt-6 space-y-2">
+            <p className="font-medium">Delete account</p>
+            <p className="text-sm text-muted-foreground">
+              Permanently removes your account and everything you own.
+            </p>
+            <div className="flex gap-2">
+              <Input
+                type="password"
+                placeholder="Current password"
+                value={password}
+                onChange={(e) => setPassword(e.target.value)}
+              />
+              <Button variant="destructive" onClick={handleDelete} disabled={!password}>
+                <Trash2 className="mr-2 h-4 w-4" />
+                Delete
+              </Button>
+            </div>
+            {error && <p className="text-sm text-destructive">{error}</p>}
+          </div>
         </div>
       </div>
     </div>
@@ -4,7 +4,7 @@ import { useEffect, useState } from "react";
 import { Editor } from "./Editor";
 import { Button } from "@/components/ui/button";
 import { Input } from "@/components/ui/input";
-import { getDoc, updateDoc } from "@/lib/api";
+import { ConflictError, getDoc, updateDoc } from "@/lib/api";
 import { Save, Loader2 } from "lucide-react";
 
 import { TagInput } from "@/components/ui/tag-input";
@@ -17,6 +17,7 @@ export function DocumentEditor({ documentId }: DocumentEditorProps) {
   const [title, setTitle] = useState("");
   const [content, setContent] = useState("");
   const [tags, setTags] = useState<string[]>([]);
+  const [version, setVersion] = useState<number | undefined>(undefined);
   const [isLoading, setIsLoading] = useState(true);
   const [isSaving, setIsSaving] = useState(false);
   const [lastSaved, setLastSaved] = useState<Date | null>(null);
@@ -33,6 +34,7 @@ export function DocumentEditor({ documentId }: DocumentEditorProps) {
       setTitle(doc.title);
       setContent(doc.content || "");
       setTags(doc.tags?.map((t) => t.id) || []);
+      setVersion(doc.version);
     } catch (error) {
       console.error("Failed to load document:", error);
     } finally {
@@ -40,12 +42,29 @@ export function DocumentEditor({ documentId }: DocumentEditorProps) {
     }
   };
 
-  const handleSave = async () => {
+  const handleSave = async (overwrite = false) => {
     try {
       setIsSaving(true);
-      await updateDoc(documentId, { title, content, tags });
+      const saved = await updateDoc(
+        documentId,
+        { title, content, tags },
+        overwrite ? undefined : version,
+      );
+      setVersion(saved.version);
       setLastSaved(new Date());
     } catch (error) {
+      if (error instanceof ConflictError) {
+        if (
+          confirm(
+            "This document was changed elsewhere since you opened it. Overwrite those changes? Cancel reloads the latest version.",
+          )
+        ) {
+          await handleSave(true);
+        } else {
+          await loadDocument();
+        }
+        return;
+      }
       console.error("Failed to save document:", error);
       alert("Failed to save document");
     } finally {
@@ -76,7 +95,7 @@ export function DocumentEditor({ documentId }: DocumentEditorProps) {
               Saved {lastSaved.toLocaleTimeString()}
             </span>
           )}
-          <Button onClick={handleSave} disabled={isSaving}>
+          <Button onClick={() => handleSave()} disabled={isSaving}>
             {isSaving ? (
               <>
                 <Loader2 className="mr-2 h-4 w-4 animate-spin" />
@@ -20,6 +20,7 @@ import { useRouter } from "next/navigation";
 import { useState } from "react";
 import { useDocuments } from "@/components/providers/DocumentsProvider";
 import { SearchDialog } from "@/components/search-dialog";
+import { WorkspaceSwitcher } from "@/components/layout/WorkspaceSwitcher";
 import { AppRouterInstance } from "next/dist/shared/lib/app-router-context.shared-runtime";
 import {
   Collapsible,
@@ -233,10 +234,8 @@ export function AppSidebar({ className }: SidebarProps) {
               <span className="text-xs">⌘</span>K
             </kbd>
           </Button>
-          <div className="flex items-center justify-between px-4 mb-2">
-            <h2 className="text-lg font-semibold tracking-tight">
-              My Workspace
-            </h2>
+          <div className="mb-2">
+            <WorkspaceSwitcher />
           </div>
           <div className="flex space-x-2 px-2">
             <Button
@@ -9,7 +9,7 @@ import { DocumentsProvider } from "@/components/providers/DocumentsProvider";
 function AuthContent({ children }: { children: React.ReactNode }) {
   const { isAuthenticated, isLoading } = useAuth();
   const pathname = usePathname();
-  const isAuthPage = ["/login", "/register"].includes(pathname);
+  const isAuthPage = ["/login", "/register", "/auth/callback"].includes(pathname);
 
   if (isAuthPage) {
     return <>{children}</>;
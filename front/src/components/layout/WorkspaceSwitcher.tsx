@@ -0,0 +1,158 @@
+"use client";
+
+import { useCallback, useEffect, useState } from "react";
+import { Check, ChevronsUpDown, Mail, Plus, User, Users } from "lucide-react";
+import { Button } from "@/components/ui/button";
+import {
+  DropdownMenu,
+  DropdownMenuContent,
+  DropdownMenuItem,
+  DropdownMenuLabel,
+  DropdownMenuSeparator,
+  DropdownMenuTrigger,
+} from "@/components/ui/dropdown-menu";
+import {
+  createWorkspace,
+  fetchInvitations,
+  fetchWorkspaces,
+  respondToInvitation,
+  switchWorkspace,
+  Workspace,
+  WorkspaceInvitation,
+} from "@/lib/api";
+import { useDocuments } from "@/components/providers/DocumentsProvider";
+import { useRouter } from "next/navigation";
+
+export function WorkspaceSwitcher() {
+  const router = useRouter();
+  const { refreshDocs } = useDocuments();
+  const [workspaces, setWorkspaces] = useState<Workspace[]>([]);
+  const [invitations, setInvitations] = useState<WorkspaceInvitation[]>([]);
+
+  const load = useCallback(async () => {
+    try {
+      const [list, pending] = await Promise.all([
+        fetchWorkspaces(),
+        fetchInvitations(),
+      ]);
+      setWorkspaces(list);
+      setInvitations(pending);
+    } catch (error) {
+      console.error("Failed to load workspaces:", error);
+    }
+  }, []);
+
+  useEffect(() => {
+    load();
+  }, [load]);
+
+  const active = workspaces.find((w) => w.active);
+
+  const handleSwitch = async (id: string | null) => {
+    try {
+      await switchWorkspace(id);
+      await Promise.all([load(), refreshDocs()]);
+      router.push("/");
+    } catch (error) {
+      console.error(error);
+    }
+  };
+
+  const handleCreate = async () => {
+    const name = prompt("Workspace name");
+    if (!name?.trim()) return;
+    try {
+      const workspace = await createWorkspace(name.trim());
+      await handleSwitch(workspace.id);
+    } catch (error) {
+      console.error(error);
+    }
+  };
+
+  const handleInvitation = async (id: string, accept: boolean) => {
+    try {
+      await respondToInvitation(id, accept);
+      await load();
+    } catch (error) {
+      console.error(error);
+    }
+  };
+
+  return (
+    <DropdownMenu>
+      <DropdownMenuTrigger asChild>
+        <Button
+          variant="ghost"
+          className="w-full justify-between px-4 text-lg font-semibold tracking-tight"
+        >
+          <span className="truncate">{active ? active.name : "Personal"}</span>
+          <ChevronsUpDown className="ml-2 h-4 w-4 shrink-0 text-muted-foreground" />
+        </Button>
+      </DropdownMenuTrigger>
+      <DropdownMenuContent className="w-64" align="start">
+        <DropdownMenuItem onClick={() => handleSwitch(null)}>
+          <User className="mr-2 h-4 w-4" />
+          <span className="flex-1">Personal</span>
+          {!active && <Check className="h-4 w-4" />}
+        </DropdownMenuItem>
+        {workspaces.length > 0 && (
+          <>
+            <DropdownMenuSeparator />
+            <DropdownMenuLabel>Workspaces</DropdownMenuLabel>
+            {workspaces.map((workspace) => (
+              <DropdownMenuItem
+                key={workspace.id}
+                onClick={() => handleSwitch(workspace.id)}
+              >
+                <Users className="mr-2 h-4 w-4" />
+                <span className="flex-1 truncate">{workspace.name}</span>
+                <span className="ml-2 text-xs text-muted-foreground">
+                  {workspace.role}
+                </span>
+                {workspace.active && <Check className="ml-2 h-4 w-4" />}
+              </DropdownMenuItem>
+            ))}
+          </>
+        )}
+        {invitations.length > 0 && (
+          <>
+            <DropdownMenuSeparator />
+            <DropdownMenuLabel>Invitations</DropdownMenuLabel>
+            {invitations.map((invitation) => (
+              <div
+                key={invitation.id}
+                className="flex items-center px-2 py-1.5 text-sm"
+              >
+                <Mail className="mr-2 h-4 w-4 shrink-0" />
+                <span className="flex-1 truncate">
+                  {invitation.workspace_name}
+                </span>
+                <Button
+                  variant="ghost"
+                  size="sm"
+                  className="h-6 px-2"
+                  onClick={() => handleInvitation(invitation.id, true)}
+                >
+                  Join
+                </Button>
+                <Button
+                  variant="ghost"
+                  size="sm"
+                  className="h-6 px-2 text-muted-foreground"
+                  onClick={() => handleInvitation(invitation.id, false)}
+                >
+                  Decline
+                </Button>
+              </div>
+            ))}
+          </>
+        )}
+        <DropdownMenuSeparator />
+        <DropdownMenuItem onClick={handleCreate}>
+          <Plus className="mr-2 h-4 w-4" />
+          New workspace
+        </DropdownMenuItem>
+      </DropdownMenuContent>
+    </DropdownMenu>
+  );
+}
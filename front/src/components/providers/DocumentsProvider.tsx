@@ -7,7 +7,13 @@ import React, {
   useEffect,
   useCallback,
 } from "react";
-import { fetchDocs, createDoc, deleteDoc, Document } from "@/lib/api";
+import {
+  fetchDocs,
+  createDoc,
+  deleteDoc,
+  subscribeEvents,
+  Document,
+} from "@/lib/api";
 import { useRouter } from "next/navigation";
 
 interface DocumentsContextType {
@@ -45,6 +51,21 @@ export function DocumentsProvider({ children }: { children: React.ReactNode }) {
     refreshDocs();
   }, [refreshDocs]);
 
+  useEffect(() => {
+    // Refetch when documents change elsewhere; bursts of events (e.g. a
+    // folder of moves) cause a single refetch.
+    let pending: ReturnType<typeof setTimeout> | undefined;
+    const unsubscribe = subscribeEvents((type) => {
+      if (type === "tag.created") return;
+      clearTimeout(pending);
+      pending = setTimeout(refreshDocs, 200);
+    });
+    return () => {
+      clearTimeout(pending);
+      unsubscribe();
+    };
+  }, [refreshDocs]);
+
   const createNewDoc = async (isFolder: boolean, parentId?: string) => {
     try {
       const doc = await createDoc(
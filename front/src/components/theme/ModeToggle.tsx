@@ -3,6 +3,7 @@
 import * as React from "react";
 import { Moon, Sun } from "lucide-react";
 import { useTheme } from "next-themes";
+import { updateProfile } from "@/lib/api";
 
 import { Button } from "@/components/ui/button";
 import {
@@ -13,7 +14,12 @@ import {
 } from "@/components/ui/dropdown-menu";
 
 export function ModeToggle() {
-  const { setTheme } = useTheme();
+  const { setTheme: applyTheme } = useTheme();
+
+  const setTheme = (mode: string) => {
+    applyTheme(mode);
+    updateProfile({ preferences: { theme_mode: mode } }).catch(() => {});
+  };
 
   return (
     <DropdownMenu>
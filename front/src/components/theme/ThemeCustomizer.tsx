@@ -6,6 +6,7 @@ import { Check } from "lucide-react";
 import { cn } from "@/lib/utils";
 import { Button } from "@/components/ui/button";
 import { useTheme } from "next-themes";
+import { fetchProfile, updateProfile } from "@/lib/api";
 
 const themes = [
   {
@@ -59,6 +60,20 @@ export function ThemeCustomizer() {
     const savedColor = localStorage.getItem("theme-color") || "zinc";
     setColor(savedColor);
     document.body.classList.add(`theme-${savedColor}`);
+
+    // The copy saved in the profile follows the user across devices.
+    fetchProfile()
+      .then((profile) => {
+        const mode = profile.preferences.theme_mode;
+        if (typeof mode === "string") setTheme(mode);
+        const synced = profile.preferences.theme_color;
+        if (typeof synced !== "string" || synced === savedColor) return;
+        document.body.classList.remove(`theme-${savedColor}`);
+        document.body.classList.add(`theme-${synced}`);
+        setColor(synced);
+        localStorage.setItem("theme-color", synced);
+      })
+      .catch(() => {});
   }, []);
 
   const handleColorChange = (newColor: string) => {
@@ -68,6 +83,7 @@ export function ThemeCustomizer() {
     document.body.classList.add(`theme-${newColor}`);
     setColor(newColor);
     localStorage.setItem("theme-color", newColor);
+    updateProfile({ preferences: { theme_color: newColor } }).catch(() => {});
   };
 
   if (!mounted) {
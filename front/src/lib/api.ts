@@ -17,40 +17,242 @@ export interface Document {
   updated_at: string;
   tags?: Tag[];
   deleted_at?: string | null;
+  version: number;
+  workspace_id: string | null;
+  access: Role;
 }
 
-function getHeaders() {
-  const token = localStorage.getItem("token");
+export type Role = "viewer" | "commenter" | "editor" | "owner";
+
+// Thrown when a save was based on an outdated version of the document.
+export class ConflictError extends Error {
+  constructor(public currentVersion: number) {
+    super("Document has been modified elsewhere");
+  }
+}
+
+// With NEXT_PUBLIC_AUTH_MODE=cookie the tokens live in HttpOnly cookies set by
+// the backend, and unsafe requests echo the adoc_csrf cookie as a header.
+export const COOKIE_AUTH = process.env.NEXT_PUBLIC_AUTH_MODE === "cookie";
+const credentials: RequestCredentials = COOKIE_AUTH ? "include" : "same-origin";
+
+function csrfToken(): string {
+  const match = document.cookie.match(/(?:^|;\s*)adoc_csrf=([^;]*)/);
+  return match ? decodeURIComponent(match[1]) : "";
+}
+
+function baseHeaders(): Record<string, string> {
+  if (!COOKIE_AUTH) return { "Content-Type": "application/json" };
   return {
     "Content-Type": "application/json",
+    "X-Auth-Mode": "cookie",
+    "X-CSRF-Token": csrfToken(),
+  };
+}
+
+function getHeaders() {
+  const token = COOKIE_AUTH ? null : localStorage.getItem("token");
+  return {
+    ...baseHeaders(),
     ...(token ? { Authorization: `Bearer ${token}` } : {}),
   };
 }
 
+async function refreshSession(): Promise<boolean> {
+  const refreshToken = localStorage.getItem("refresh_token");
+  if (!COOKIE_AUTH && !refreshToken) return false;
+  const res = await fetch(`${API_URL}/auth/refresh`, {
+    method: "POST",
+    headers: baseHeaders(),
+    credentials,
+    body: JSON.stringify(COOKIE_AUTH ? {} : { refresh_token: refreshToken }),
+  });
+  if (!res.ok) {
+    localStorage.removeItem("refresh_token");
+    return false;
+  }
+  if (COOKIE_AUTH) return true;
+  const data = await res.json();
+  localStorage.setItem("token", data.token);
+  localStorage.setItem("refresh_token", data.refresh_token);
+  return true;
+}
+
+// Access tokens are short-lived: on a 401, rotate the refresh token once and retry.
+async function authFetch(url: string, init: RequestInit = {}): Promise<Response> {
+  const headers = () => ({ ...getHeaders(), ...(init.headers as Record<string, string>) });
+  const res = await fetch(url, { ...init, headers: headers(), credentials });
+  if (res.status !== 401 || !(await refreshSession())) return res;
+  return fetch(url, { ...init, headers: headers(), credentials });
+}
+
 export async function login(data: any) {
   const res = await fetch(`${API_URL}/auth/login`, {
     method: "POST",
-    headers: { "Content-Type": "application/json" },
+    headers: baseHeaders(),
+    credentials,
     body: JSON.stringify(data),
   });
   if (!res.ok) throw new Error("Login failed");
   return res.json();
 }
 
+export async function loginTwoFactor(challenge_token: string, code: string) {
+  const res = await fetch(`${API_URL}/auth/login/2fa`, {
+    method: "POST",
+    headers: baseHeaders(),
+    credentials,
+    body: JSON.stringify({ challenge_token, code }),
+  });
+  if (!res.ok) throw new Error("Verification failed");
+  return res.json();
+}
+
+export async function startSso(): Promise<void> {
+  const res = await fetch(`${API_URL}/auth/oidc/authorize`, { credentials });
+  if (!res.ok) throw new Error("Single sign-on is not available");
+  const { authorization_url } = await res.json();
+  window.location.href = authorization_url;
+}
+
+export async function finishSso(code: string, state: string) {
+  const res = await fetch(`${API_URL}/auth/oidc/callback`, {
+    method: "POST",
+    headers: baseHeaders(),
+    credentials,
+    body: JSON.stringify({ code, state }),
+  });
+  if (!res.ok) throw new Error("Single sign-on failed");
+  return res.json();
+}
+
 export async function register(data: any) {
   const res = await fetch(`${API_URL}/auth/register`, {
     method: "POST",
     headers: { "Content-Type": "application/json" },
     body: JSON.stringify(data),
   });
-  if (!res.ok) throw new Error("Registration failed");
+  if (!res.ok) {
+    const body = await res.json().catch(() => ({}));
+    throw new RegistrationError(body.error ?? "Registration failed", body.fields ?? {});
+  }
   return res.json();
 }
 
-export async function fetchDocs(): Promise<Document[]> {
-  const res = await fetch(`${API_URL}/documents`, {
-    headers: getHeaders(),
+export class RegistrationError extends Error {
+  constructor(message: string, public fields: Record<string, string[]>) {
+    super(message);
+  }
+}
+
+export async function fetchRegistrationInfo() {
+  const res = await fetch(`${API_URL}/auth/registration`);
+  if (!res.ok) throw new Error("Failed to load registration settings");
+  return res.json();
+}
+
+export async function logout(): Promise<void> {
+  const refreshToken = localStorage.getItem("refresh_token");
+  if (!COOKIE_AUTH && !refreshToken) return;
+  await fetch(`${API_URL}/auth/logout`, {
+    method: "POST",
+    headers: baseHeaders(),
+    credentials,
+    body: JSON.stringify(COOKIE_AUTH ? {} : { refresh_token: refreshToken }),
   });
+}
+
+export interface Profile {
+  id: string;
+  username: string;
+  role: string;
+  display_name: string | null;
+  email: string | null;
+  avatar_url: string | null;
+  locale: string | null;
+  timezone: string | null;
+  preferences: Record<string, unknown>;
+  two_factor_enabled: boolean;
+  created_at: string;
+}
+
+export class ProfileError extends Error {
+  constructor(message: string, public fields: Record<string, string[]>) {
+    super(message);
+  }
+}
+
+export function avatarSrc(profile: Profile | null): string | undefined {
+  return profile?.avatar_url ? `${API_URL}${profile.avatar_url}` : undefined;
+}
+
+async function profileResponse(res: Response): Promise<Profile> {
+  if (!res.ok) {
+    const body = await res.json().catch(() => ({}));
+    throw new ProfileError(body.error ?? "Failed to update profile", body.fields ?? {});
+  }
+  return res.json();
+}
+
+export async function fetchProfile(): Promise<Profile> {
+  const res = await authFetch(`${API_URL}/me`);
+  if (!res.ok) throw new Error("Failed to load profile");
+  return res.json();
+}
+
+// Omitted fields are unchanged, null clears a field; preferences are merged key by key.
+export async function updateProfile(data: {
+  display_name?: string | null;
+  email?: string | null;
+  locale?: string | null;
+  timezone?: string | null;
+  preferences?: Record<string, unknown>;
+}): Promise<Profile> {
+  const res = await authFetch(`${API_URL}/me`, {
+    method: "PATCH",
+    body: JSON.stringify(data),
+  });
+  return profileResponse(res);
+}
+
+export async function uploadAvatar(file: File): Promise<Profile> {
+  const res = await authFetch(`${API_URL}/me/avatar`, {
+    method: "PUT",
+    headers: { "Content-Type": file.type || "application/octet-stream" },
+    body: file,
+  });
+  return profileResponse(res);
+}
+
+export async function deleteAvatar(): Promise<Profile> {
+  const res = await authFetch(`${API_URL}/me/avatar`, { method: "DELETE" });
+  return profileResponse(res);
+}
+
+export async function exportAccount(): Promise<void> {
+  const res = await authFetch(`${API_URL}/me/export`);
+  if (!res.ok) throw new Error("Failed to export data");
+  const url = URL.createObjectURL(await res.blob());
+  const link = document.createElement("a");
+  link.href = url;
+  link.download = "actix-doc-export.zip";
+  link.click();
+  URL.revokeObjectURL(url);
+}
+
+export async function deleteAccount(password: string): Promise<void> {
+  const res = await authFetch(`${API_URL}/me`, {
+    method: "DELETE",
+    body: JSON.stringify({ password }),
+  });
+  if (!res.ok) {
+    const body = await res.json().catch(() => ({}));
+    throw new Error(body.fields?.password?.[0] ?? body.error ?? "Failed to delete account");
+  }
+}
+
+export async function fetchDocs(): Promise<Document[]> {
+  const res = await authFetch(`${API_URL}/documents`);
   if (res.status === 401) {
     localStorage.removeItem("token");
     window.location.href = "/login";
@@ -60,15 +262,69 @@ export async function fetchDocs(): Promise<Document[]> {
   return res.json();
 }
 
+// Changes pushed by GET /events. EventSource reconnects by itself and resumes
+// with Last-Event-ID; when the server refuses it (e.g. the access token
+// expired) the session is refreshed and a new stream opened.
+const CHANGE_EVENTS = [
+  "document.created",
+  "document.updated",
+  "document.moved",
+  "document.trashed",
+  "document.restored",
+  "document.purged",
+  "document.access_changed",
+  "tag.created",
+  "reset",
+];
+
+export function subscribeEvents(
+  onEvent: (type: string, data: any) => void,
+): () => void {
+  let source: EventSource | null = null;
+  let lastEventId: string | undefined;
+  let retry: ReturnType<typeof setTimeout> | undefined;
+  let closed = false;
+
+  const open = () => {
+    const params = new URLSearchParams();
+    const token = COOKIE_AUTH ? null : localStorage.getItem("token");
+    if (token) params.set("token", token);
+    if (lastEventId) params.set("last_event_id", lastEventId);
+    source = new EventSource(`${API_URL}/events?${params}`, {
+      withCredentials: COOKIE_AUTH,
+    });
+    for (const type of CHANGE_EVENTS) {
+      source.addEventListener(type, (e) => {
+        const message = e as MessageEvent;
+        if (message.lastEventId) lastEventId = message.lastEventId;
+        onEvent(type, JSON.parse(message.data));
+      });
+    }
+    source.onerror = () => {
+      if (closed || source?.readyState !== EventSource.CLOSED) return;
+      retry = setTimeout(async () => {
+        await refreshSession();
+        if (!closed) open();
+      }, 5000);
+    };
+  };
+
+  open();
+  return () => {
+    closed = true;
+    clearTimeout(retry);
+    source?.close();
+  };
+}
+
 export async function createDoc(
   title: string,
   is_folder: boolean = false,
   parent_id?: string,
   tags?: string[],
 ): Promise<Document> {
-  const res = await fetch(`${API_URL}/documents`, {
+  const res = await authFetch(`${API_URL}/documents`, {
     method: "POST",
-    headers: getHeaders(),
     body: JSON.stringify({
       title,
       is_folder,
@@ -83,9 +339,7 @@ export async function createDoc(
 }
 
 export async function getDoc(id: string): Promise<Document> {
-  const res = await fetch(`${API_URL}/documents/${id}`, {
-    headers: getHeaders(),
-  });
+  const res = await authFetch(`${API_URL}/documents/${id}`);
   if (res.status === 401) {
     localStorage.removeItem("token");
     window.location.href = "/login";
@@ -102,13 +356,23 @@ export async function updateDoc(
     content?: string;
     parent_id?: string;
     tags?: string[];
+    reason?: string;
+    // Consecutive autosaves are squashed into one revision on the server.
+    autosave?: boolean;
   },
+  // Version the edit is based on; the save fails with ConflictError if the
+  // document has changed since.
+  baseVersion?: number,
 ): Promise<Document> {
-  const res = await fetch(`${API_URL}/documents/${id}`, {
+  const res = await authFetch(`${API_URL}/documents/${id}`, {
     method: "PUT",
-    headers: getHeaders(),
+    headers: baseVersion !== undefined ? { "If-Match": `"${baseVersion}"` } : undefined,
     body: JSON.stringify(data),
   });
+  if (res.status === 412) {
+    const body = await res.json().catch(() => ({}));
+    throw new ConflictError(body.current_version);
+  }
   if (res.status === 401) {
     localStorage.removeItem("token");
     window.location.href = "/login";
@@ -119,9 +383,8 @@ export async function updateDoc(
 }
 
 export async function deleteDoc(id: string): Promise<void> {
-  const res = await fetch(`${API_URL}/documents/${id}`, {
+  const res = await authFetch(`${API_URL}/documents/${id}`, {
     method: "DELETE",
-    headers: getHeaders(),
   });
   if (res.status === 401) {
     localStorage.removeItem("token");
@@ -131,18 +394,94 @@ export async function deleteDoc(id: string): Promise<void> {
   if (!res.ok) throw new Error("Failed to delete document");
 }
 
-export async function fetchTags(): Promise<Tag[]> {
-  const res = await fetch(`${API_URL}/tags`, {
-    headers: getHeaders(),
+export interface RevisionInfo {
+  id: string;
+  revision: number;
+  title: string;
+  author_id: string | null;
+  author_name: string | null;
+  reason: string | null;
+  autosave: boolean;
+  created_at: string;
+  updated_at: string;
+}
+
+export interface Revision extends RevisionInfo {
+  content: string | null;
+}
+
+export async function fetchRevisions(id: string): Promise<RevisionInfo[]> {
+  const res = await authFetch(`${API_URL}/documents/${id}/revisions`);
+  if (!res.ok) throw new Error("Failed to fetch revisions");
+  return res.json();
+}
+
+export async function getRevision(id: string, revision: number): Promise<Revision> {
+  const res = await authFetch(`${API_URL}/documents/${id}/revisions/${revision}`);
+  if (!res.ok) throw new Error("Failed to fetch revision");
+  return res.json();
+}
+
+export async function restoreRevision(id: string, revision: number): Promise<Document> {
+  const res = await authFetch(`${API_URL}/documents/${id}/revisions/${revision}/restore`, {
+    method: "POST",
   });
+  if (!res.ok) throw new Error("Failed to restore revision");
+  return res.json();
+}
+
+export type DiffOp = "equal" | "insert" | "delete" | "replace";
+
+export interface RevisionDiff {
+  from: number;
+  to: number;
+  title: { from: string; to: string } | null;
+  blocks: {
+    op: DiffOp;
+    old_index?: number;
+    new_index?: number;
+    text: string;
+    words?: { op: DiffOp; text: string }[];
+  }[];
+  stats: { inserted: number; deleted: number; replaced: number; unchanged: number };
+}
+
+export interface Blame {
+  revision: number;
+  blocks: {
+    text: string;
+    revision: number | null;
+    author_id: string | null;
+    author_name: string | null;
+    changed_at: string | null;
+  }[];
+}
+
+// Without arguments, compares the latest revision with the one before it.
+export async function fetchDiff(id: string, from?: number, to?: number): Promise<RevisionDiff> {
+  const params = new URLSearchParams();
+  if (from !== undefined) params.set("from", String(from));
+  if (to !== undefined) params.set("to", String(to));
+  const res = await authFetch(`${API_URL}/documents/${id}/diff?${params}`);
+  if (!res.ok) throw new Error("Failed to fetch diff");
+  return res.json();
+}
+
+export async function fetchBlame(id: string): Promise<Blame> {
+  const res = await authFetch(`${API_URL}/documents/${id}/blame`);
+  if (!res.ok) throw new Error("Failed to fetch blame");
+  return res.json();
+}
+
+export async function fetchTags(): Promise<Tag[]> {
+  const res = await authFetch(`${API_URL}/tags`);
   if (!res.ok) throw new Error("Failed to fetch tags");
   return res.json();
 }
 
 export async function createTag(name: string): Promise<Tag> {
-  const res = await fetch(`${API_URL}/tags`, {
+  const res = await authFetch(`${API_URL}/tags`, {
     method: "POST",
-    headers: getHeaders(),
     body: JSON.stringify({ name }),
   });
   if (!res.ok) throw new Error("Failed to create tag");
@@ -150,28 +489,21 @@ export async function createTag(name: string): Promise<Tag> {
 }
 
 export async function fetchTrash(): Promise<Document[]> {
-  const token = localStorage.getItem("token");
-  const res = await fetch(`${API_URL}/trash`, {
-    headers: getHeaders(),
-  });
+  const res = await authFetch(`${API_URL}/trash`);
   if (!res.ok) throw new Error("Failed to fetch trash");
   return res.json();
 }
 
 export async function restoreDoc(id: string): Promise<void> {
-  const token = localStorage.getItem("token");
-  const res = await fetch(`${API_URL}/documents/${id}/restore`, {
+  const res = await authFetch(`${API_URL}/documents/${id}/restore`, {
     method: "POST",
-    headers: { Authorization: `Bearer ${token}` },
   });
   if (!res.ok) throw new Error("Failed to restore document");
 }
 
 export async function permanentDeleteDoc(id: string): Promise<void> {
-  const token = localStorage.getItem("token");
-  const res = await fetch(`${API_URL}/documents/${id}/permanent`, {
+  const res = await authFetch(`${API_URL}/documents/${id}/permanent`, {
     method: "DELETE",
-    headers: { Authorization: `Bearer ${token}` },
   });
   if (!res.ok) throw new Error("Failed to delete document permanently");
 }
@@ -184,10 +516,233 @@ export interface SearchResult {
 }
 
 export async function searchDocs(query: string): Promise<SearchResult[]> {
-  const token = localStorage.getItem("token");
-  const res = await fetch(`${API_URL}/search?q=${encodeURIComponent(query)}`, {
-    headers: { Authorization: `Bearer ${token}` },
-  });
+  const res = await authFetch(`${API_URL}/search?q=${encodeURIComponent(query)}`);
   if (!res.ok) throw new Error("Failed to search docs");
   return res.json();
 }
+
+export interface Grant {
+  user_id: string;
+  username: string;
+  role: Role;
+  granted_by?: string;
+  created_at: string;
+  updated_at: string;
+}
+
+export interface SharedDocument {
+  id: string;
+  title: string;
+  is_folder: boolean;
+  workspace_id: string | null;
+  owner_id: string;
+  owner_name: string;
+  role: Role;
+  shared_at: string;
+  updated_at: string;
+}
+
+export async function fetchGrants(docId: string): Promise<Grant[]> {
+  const res = await authFetch(`${API_URL}/documents/${docId}/permissions`);
+  if (!res.ok) throw new Error("Failed to fetch permissions");
+  return res.json();
+}
+
+export async function shareDoc(docId: string, username: string, role: Role) {
+  const res = await authFetch(`${API_URL}/documents/${docId}/permissions`, {
+    method: "POST",
+    body: JSON.stringify({ username, role }),
+  });
+  if (!res.ok) throw new Error("Failed to share document");
+  return res.json();
+}
+
+export async function unshareDoc(docId: string, userId: string) {
+  const res = await authFetch(
+    `${API_URL}/documents/${docId}/permissions/${userId}`,
+    { method: "DELETE" },
+  );
+  if (!res.ok) throw new Error("Failed to revoke access");
+}
+
+export async function fetchSharedWithMe(): Promise<SharedDocument[]> {
+  const res = await authFetch(`${API_URL}/shared`);
+  if (!res.ok) throw new Error("Failed to fetch shared documents");
+  return res.json();
+}
+
+export interface ShareLink {
+  id: string;
+  document_id: string;
+  document_title: string;
+  has_password: boolean;
+  include_subfolders: boolean;
+  expires_at: string | null;
+  last_accessed_at: string | null;
+  created_at: string;
+}
+
+export interface CreatedShareLink extends ShareLink {
+  token: string;
+  url: string;
+}
+
+export async function createShareLink(
+  docId: string,
+  options: {
+    expires_at?: string;
+    password?: string;
+    include_subfolders?: boolean;
+  } = {},
+): Promise<CreatedShareLink> {
+  const res = await authFetch(`${API_URL}/documents/${docId}/share-links`, {
+    method: "POST",
+    body: JSON.stringify(options),
+  });
+  if (!res.ok) throw new Error("Failed to create share link");
+  return res.json();
+}
+
+export async function fetchShareLinks(docId?: string): Promise<ShareLink[]> {
+  const query = docId ? `?document_id=${encodeURIComponent(docId)}` : "";
+  const res = await authFetch(`${API_URL}/share-links${query}`);
+  if (!res.ok) throw new Error("Failed to fetch share links");
+  return res.json();
+}
+
+export async function revokeShareLink(id: string) {
+  const res = await authFetch(`${API_URL}/share-links/${id}`, {
+    method: "DELETE",
+  });
+  if (!res.ok) throw new Error("Failed to revoke share link");
+}
+
+export type WorkspaceRole = "owner" | "admin" | "member" | "guest";
+
+export interface Workspace {
+  id: string;
+  name: string;
+  role: WorkspaceRole;
+  member_count: number;
+  active: boolean;
+  created_at: string;
+}
+
+export interface WorkspaceMember {
+  user_id: string;
+  username: string;
+  display_name: string | null;
+  role: WorkspaceRole;
+  joined_at: string;
+}
+
+export interface WorkspaceInvitation {
+  id: string;
+  workspace_id: string;
+  workspace_name: string;
+  user_id: string;
+  username: string;
+  role: WorkspaceRole;
+  invited_by: string | null;
+  invited_by_name: string | null;
+  created_at: string;
+}
+
+export async function fetchWorkspaces(): Promise<Workspace[]> {
+  const res = await authFetch(`${API_URL}/workspaces`);
+  if (!res.ok) throw new Error("Failed to fetch workspaces");
+  return res.json();
+}
+
+export async function createWorkspace(name: string): Promise<Workspace> {
+  const res = await authFetch(`${API_URL}/workspaces`, {
+    method: "POST",
+    body: JSON.stringify({ name }),
+  });
+  if (!res.ok) throw new Error("Failed to create workspace");
+  return res.json();
+}
+
+/** Switches the active workspace; `null` is the personal space. */
+export async function switchWorkspace(workspaceId: string | null) {
+  const res = await authFetch(`${API_URL}/workspaces/active`, {
+    method: "PUT",
+    body: JSON.stringify({ workspace_id: workspaceId }),
+  });
+  if (!res.ok) throw new Error("Failed to switch workspace");
+  return res.json();
+}
+
+export async function fetchWorkspaceMembers(
+  workspaceId: string,
+): Promise<WorkspaceMember[]> {
+  const res = await authFetch(`${API_URL}/workspaces/${workspaceId}/members`);
+  if (!res.ok) throw new Error("Failed to fetch members");
+  return res.json();
+}
+
+export async function inviteToWorkspace(
+  workspaceId: string,
+  username: string,
+  role: WorkspaceRole,
+) {
+  const res = await authFetch(
+    `${API_URL}/workspaces/${workspaceId}/invitations`,
+    {
+      method: "POST",
+      body: JSON.stringify({ username, role }),
+    },
+  );
+  if (!res.ok) throw new Error("Failed to invite user");
+  return res.json();
+}
+
+export async function removeWorkspaceMember(
+  workspaceId: string,
+  userId: string,
+) {
+  const res = await authFetch(
+    `${API_URL}/workspaces/${workspaceId}/members/${userId}`,
+    { method: "DELETE" },
+  );
+  if (!res.ok) throw new Error("Failed to remove member");
+}
+
+export async function fetchInvitations(): Promise<WorkspaceInvitation[]> {
+  const res = await authFetch(`${API_URL}/invitations`);
+  if (!res.ok) throw new Error("Failed to fetch invitations");
+  return res.json();
+}
+
+export async function respondToInvitation(id: string, accept: boolean) {
+  const action = accept ? "accept" : "decline";
+  const res = await authFetch(`${API_URL}/invitations/${id}/${action}`, {
+    method: "POST",
+  });
+  if (!res.ok) throw new Error(`Failed to ${action} invitation`);
+}
+
+export interface TreeNode {
+  id: string;
+  title: string;
+  parent_id: string | null;
+  is_folder: boolean;
+  owner_id: string;
+  updated_at: string;
+  child_count: number;
+  children: TreeNode[];
+}
+
+export async function fetchDocTree(
+  options: { root?: string; depth?: number } = {},
+): Promise<TreeNode[]> {
+  const params = new URLSearchParams();
+  if (options.root) params.set("root", options.root);
+  if (options.depth) params.set("depth", String(options.depth));
+  const query = params.toString();
+  const res = await authFetch(
+    `${API_URL}/documents/tree${query ? `?${query}` : ""}`,
+  );
+  if (!res.ok) throw new Error("Failed to fetch document tree");
+  return res.json();
+}
@@ -2,15 +2,17 @@
 
 import React, { createContext, useContext, useEffect, useState } from "react";
 import { useRouter, usePathname } from "next/navigation";
+import { COOKIE_AUTH, logout as apiLogout } from "@/lib/api";
 
 interface User {
   username: string;
-  token: string;
+  // Absent in cookie mode, where the token is not readable from JS.
+  token?: string;
 }
 
 interface AuthContextType {
   user: User | null;
-  login: (token: string, username: string) => void;
+  login: (token: string | undefined, username: string, refreshToken?: string) => void;
   logout: () => void;
   isAuthenticated: boolean;
   isLoading: boolean;
@@ -35,8 +37,8 @@ export function AuthProvider({ children }: { children: React.ReactNode }) {
     const token = localStorage.getItem("token");
     const username = localStorage.getItem("username");
 
-    if (token && username) {
-      setUser({ token, username });
+    if (username && (token || COOKIE_AUTH)) {
+      setUser({ token: token ?? undefined, username });
     }
     setIsLoading(false);
   }, []);
@@ -44,7 +46,7 @@ export function AuthProvider({ children }: { children: React.ReactNode }) {
   useEffect(() => {
     if (isLoading) return;
 
-    const isPublicPath = ["/login", "/register"].includes(pathname);
+    const isPublicPath = ["/login", "/register", "/auth/callback"].includes(pathname);
 
     if (!user && !isPublicPath) {
       window.location.href = "/login.html";
@@ -53,15 +55,18 @@ export function AuthProvider({ children }: { children: React.ReactNode }) {
     }
   }, [user, isLoading, pathname]);
 
-  const login = (token: string, username: string) => {
-    localStorage.setItem("token", token);
+  const login = (token: string | undefined, username: string, refreshToken?: string) => {
+    if (token) localStorage.setItem("token", token);
     localStorage.setItem("username", username);
+    if (refreshToken) localStorage.setItem("refresh_token", refreshToken);
     setUser({ token, username });
     window.location.href = "/index.html";
   };
 
   const logout = () => {
+    apiLogout().catch(() => {});
     localStorage.removeItem("token");
+    localStorage.removeItem("refresh_token");
     localStorage.removeItem("username");
     setUser(null);
     window.location.href = "/login.html";
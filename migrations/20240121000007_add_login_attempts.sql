@@ -0,0 +1,7 @@
+-- Failed-attempt counters for login throttling, keyed by username or client IP
+CREATE TABLE login_attempts (
+    key TEXT PRIMARY KEY NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure_at DATETIME NOT NULL,
+    locked_until DATETIME
+);
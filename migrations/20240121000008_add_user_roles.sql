@@ -0,0 +1,5 @@
+-- User roles and account disabling
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
+ALTER TABLE users ADD COLUMN disabled_at DATETIME;
+
+UPDATE users SET role = 'admin' WHERE username = 'admin';
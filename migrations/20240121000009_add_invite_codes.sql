@@ -0,0 +1,12 @@
+-- Invite codes for invite-only registration
+CREATE TABLE invite_codes (
+    id TEXT PRIMARY KEY NOT NULL,
+    code_hash TEXT NOT NULL UNIQUE,
+    note TEXT,
+    created_by TEXT,
+    max_uses INTEGER NOT NULL DEFAULT 1,
+    uses INTEGER NOT NULL DEFAULT 0,
+    expires_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
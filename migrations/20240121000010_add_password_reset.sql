@@ -0,0 +1,15 @@
+-- Access tokens issued before this instant are rejected (password change, reset)
+ALTER TABLE users ADD COLUMN tokens_valid_after DATETIME;
+
+-- Single-use password reset tokens, stored hashed
+CREATE TABLE password_reset_tokens (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at DATETIME NOT NULL,
+    used_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_password_reset_tokens_user_id ON password_reset_tokens(user_id);
@@ -0,0 +1,9 @@
+-- Self-service profile fields; the avatar image itself lives in AVATAR_DIR
+ALTER TABLE users ADD COLUMN display_name TEXT;
+ALTER TABLE users ADD COLUMN email TEXT;
+ALTER TABLE users ADD COLUMN avatar_file TEXT;
+ALTER TABLE users ADD COLUMN locale TEXT;
+ALTER TABLE users ADD COLUMN timezone TEXT;
+ALTER TABLE users ADD COLUMN preferences TEXT NOT NULL DEFAULT '{}';
+
+CREATE UNIQUE INDEX idx_users_email ON users(lower(email));
@@ -0,0 +1,25 @@
+-- Snapshot of a document's title and content after each change. Rows are
+-- never edited, except that consecutive autosaves by the same author within
+-- the squash window are folded into the latest one (updated_at moves).
+CREATE TABLE document_revisions (
+    id TEXT PRIMARY KEY NOT NULL,
+    document_id TEXT NOT NULL,
+    revision INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    content TEXT,
+    author_id TEXT,
+    reason TEXT,
+    autosave BOOLEAN NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (document_id, revision),
+    FOREIGN KEY (document_id) REFERENCES documents(id) ON DELETE CASCADE,
+    FOREIGN KEY (author_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+-- Existing documents start their history at their current state
+INSERT INTO document_revisions (id, document_id, revision, title, content, author_id, reason, created_at, updated_at)
+SELECT
+    substr(h, 1, 8) || '-' || substr(h, 9, 4) || '-' || substr(h, 13, 4) || '-' || substr(h, 17, 4) || '-' || substr(h, 21),
+    id, 1, title, content, owner_id, 'Initial version', updated_at, updated_at
+FROM (SELECT *, lower(hex(randomblob(16))) AS h FROM documents);
@@ -0,0 +1,2 @@
+-- Incremented on every update; exposed as the ETag for If-Match checks
+ALTER TABLE documents ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,11 @@
+-- Collaborative editing state: the Yjs updates received for a document, in
+-- order. Compacted to the server's encoded state whenever it is saved.
+CREATE TABLE document_updates (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    document_id TEXT NOT NULL,
+    update_data BLOB NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (document_id) REFERENCES documents(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_document_updates_document ON document_updates(document_id, id);
@@ -0,0 +1,27 @@
+-- Access to a document or folder granted to other users
+CREATE TABLE document_permissions (
+    id TEXT PRIMARY KEY NOT NULL,
+    document_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('viewer', 'commenter', 'editor')),
+    granted_by TEXT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (document_id, user_id),
+    FOREIGN KEY (document_id) REFERENCES documents(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (granted_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_document_permissions_user ON document_permissions(user_id);
+
+-- Effective grants: a grant on a folder also applies to everything below it
+CREATE VIEW document_grants AS
+WITH RECURSIVE grants(document_id, user_id, role) AS (
+    SELECT document_id, user_id, role FROM document_permissions
+    UNION
+    SELECT d.id, g.user_id, g.role
+    FROM documents d
+    JOIN grants g ON d.parent_id = g.document_id
+)
+SELECT document_id, user_id, role FROM grants;
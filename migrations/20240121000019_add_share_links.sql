@@ -0,0 +1,17 @@
+-- Public read-only links to a document or folder. Only a hash of the token is
+-- stored; the link itself is shown once when it is created.
+CREATE TABLE share_links (
+    id TEXT PRIMARY KEY NOT NULL,
+    document_id TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_by TEXT NOT NULL,
+    password_hash TEXT,
+    include_subfolders BOOLEAN NOT NULL DEFAULT 0,
+    expires_at DATETIME,
+    last_accessed_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (document_id) REFERENCES documents(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_share_links_created_by ON share_links(created_by);
@@ -0,0 +1,74 @@
+-- Team workspaces; documents and tags without one are in their owner's personal space
+CREATE TABLE workspaces (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    created_by TEXT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE TABLE workspace_members (
+    workspace_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'member', 'guest')),
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (workspace_id, user_id),
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_workspace_members_user ON workspace_members(user_id);
+
+-- Pending invitations; accepting one turns it into a membership
+CREATE TABLE workspace_invitations (
+    id TEXT PRIMARY KEY NOT NULL,
+    workspace_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('admin', 'member', 'guest')),
+    invited_by TEXT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (workspace_id, user_id),
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (invited_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_workspace_invitations_user ON workspace_invitations(user_id);
+
+-- Added columns can only declare their foreign key inline
+ALTER TABLE documents ADD COLUMN workspace_id TEXT REFERENCES workspaces(id) ON DELETE CASCADE;
+CREATE INDEX idx_documents_workspace ON documents(workspace_id);
+
+-- The workspace the user last switched to; NULL is the personal space
+ALTER TABLE users ADD COLUMN active_workspace_id TEXT REFERENCES workspaces(id) ON DELETE SET NULL;
+
+-- Tag names become unique per workspace instead of globally. SQLite can't
+-- drop the old constraint, so both tag tables are rebuilt; document_tags goes
+-- first so dropping tags doesn't cascade into it.
+CREATE TABLE tags_new (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    workspace_id TEXT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE
+);
+INSERT INTO tags_new (id, name, created_at) SELECT id, name, created_at FROM tags;
+
+CREATE TABLE document_tags_new (
+    document_id TEXT NOT NULL,
+    tag_id TEXT NOT NULL,
+    PRIMARY KEY (document_id, tag_id),
+    FOREIGN KEY (document_id) REFERENCES documents(id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tags_new(id) ON DELETE CASCADE
+);
+INSERT INTO document_tags_new (document_id, tag_id) SELECT document_id, tag_id FROM document_tags;
+
+DROP TABLE document_tags;
+DROP TABLE tags;
+-- Renaming also updates the foreign key in document_tags_new.
+ALTER TABLE tags_new RENAME TO tags;
+ALTER TABLE document_tags_new RENAME TO document_tags;
+
+CREATE UNIQUE INDEX idx_tags_workspace_name ON tags(COALESCE(workspace_id, ''), name);
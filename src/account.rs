@@ -0,0 +1,309 @@
+use actix_web::{delete, get, web, web::Bytes, HttpRequest, HttpResponse};
+use chrono::{NaiveDateTime, Utc};
+use futures::stream;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    admin::{delete_account as remove_account, ensure_not_last_admin},
+    audit::{self, Event},
+    auth::{get_session_user_id, verify_password},
+    cookie_auth,
+    db::DbPool,
+    errors::ServiceError,
+    models::Document,
+    profile::{avatar_dir, load_profile, Profile},
+    zip::ZipWriter,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedProfile {
+    #[serde(flatten)]
+    profile: Profile,
+    exported_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedDocument<'a> {
+    id: &'a str,
+    title: &'a str,
+    parent_id: Option<&'a str>,
+    is_folder: bool,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+    deleted_at: Option<NaiveDateTime>,
+    tags: Vec<&'a str>,
+    /// Path of the content file inside the archive; folders have none.
+    file: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TagAssignment {
+    document_id: String,
+    tag_id: String,
+    tag: String,
+}
+
+#[derive(Debug, Serialize)]
+struct FolderNode<'a> {
+    id: &'a str,
+    title: &'a str,
+    is_folder: bool,
+    deleted: bool,
+    children: Vec<FolderNode<'a>>,
+}
+
+/// Guards against parent cycles when walking the tree.
+const MAX_DEPTH: usize = 64;
+
+/// Makes a title usable as a path segment on common file systems.
+fn file_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(80)
+        .collect();
+    let name = name.trim().trim_matches('.').to_string();
+    if name.is_empty() {
+        "untitled".to_string()
+    } else {
+        name
+    }
+}
+
+/// Archive path of a document's content file, mirroring its folders. The id
+/// prefix keeps documents with the same title apart.
+fn content_path(doc: &Document, by_id: &HashMap<&str, &Document>) -> String {
+    let mut segments = vec![format!(
+        "{} ({}).html",
+        file_name(&doc.title),
+        doc.id.chars().take(8).collect::<String>()
+    )];
+    let mut parent = doc.parent_id.as_deref();
+    while let Some(folder) = parent.and_then(|id| by_id.get(id)) {
+        if segments.len() > MAX_DEPTH {
+            break;
+        }
+        segments.push(file_name(&folder.title));
+        parent = folder.parent_id.as_deref();
+    }
+    segments.push("documents".to_string());
+    segments.reverse();
+    segments.join("/")
+}
+
+fn folder_tree<'a>(
+    parent: Option<&str>,
+    children: &HashMap<Option<&str>, Vec<&'a Document>>,
+    depth: usize,
+) -> Vec<FolderNode<'a>> {
+    if depth > MAX_DEPTH {
+        return Vec::new();
+    }
+    children
+        .get(&parent)
+        .map(|docs| {
+            docs.iter()
+                .map(|doc| FolderNode {
+                    id: &doc.id,
+                    title: &doc.title,
+                    is_folder: doc.is_folder,
+                    deleted: doc.deleted_at.is_some(),
+                    children: folder_tree(Some(&doc.id), children, depth + 1),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, ServiceError> {
+    serde_json::to_vec_pretty(value).map_err(|_| ServiceError::InternalServerError)
+}
+
+/// Streams a ZIP with everything the caller owns: `profile.json`,
+/// `documents.json` (metadata, including trashed documents), `tags.json`,
+/// `structure.json` (the folder tree), one HTML file per document under
+/// `documents/` and the avatar image, if any.
+#[get("/me/export")]
+pub async fn export(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+    let now = Utc::now().naive_utc();
+
+    let profile = load_profile(pool.get_ref(), &user_id).await?;
+    let avatar_file = sqlx::query_scalar!("SELECT avatar_file FROM users WHERE id = ?", user_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    let docs = sqlx::query_as!(
+        Document,
+        "SELECT * FROM documents WHERE owner_id = ? ORDER BY is_folder DESC, title ASC",
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let assignments = sqlx::query_as!(
+        TagAssignment,
+        r#"
+        SELECT dt.document_id, t.id AS tag_id, t.name AS tag
+        FROM document_tags dt
+        JOIN tags t ON t.id = dt.tag_id
+        JOIN documents d ON d.id = dt.document_id
+        WHERE d.owner_id = ?
+        ORDER BY t.name
+        "#,
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let by_id: HashMap<&str, &Document> = docs.iter().map(|d| (d.id.as_str(), d)).collect();
+    let mut tags_by_doc: HashMap<&str, Vec<&str>> = HashMap::new();
+    for a in &assignments {
+        tags_by_doc
+            .entry(a.document_id.as_str())
+            .or_default()
+            .push(a.tag.as_str());
+    }
+    // Documents whose parent isn't theirs are shown at the top level.
+    let mut children: HashMap<Option<&str>, Vec<&Document>> = HashMap::new();
+    for doc in &docs {
+        let parent = doc.parent_id.as_deref().filter(|p| by_id.contains_key(p));
+        children.entry(parent).or_default().push(doc);
+    }
+
+    let mut files: Vec<(String, Vec<u8>, NaiveDateTime)> = Vec::new();
+    let mut metadata = Vec::with_capacity(docs.len());
+    for doc in &docs {
+        let file = (!doc.is_folder).then(|| content_path(doc, &by_id));
+        if let Some(path) = &file {
+            let content = doc.content.clone().unwrap_or_default().into_bytes();
+            files.push((path.clone(), content, doc.updated_at));
+        }
+        metadata.push(ExportedDocument {
+            id: &doc.id,
+            title: &doc.title,
+            parent_id: doc.parent_id.as_deref(),
+            is_folder: doc.is_folder,
+            created_at: doc.created_at,
+            updated_at: doc.updated_at,
+            deleted_at: doc.deleted_at,
+            tags: tags_by_doc.remove(doc.id.as_str()).unwrap_or_default(),
+            file,
+        });
+    }
+
+    if let Some(file) = avatar_file {
+        let path = avatar_dir().join(&file);
+        // A missing avatar file shouldn't fail the whole export.
+        if let Ok(Ok(bytes)) = web::block(move || std::fs::read(path)).await {
+            let extension = file.rsplit('.').next().unwrap_or("img");
+            files.push((format!("avatar.{}", extension), bytes, now));
+        }
+    }
+
+    let filename = format!(
+        "actix-doc-export-{}-{}.zip",
+        file_name(&profile.username),
+        now.format("%Y%m%d")
+    );
+    let profile = ExportedProfile {
+        profile,
+        exported_at: now,
+    };
+    let mut entries = vec![
+        ("profile.json".to_string(), to_json(&profile)?, now),
+        ("documents.json".to_string(), to_json(&metadata)?, now),
+        ("tags.json".to_string(), to_json(&assignments)?, now),
+        (
+            "structure.json".to_string(),
+            to_json(&folder_tree(None, &children, 0))?,
+            now,
+        ),
+    ];
+    entries.append(&mut files);
+
+    // Each entry is compressed only when the client is ready for it.
+    let body = stream::unfold(
+        (Some(ZipWriter::new()), entries.into_iter()),
+        |(writer, mut entries)| async move {
+            let mut writer = writer?;
+            let (chunk, writer) = match entries.next() {
+                Some((name, data, modified)) => {
+                    (writer.add_file(&name, &data, modified), Some(writer))
+                }
+                None => (writer.finish(), None),
+            };
+            // Stop after a failed entry rather than emitting a corrupt archive.
+            let writer = writer.filter(|_| chunk.is_ok());
+            let chunk = chunk.map(Bytes::from).map_err(|e| {
+                eprintln!("Export error: {:?}", e);
+                e
+            });
+            Some((chunk, (writer, entries)))
+        },
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .streaming(body))
+}
+
+/// Deletes the caller's account and everything they own. The current
+/// password is required even though the request is authenticated.
+#[delete("/me")]
+pub async fn delete_account(
+    pool: web::Data<DbPool>,
+    req: web::Json<DeleteAccountRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&http_req, pool.get_ref()).await?;
+
+    let password_hash =
+        sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = ?", user_id)
+            .fetch_one(pool.get_ref())
+            .await?;
+    verify_password(&password_hash, &req.password).map_err(|_| {
+        ServiceError::ValidationError(BTreeMap::from([(
+            "password".to_string(),
+            vec!["Password is incorrect".to_string()],
+        )]))
+    })?;
+    ensure_not_last_admin(pool.get_ref(), &user_id).await?;
+
+    let username = sqlx::query_scalar!("SELECT username FROM users WHERE id = ?", user_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+    remove_account(pool.get_ref(), &user_id).await?;
+
+    // Recorded after the fact, so the actor name comes from the details.
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("user.delete")
+            .actor(&user_id)
+            .target("user", &user_id)
+            .details(serde_json::json!({"username": username})),
+    )
+    .await;
+
+    let mut res = HttpResponse::Ok();
+    cookie_auth::clear_session_cookies(&mut res);
+    Ok(res.json(serde_json::json!({"message": "Account deleted"})))
+}
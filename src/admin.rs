@@ -0,0 +1,358 @@
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    audit::{self, Event},
+    auth::{hash_password, require_admin, revoke_user_sessions},
+    db::DbPool,
+    errors::ServiceError,
+    models::{ROLE_ADMIN, ROLE_USER},
+    profile::remove_avatar_file,
+    registration::validate_password,
+};
+
+#[derive(Debug, Serialize)]
+pub struct AdminUser {
+    pub id: String,
+    pub username: String,
+    pub role: String,
+    pub created_at: NaiveDateTime,
+    pub disabled_at: Option<NaiveDateTime>,
+    pub document_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeRoleRequest {
+    pub role: String,
+}
+
+/// Fails unless `user_id` exists.
+async fn ensure_user_exists(pool: &DbPool, user_id: &str) -> Result<(), ServiceError> {
+    sqlx::query_scalar!("SELECT id FROM users WHERE id = ?", user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(ServiceError::BadRequest("User not found".into()))?;
+    Ok(())
+}
+
+/// Refuses to demote, disable or delete `user_id` when it is the last
+/// enabled administrator, so the instance can't be locked out of its admin API.
+pub async fn ensure_not_last_admin(pool: &DbPool, user_id: &str) -> Result<(), ServiceError> {
+    let remaining = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM users WHERE role = ? AND disabled_at IS NULL AND id != ?",
+        ROLE_ADMIN,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let role = sqlx::query_scalar!("SELECT role FROM users WHERE id = ?", user_id)
+        .fetch_one(pool)
+        .await?;
+
+    if role == ROLE_ADMIN && remaining == 0 {
+        return Err(ServiceError::BadRequest(
+            "Cannot remove the last administrator".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[get("/admin/users")]
+pub async fn list_users(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    require_admin(&req, pool.get_ref()).await?;
+
+    let users = sqlx::query_as!(
+        AdminUser,
+        r#"
+        SELECT u.id, u.username, u.role, u.created_at, u.disabled_at,
+            (SELECT COUNT(*) FROM documents d WHERE d.owner_id = u.id) as "document_count!: i64"
+        FROM users u
+        ORDER BY u.created_at ASC
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[post("/admin/users/{id}/disable")]
+pub async fn disable_user(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin_id = require_admin(&req, pool.get_ref()).await?;
+    let user_id = id.into_inner();
+
+    if user_id == admin_id {
+        return Err(ServiceError::BadRequest(
+            "You cannot disable your own account".into(),
+        ));
+    }
+    ensure_user_exists(pool.get_ref(), &user_id).await?;
+    ensure_not_last_admin(pool.get_ref(), &user_id).await?;
+
+    let now = Utc::now().naive_utc();
+    sqlx::query!(
+        "UPDATE users SET disabled_at = ? WHERE id = ? AND disabled_at IS NULL",
+        now,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+    revoke_user_sessions(pool.get_ref(), &user_id).await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("admin.user_disable")
+            .actor(&admin_id)
+            .target("user", &user_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "User disabled"})))
+}
+
+#[post("/admin/users/{id}/enable")]
+pub async fn enable_user(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin_id = require_admin(&req, pool.get_ref()).await?;
+    let user_id = id.into_inner();
+
+    ensure_user_exists(pool.get_ref(), &user_id).await?;
+
+    sqlx::query!("UPDATE users SET disabled_at = NULL WHERE id = ?", user_id)
+        .execute(pool.get_ref())
+        .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("admin.user_enable")
+            .actor(&admin_id)
+            .target("user", &user_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "User enabled"})))
+}
+
+#[post("/admin/users/{id}/password")]
+pub async fn reset_password(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: web::Json<ResetPasswordRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin_id = require_admin(&http_req, pool.get_ref()).await?;
+    let user_id = id.into_inner();
+
+    let username = sqlx::query_scalar!("SELECT username FROM users WHERE id = ?", user_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or(ServiceError::BadRequest("User not found".into()))?;
+
+    let problems = validate_password(&req.password, &username);
+    if !problems.is_empty() {
+        return Err(ServiceError::ValidationError(BTreeMap::from([(
+            "password".to_string(),
+            problems,
+        )])));
+    }
+
+    let password_hash = hash_password(&req.password)?;
+    sqlx::query!(
+        "UPDATE users SET password_hash = ? WHERE id = ?",
+        password_hash,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+    revoke_user_sessions(pool.get_ref(), &user_id).await?;
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("admin.password_reset")
+            .actor(&admin_id)
+            .target("user", &user_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Password reset"})))
+}
+
+#[put("/admin/users/{id}/role")]
+pub async fn change_role(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: web::Json<ChangeRoleRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin_id = require_admin(&http_req, pool.get_ref()).await?;
+    let user_id = id.into_inner();
+
+    if req.role != ROLE_ADMIN && req.role != ROLE_USER {
+        return Err(ServiceError::BadRequest(format!(
+            "Unknown role: {}",
+            req.role
+        )));
+    }
+    if user_id == admin_id && req.role != ROLE_ADMIN {
+        return Err(ServiceError::BadRequest(
+            "You cannot remove your own admin role".into(),
+        ));
+    }
+    ensure_user_exists(pool.get_ref(), &user_id).await?;
+    if req.role != ROLE_ADMIN {
+        ensure_not_last_admin(pool.get_ref(), &user_id).await?;
+    }
+
+    sqlx::query!("UPDATE users SET role = ? WHERE id = ?", req.role, user_id)
+        .execute(pool.get_ref())
+        .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("admin.role_change")
+            .actor(&admin_id)
+            .target("user", &user_id)
+            .details(serde_json::json!({"role": req.role})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Role updated"})))
+}
+
+#[delete("/admin/users/{id}")]
+pub async fn delete_user(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin_id = require_admin(&req, pool.get_ref()).await?;
+    let user_id = id.into_inner();
+
+    if user_id == admin_id {
+        return Err(ServiceError::BadRequest(
+            "You cannot delete your own account".into(),
+        ));
+    }
+    ensure_user_exists(pool.get_ref(), &user_id).await?;
+    ensure_not_last_admin(pool.get_ref(), &user_id).await?;
+
+    let username = sqlx::query_scalar!("SELECT username FROM users WHERE id = ?", user_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+    delete_account(pool.get_ref(), &user_id).await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("admin.user_delete")
+            .actor(&admin_id)
+            .target("user", &user_id)
+            .details(serde_json::json!({"username": username})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "User deleted"})))
+}
+
+/// Removes a user and everything they own in one transaction.
+pub async fn delete_account(pool: &DbPool, user_id: &str) -> Result<(), ServiceError> {
+    let avatar = sqlx::query_scalar!("SELECT avatar_file FROM users WHERE id = ?", user_id)
+        .fetch_one(pool)
+        .await?;
+
+    // A team shouldn't lose its workspace with one account.
+    let orphaned = sqlx::query_scalar!(
+        r#"
+        SELECT w.name FROM workspaces w
+        JOIN workspace_members m ON m.workspace_id = w.id AND m.user_id = ?1 AND m.role = 'owner'
+        WHERE NOT EXISTS (
+            SELECT 1 FROM workspace_members o
+            WHERE o.workspace_id = w.id AND o.user_id <> ?1 AND o.role = 'owner'
+        )
+        AND EXISTS (SELECT 1 FROM workspace_members o WHERE o.workspace_id = w.id AND o.user_id <> ?1)
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(name) = orphaned {
+        return Err(ServiceError::BadRequest(format!(
+            "Make someone else an owner of the workspace \"{}\" first",
+            name
+        )));
+    }
+
+    // Documents don't cascade from users, so remove them first; tokens,
+    // credentials and the like cascade with the user row. Workspaces only
+    // the user belongs to go with their documents, and documents they
+    // created in shared workspaces pass to an owner there.
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM workspaces WHERE id IN (
+            SELECT workspace_id FROM workspace_members WHERE user_id = ?1
+        ) AND NOT EXISTS (
+            SELECT 1 FROM workspace_members o WHERE o.workspace_id = workspaces.id AND o.user_id <> ?1
+        )
+        "#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE documents SET owner_id = (
+            SELECT o.user_id FROM workspace_members o
+            WHERE o.workspace_id = documents.workspace_id AND o.user_id <> ?1 AND o.role = 'owner'
+            ORDER BY o.created_at LIMIT 1
+        )
+        WHERE owner_id = ?1 AND workspace_id IS NOT NULL
+        "#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM documents WHERE owner_id = ? AND workspace_id IS NULL",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM users WHERE id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    if let Some(avatar) = avatar {
+        remove_avatar_file(avatar).await;
+    }
+    Ok(())
+}
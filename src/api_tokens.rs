@@ -0,0 +1,266 @@
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{generate_token, get_session_user_id, hash_token},
+    db::DbPool,
+    errors::ServiceError,
+};
+
+/// Prefix that distinguishes personal access tokens from JWT access tokens.
+pub const API_TOKEN_PREFIX: &str = "adt_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    DocsRead,
+    DocsWrite,
+    TagsRead,
+    TagsWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [
+        Scope::DocsRead,
+        Scope::DocsWrite,
+        Scope::TagsRead,
+        Scope::TagsWrite,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::DocsRead => "docs:read",
+            Scope::DocsWrite => "docs:write",
+            Scope::TagsRead => "tags:read",
+            Scope::TagsWrite => "tags:write",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Scope> {
+        Scope::ALL.into_iter().find(|s| s.as_str() == value)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiTokenInfo {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedApiToken {
+    #[serde(flatten)]
+    info: ApiTokenInfo,
+    /// Plaintext token, only ever returned once.
+    token: String,
+}
+
+fn split_scopes(scopes: &str) -> Vec<String> {
+    scopes.split_whitespace().map(str::to_string).collect()
+}
+
+/// Resolves a personal access token to its owner, checking expiry and that it
+/// was granted `scope`.
+pub async fn authorize_api_token(
+    pool: &DbPool,
+    token: &str,
+    scope: Scope,
+) -> Result<String, ServiceError> {
+    let token_hash = hash_token(token);
+
+    let stored = sqlx::query!(
+        r#"
+        SELECT t.id, t.user_id, t.scopes, t.expires_at
+        FROM api_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE t.token_hash = ? AND u.disabled_at IS NULL
+        "#,
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(ServiceError::Unauthorized("Invalid token".into()))?;
+
+    let now = Utc::now().naive_utc();
+    if stored.expires_at.is_some_and(|expires_at| expires_at < now) {
+        return Err(ServiceError::Unauthorized("Token expired".into()));
+    }
+
+    if !stored
+        .scopes
+        .split_whitespace()
+        .any(|s| s == scope.as_str())
+    {
+        return Err(ServiceError::Forbidden(format!(
+            "Token is missing the {} scope",
+            scope.as_str()
+        )));
+    }
+
+    sqlx::query!(
+        "UPDATE api_tokens SET last_used_at = ? WHERE id = ?",
+        now,
+        stored.id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(stored.user_id)
+}
+
+#[post("/auth/tokens")]
+pub async fn create_token(
+    pool: web::Data<DbPool>,
+    req: web::Json<CreateApiTokenRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&http_req, pool.get_ref()).await?;
+
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(ServiceError::BadRequest("Token name is required".into()));
+    }
+
+    if req.scopes.is_empty() {
+        return Err(ServiceError::BadRequest(
+            "At least one scope is required".into(),
+        ));
+    }
+    let mut scopes = Vec::new();
+    for scope in &req.scopes {
+        let scope = Scope::parse(scope)
+            .ok_or_else(|| ServiceError::BadRequest(format!("Unknown scope: {}", scope)))?;
+        if !scopes.contains(&scope.as_str()) {
+            scopes.push(scope.as_str());
+        }
+    }
+    let scopes = scopes.join(" ");
+
+    let expires_at = match req.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(ServiceError::BadRequest(
+                "expires_in_days must be positive".into(),
+            ))
+        }
+        Some(days) => Some((Utc::now() + Duration::days(days)).naive_utc()),
+        None => None,
+    };
+
+    let id = Uuid::new_v4().to_string();
+    let token = format!("{}{}", API_TOKEN_PREFIX, generate_token());
+    let token_hash = hash_token(&token);
+
+    sqlx::query!(
+        "INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, expires_at) VALUES (?, ?, ?, ?, ?, ?)",
+        id,
+        user_id,
+        name,
+        token_hash,
+        scopes,
+        expires_at
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    let created = sqlx::query!(
+        "SELECT id, name, scopes, expires_at, last_used_at, created_at FROM api_tokens WHERE id = ?",
+        id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("api_token.create")
+            .actor(&user_id)
+            .target("api_token", &id)
+            .details(serde_json::json!({"name": name, "scopes": scopes, "expires_at": expires_at})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(CreatedApiToken {
+        info: ApiTokenInfo {
+            id: created.id,
+            name: created.name,
+            scopes: split_scopes(&created.scopes),
+            expires_at: created.expires_at,
+            last_used_at: created.last_used_at,
+            created_at: created.created_at,
+        },
+        token,
+    }))
+}
+
+#[get("/auth/tokens")]
+pub async fn list_tokens(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+
+    let tokens = sqlx::query!(
+        "SELECT id, name, scopes, expires_at, last_used_at, created_at FROM api_tokens WHERE user_id = ? ORDER BY created_at DESC",
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?
+    .into_iter()
+    .map(|t| ApiTokenInfo {
+        id: t.id,
+        name: t.name,
+        scopes: split_scopes(&t.scopes),
+        expires_at: t.expires_at,
+        last_used_at: t.last_used_at,
+        created_at: t.created_at,
+    })
+    .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[delete("/auth/tokens/{id}")]
+pub async fn revoke_token(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+    let token_id = id.into_inner();
+
+    let result = sqlx::query!(
+        "DELETE FROM api_tokens WHERE id = ? AND user_id = ?",
+        token_id,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::BadRequest("Token not found".into()));
+    }
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("api_token.revoke")
+            .actor(&user_id)
+            .target("api_token", &token_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Token revoked"})))
+}
@@ -0,0 +1,235 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    auth::{client_ip, require_admin},
+    db::DbPool,
+    errors::ServiceError,
+};
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+
+/// An entry for the audit log, built up with the helper methods:
+///
+/// ```ignore
+/// audit::record(pool, &req, Event::new("user.disable").actor(&admin_id).target("user", &user_id)).await;
+/// ```
+pub struct Event {
+    action: &'static str,
+    actor_id: Option<String>,
+    target_type: Option<&'static str>,
+    target_id: Option<String>,
+    details: Value,
+}
+
+impl Event {
+    pub fn new(action: &'static str) -> Self {
+        Event {
+            action,
+            actor_id: None,
+            target_type: None,
+            target_id: None,
+            details: Value::Object(Default::default()),
+        }
+    }
+
+    /// The user who performed the action.
+    pub fn actor(mut self, user_id: &str) -> Self {
+        self.actor_id = Some(user_id.to_string());
+        self
+    }
+
+    pub fn target(mut self, target_type: &'static str, target_id: &str) -> Self {
+        self.target_type = Some(target_type);
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    pub fn details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Writes an event to the audit log. The actor's username is stored along
+/// with the id so the entry stays readable after the account is deleted.
+/// Failures are logged rather than returned: auditing must not block the
+/// action being audited.
+pub async fn record(pool: &DbPool, req: &HttpRequest, event: Event) {
+    let id = Uuid::new_v4().to_string();
+    let ip = client_ip(req);
+    let details = event.details.to_string();
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO audit_events (id, action, actor_id, actor_name, ip, target_type, target_id, details)
+        VALUES (?, ?, ?, (SELECT username FROM users WHERE id = ?), ?, ?, ?, ?)
+        "#,
+        id,
+        event.action,
+        event.actor_id,
+        event.actor_id,
+        ip,
+        event.target_type,
+        event.target_id,
+        details
+    )
+    .execute(pool)
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Failed to write audit event {}: {:?}", event.action, e);
+    }
+}
+
+/// Days audit events are kept (`AUDIT_RETENTION_DAYS`, default 365; 0 keeps
+/// them forever).
+fn retention_days() -> i64 {
+    std::env::var("AUDIT_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(365)
+}
+
+/// Deletes events older than the retention period.
+pub async fn prune(pool: &DbPool) -> Result<u64, ServiceError> {
+    let days = retention_days();
+    if days <= 0 {
+        return Ok(0);
+    }
+    let cutoff = (Utc::now() - Duration::days(days)).naive_utc();
+
+    let result = sqlx::query!("DELETE FROM audit_events WHERE created_at < ?", cutoff)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Background task applying the retention period once an hour.
+pub async fn run_retention(pool: DbPool) {
+    let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match prune(&pool).await {
+            Ok(0) | Err(_) => {}
+            Ok(n) => println!("Pruned {} audit events past retention", n),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    /// Exact action, or a prefix such as `auth` matching `auth.*`.
+    pub action: Option<String>,
+    pub actor_id: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEvent {
+    pub id: String,
+    pub action: String,
+    pub actor_id: Option<String>,
+    pub actor_name: Option<String>,
+    pub ip: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub details: Value,
+    pub created_at: NaiveDateTime,
+}
+
+/// Lists audit events, newest first. All filters are optional and combined.
+#[get("/admin/audit")]
+pub async fn list_events(
+    pool: web::Data<DbPool>,
+    query: web::Query<AuditQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    require_admin(&req, pool.get_ref()).await?;
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let offset = (page - 1) * per_page;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) FROM audit_events
+        WHERE (?1 IS NULL OR action = ?1 OR action LIKE ?1 || '.%')
+          AND (?2 IS NULL OR actor_id = ?2)
+          AND (?3 IS NULL OR target_type = ?3)
+          AND (?4 IS NULL OR target_id = ?4)
+          AND (?5 IS NULL OR ip = ?5)
+          AND (?6 IS NULL OR created_at >= ?6)
+          AND (?7 IS NULL OR created_at < ?7)
+        "#,
+        query.action,
+        query.actor_id,
+        query.target_type,
+        query.target_id,
+        query.ip,
+        query.since,
+        query.until
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    let events = sqlx::query!(
+        r#"
+        SELECT id, action, actor_id, actor_name, ip, target_type, target_id, details, created_at
+        FROM audit_events
+        WHERE (?1 IS NULL OR action = ?1 OR action LIKE ?1 || '.%')
+          AND (?2 IS NULL OR actor_id = ?2)
+          AND (?3 IS NULL OR target_type = ?3)
+          AND (?4 IS NULL OR target_id = ?4)
+          AND (?5 IS NULL OR ip = ?5)
+          AND (?6 IS NULL OR created_at >= ?6)
+          AND (?7 IS NULL OR created_at < ?7)
+        ORDER BY created_at DESC, rowid DESC
+        LIMIT ?8 OFFSET ?9
+        "#,
+        query.action,
+        query.actor_id,
+        query.target_type,
+        query.target_id,
+        query.ip,
+        query.since,
+        query.until,
+        per_page,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await?
+    .into_iter()
+    .map(|e| AuditEvent {
+        id: e.id,
+        action: e.action,
+        actor_id: e.actor_id,
+        actor_name: e.actor_name,
+        ip: e.ip,
+        target_type: e.target_type,
+        target_id: e.target_id,
+        details: serde_json::from_str(&e.details).unwrap_or(Value::Null),
+        created_at: e.created_at,
+    })
+    .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "events": events,
+        "total": total,
+        "page": page,
+        "per_page": per_page,
+    })))
+}
@@ -1,77 +1,286 @@
-use actix_web::{post, web, HttpResponse};
+use actix_web::{post, web, HttpRequest, HttpResponse};
 use argon2::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use uuid::Uuid;
 
 use crate::{
+    api_tokens::{self, Scope, API_TOKEN_PREFIX},
+    audit::{self, Event},
+    cookie_auth,
     db::DbPool,
     errors::ServiceError,
-    models::{CreateUserRequest, LoginRequest, User},
+    hashing, jwt_keys,
+    models::{CreateUserRequest, LoginRequest, RefreshTokenRequest, User, ROLE_ADMIN},
+    registration::{self, RegistrationMode},
+    sessions, throttle, two_factor,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: String,
+    pub iss: String,
+    pub aud: String,
     pub exp: usize,
     pub iat: usize,
+    /// Unique id of this access token.
+    pub jti: String,
+    /// Session the token belongs to; see `sessions`.
+    pub sid: String,
 }
 
+/// Tokens are left out when they are delivered as cookies; see `cookie_auth`.
 #[derive(Serialize)]
 struct AuthResponse {
-    token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    expires_in: i64,
     username: String,
 }
 
-pub fn validate_token(token: &str) -> Result<String, ServiceError> {
-    let jwt_secret = std::env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "dev_fallback_secret_key_change_me".to_string());
+/// Lifetime of an access token in minutes (`ACCESS_TOKEN_TTL_MINUTES`, default 15).
+fn access_token_ttl() -> Duration {
+    let minutes = std::env::var("ACCESS_TOKEN_TTL_MINUTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(15);
+    Duration::minutes(minutes)
+}
+
+/// Lifetime of a refresh token in days (`REFRESH_TOKEN_TTL_DAYS`, default 30).
+fn refresh_token_ttl() -> Duration {
+    let days = std::env::var("REFRESH_TOKEN_TTL_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+    Duration::days(days)
+}
+
+/// Generates an opaque, URL-safe random token.
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    hex::encode(bytes)
+}
+
+/// Hashes an opaque token for storage. Tokens are high-entropy, so a plain
+/// SHA-256 digest is enough and keeps lookups by hash possible.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub async fn validate_token(pool: &DbPool, token: &str) -> Result<TokenClaims, ServiceError> {
+    let claims: TokenClaims = jwt_keys::keys().decode(token)?;
+
+    ensure_active(pool, &claims.sub).await?;
+    sessions::check_session(pool, &claims.sid, &claims.sub).await?;
+
+    let valid_after = sqlx::query_scalar!(
+        "SELECT tokens_valid_after FROM users WHERE id = ?",
+        claims.sub
+    )
+    .fetch_one(pool)
+    .await?;
+    if let Some(valid_after) = valid_after {
+        if (claims.iat as i64) < valid_after.and_utc().timestamp() {
+            return Err(ServiceError::Unauthorized("Session revoked".into()));
+        }
+    }
+
+    Ok(claims)
+}
+
+/// Rejects users that were deleted or disabled by an administrator, even
+/// when they still hold an unexpired token.
+pub async fn ensure_active(pool: &DbPool, user_id: &str) -> Result<(), ServiceError> {
+    let user = sqlx::query!("SELECT disabled_at FROM users WHERE id = ?", user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(ServiceError::Unauthorized("Invalid token".into()))?;
+
+    if user.disabled_at.is_some() {
+        return Err(ServiceError::Unauthorized("Account disabled".into()));
+    }
+
+    Ok(())
+}
+
+/// Reads the token from the `Authorization: Bearer` header, falling back to
+/// the access cookie. Cookie-authenticated requests must pass the CSRF check.
+fn bearer_token(req: &HttpRequest) -> Result<String, ServiceError> {
+    let Some(auth_header) = req.headers().get("Authorization") else {
+        let token = cookie_auth::access_token(req)
+            .ok_or(ServiceError::Unauthorized("No token provided".into()))?;
+        cookie_auth::verify_csrf(req)?;
+        return Ok(token);
+    };
+
+    auth_header
+        .to_str()
+        .ok()
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .ok_or(ServiceError::Unauthorized("Invalid token format".into()))
+}
+
+/// Resolves the user behind the request's Bearer token and checks that it may
+/// act with `scope`. Access tokens from `login` carry every scope, personal
+/// access tokens only the ones they were created with.
+pub async fn get_user_id(
+    req: &HttpRequest,
+    pool: &DbPool,
+    scope: Scope,
+) -> Result<String, ServiceError> {
+    let token = bearer_token(req)?;
+    token_user_id(pool, &token, scope).await
+}
+
+/// Resolves an access token or API token to its user, for tokens passed
+/// some other way than the `Authorization` header.
+pub async fn token_user_id(
+    pool: &DbPool,
+    token: &str,
+    scope: Scope,
+) -> Result<String, ServiceError> {
+    if token.starts_with(API_TOKEN_PREFIX) {
+        return api_tokens::authorize_api_token(pool, token, scope).await;
+    }
+
+    Ok(validate_token(pool, token).await?.sub)
+}
+
+/// Like `get_user_id`, but only accepts an interactive login. Used for
+/// account management so an API token cannot mint or revoke other tokens.
+pub async fn get_session_user_id(req: &HttpRequest, pool: &DbPool) -> Result<String, ServiceError> {
+    Ok(get_session_claims(req, pool).await?.sub)
+}
 
-    let key = DecodingKey::from_secret(jwt_secret.as_ref());
-    let validation = Validation::default();
+/// Like `get_session_user_id`, returning the full claims including the
+/// session id.
+pub async fn get_session_claims(
+    req: &HttpRequest,
+    pool: &DbPool,
+) -> Result<TokenClaims, ServiceError> {
+    let token = bearer_token(req)?;
 
-    let token_data = decode::<TokenClaims>(token, &key, &validation)
-        .map_err(|_| ServiceError::Unauthorized("Invalid token".into()))?;
+    if token.starts_with(API_TOKEN_PREFIX) {
+        return Err(ServiceError::Forbidden(
+            "API tokens cannot be used for this endpoint".into(),
+        ));
+    }
 
-    Ok(token_data.claims.sub)
+    validate_token(pool, &token).await
+}
+
+/// Rejects callers whose role is not `admin`.
+pub async fn require_admin(req: &HttpRequest, pool: &DbPool) -> Result<String, ServiceError> {
+    let user_id = get_session_user_id(req, pool).await?;
+
+    let role = sqlx::query_scalar!("SELECT role FROM users WHERE id = ?", user_id)
+        .fetch_one(pool)
+        .await?;
+
+    if role != ROLE_ADMIN {
+        return Err(ServiceError::Forbidden("Admin access required".into()));
+    }
+
+    Ok(user_id)
+}
+
+/// Client address used for throttling. Proxy headers are only honoured when
+/// `TRUST_PROXY_HEADERS` is set, since clients can forge them.
+pub fn client_ip(req: &HttpRequest) -> String {
+    if std::env::var("TRUST_PROXY_HEADERS").is_ok() {
+        if let Some(ip) = req.connection_info().realip_remote_addr() {
+            return ip.to_string();
+        }
+    }
+
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
 }
 
 #[post("/auth/register")]
 pub async fn register(
     pool: web::Data<DbPool>,
     req: web::Json<CreateUserRequest>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
+    let mode = registration::registration_mode();
+    if mode == RegistrationMode::Closed {
+        return Err(ServiceError::Forbidden("Registration is closed".into()));
+    }
+
+    throttle::check_register(pool.get_ref(), &client_ip(&http_req)).await?;
+
+    let mut fields = registration::validate_credentials(&req.username, &req.password)?;
+
     let exists = sqlx::query!("SELECT id FROM users WHERE username = ?", req.username)
         .fetch_optional(pool.get_ref())
         .await?;
-
     if exists.is_some() {
-        return Err(ServiceError::BadRequest("Username already exists".into()));
+        fields
+            .entry("username".to_string())
+            .or_default()
+            .push("Username already exists".to_string());
     }
 
-    let salt = SaltString::generate(&mut rand::thread_rng());
-    let argon2 = Argon2::default();
-    let password_hash = argon2
-        .hash_password(req.password.as_bytes(), &salt)
-        .map_err(|_| ServiceError::InternalServerError)?
-        .to_string();
+    let invite_code = req.invite_code.as_deref().unwrap_or("").trim();
+    if mode == RegistrationMode::InviteOnly && invite_code.is_empty() {
+        fields.insert(
+            "invite_code".to_string(),
+            vec!["An invite code is required".to_string()],
+        );
+    }
+
+    if !fields.is_empty() {
+        return Err(ServiceError::ValidationError(fields));
+    }
+
+    let password_hash = hash_password(&req.password)?;
 
     let user_id = Uuid::new_v4().to_string();
 
+    let mut tx = pool.begin().await?;
+
+    if mode == RegistrationMode::InviteOnly
+        && !registration::redeem_invite(&mut tx, invite_code).await?
+    {
+        fields.insert(
+            "invite_code".to_string(),
+            vec!["Invalid or expired invite code".to_string()],
+        );
+        return Err(ServiceError::ValidationError(fields));
+    }
+
     sqlx::query!(
         "INSERT INTO users (id, username, password_hash) VALUES (?, ?, ?)",
         user_id,
         req.username,
         password_hash
     )
-    .execute(pool.get_ref())
+    .execute(&mut *tx)
     .await?;
 
+    tx.commit().await?;
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("user.register")
+            .actor(&user_id)
+            .target("user", &user_id)
+            .details(serde_json::json!({"invited": mode == RegistrationMode::InviteOnly})),
+    )
+    .await;
+
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "User created successfully"})))
 }
 
@@ -79,46 +288,377 @@ pub async fn register(
 pub async fn login(
     pool: web::Data<DbPool>,
     req: web::Json<LoginRequest>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
+    let ip = client_ip(&http_req);
+    if let Err(e) = throttle::check_login(pool.get_ref(), &req.username, &ip).await {
+        audit::record(
+            pool.get_ref(),
+            &http_req,
+            Event::new("auth.login_failed")
+                .details(serde_json::json!({"username": req.username, "reason": "throttled"})),
+        )
+        .await;
+        return Err(e);
+    }
+
     let user = sqlx::query_as!(
         User,
         "SELECT id, username, password_hash, created_at FROM users WHERE username = ?",
         req.username
     )
     .fetch_optional(pool.get_ref())
-    .await?
-    .ok_or(ServiceError::BadRequest("Invalid credentials".into()))?;
+    .await?;
+
+    let user = match user {
+        Some(user) if verify_password(&user.password_hash, &req.password).is_ok() => user,
+        user => {
+            let mut event = Event::new("auth.login_failed").details(
+                serde_json::json!({"username": req.username, "reason": "invalid_credentials"}),
+            );
+            if let Some(user) = &user {
+                event = event.target("user", &user.id);
+            }
+            audit::record(pool.get_ref(), &http_req, event).await;
+            throttle::login_failed(pool.get_ref(), &req.username, &ip).await?;
+            return Err(ServiceError::BadRequest("Invalid credentials".into()));
+        }
+    };
+    upgrade_password_hash(pool.get_ref(), &user.id, &user.password_hash, &req.password).await;
+
+    if two_factor::is_enabled(pool.get_ref(), &user.id).await? {
+        let challenge_token = two_factor::start_challenge(pool.get_ref(), &user.id).await?;
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "two_factor_required": true,
+            "challenge_token": challenge_token,
+            "username": user.username,
+        })));
+    }
 
+    complete_login(
+        pool.get_ref(),
+        &http_req,
+        &user.id,
+        user.username,
+        "password",
+    )
+    .await
+}
+
+/// Hashes a password with the configured Argon2 parameters and a fresh salt.
+pub fn hash_password(password: &str) -> Result<String, ServiceError> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    hashing::argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| ServiceError::InternalServerError)
+}
+
+/// Checks `password` against a stored Argon2 hash.
+pub fn verify_password(password_hash: &str, password: &str) -> Result<(), ServiceError> {
     let parsed_hash =
-        PasswordHash::new(&user.password_hash).map_err(|_| ServiceError::InternalServerError)?;
+        PasswordHash::new(password_hash).map_err(|_| ServiceError::InternalServerError)?;
 
     Argon2::default()
-        .verify_password(req.password.as_bytes(), &parsed_hash)
-        .map_err(|_| ServiceError::BadRequest("Invalid credentials".into()))?;
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .map_err(|_| ServiceError::BadRequest("Invalid credentials".into()))
+}
 
+/// Replaces a hash made with weaker parameters than the configured ones once
+/// the password has been verified. Failures are logged and otherwise ignored;
+/// the login itself has already succeeded.
+async fn upgrade_password_hash(pool: &DbPool, user_id: &str, stored: &str, password: &str) {
+    let outdated = PasswordHash::new(stored).is_ok_and(|hash| hashing::needs_rehash(&hash));
+    if !outdated {
+        return;
+    }
+    let Ok(password_hash) = hash_password(password) else {
+        return;
+    };
+
+    // Conditional on the old hash so a concurrent password change wins.
+    let result = sqlx::query!(
+        "UPDATE users SET password_hash = ? WHERE id = ? AND password_hash = ?",
+        password_hash,
+        user_id,
+        stored
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        eprintln!("Failed to upgrade password hash: {:?}", e);
+    }
+}
+
+/// Starts a session and issues the access and refresh tokens for a user whose
+/// credentials have been fully verified, clearing their failed-login counter.
+/// `method` names how they were verified for the audit log.
+pub async fn complete_login(
+    pool: &DbPool,
+    req: &HttpRequest,
+    user_id: &str,
+    username: String,
+    method: &'static str,
+) -> Result<HttpResponse, ServiceError> {
+    ensure_active(pool, user_id).await?;
+    throttle::login_succeeded(pool, &username).await?;
+
+    let session_id = sessions::create_session(pool, user_id, req).await?;
+    audit::record(
+        pool,
+        req,
+        Event::new("auth.login")
+            .actor(user_id)
+            .target("session", &session_id)
+            .details(serde_json::json!({"method": method})),
+    )
+    .await;
+    let token = issue_access_token(user_id, &session_id)?;
+    let (_, refresh_token) = issue_refresh_token(pool, user_id, &session_id).await?;
+
+    let as_cookies = cookie_auth::wants_cookies(req);
+    Ok(auth_response(
+        req,
+        as_cookies,
+        token,
+        refresh_token,
+        username,
+    ))
+}
+
+/// Builds the login/refresh response, delivering the tokens either in the
+/// body or as cookies.
+fn auth_response(
+    req: &HttpRequest,
+    as_cookies: bool,
+    token: String,
+    refresh_token: String,
+    username: String,
+) -> HttpResponse {
+    let mut res = HttpResponse::Ok();
+    let expires_in = access_token_ttl().num_seconds();
+
+    if as_cookies {
+        cookie_auth::set_session_cookies(
+            &mut res,
+            req,
+            token,
+            access_token_ttl(),
+            refresh_token,
+            refresh_token_ttl(),
+        );
+        return res.json(AuthResponse {
+            token: None,
+            refresh_token: None,
+            expires_in,
+            username,
+        });
+    }
+
+    res.json(AuthResponse {
+        token: Some(token),
+        refresh_token: Some(refresh_token),
+        expires_in,
+        username,
+    })
+}
+
+/// Takes the refresh token from the body, or from the cookie for cookie-mode
+/// clients, which then also need a valid CSRF header.
+fn presented_refresh_token(
+    req: &RefreshTokenRequest,
+    http_req: &HttpRequest,
+) -> Result<String, ServiceError> {
+    if let Some(token) = &req.refresh_token {
+        return Ok(token.clone());
+    }
+    let token = cookie_auth::refresh_token(http_req)
+        .ok_or(ServiceError::Unauthorized("Invalid refresh token".into()))?;
+    cookie_auth::verify_csrf(http_req)?;
+    Ok(token)
+}
+
+fn issue_access_token(user_id: &str, session_id: &str) -> Result<String, ServiceError> {
     let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(24))
+        .checked_add_signed(access_token_ttl())
         .expect("valid timestamp")
         .timestamp();
 
+    let keys = jwt_keys::keys();
     let claims = TokenClaims {
-        sub: user.id.clone(),
+        sub: user_id.to_string(),
+        iss: keys.issuer().to_string(),
+        aud: keys.audience().to_string(),
         exp: expiration as usize,
         iat: Utc::now().timestamp() as usize,
+        jti: Uuid::new_v4().to_string(),
+        sid: session_id.to_string(),
     };
 
-    let jwt_secret = std::env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "dev_fallback_secret_key_change_me".to_string());
+    keys.encode(&claims)
+}
+
+/// Stores a new refresh token for `user_id` and returns its row id and plaintext
+/// value. All tokens rotated from one login share a `family_id`, which is the
+/// id of its session.
+async fn issue_refresh_token(
+    pool: &DbPool,
+    user_id: &str,
+    family_id: &str,
+) -> Result<(String, String), ServiceError> {
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+    let id = Uuid::new_v4().to_string();
+    let expires_at = (Utc::now() + refresh_token_ttl()).naive_utc();
+
+    sqlx::query!(
+        "INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at) VALUES (?, ?, ?, ?, ?)",
+        id,
+        user_id,
+        family_id,
+        token_hash,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+
+    Ok((id, token))
+}
+
+/// Signs `user_id` out everywhere: revokes all refresh tokens and rejects
+/// access tokens issued before now.
+pub async fn revoke_user_sessions(pool: &DbPool, user_id: &str) -> Result<(), ServiceError> {
+    let now = Utc::now().naive_utc();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL",
+        now,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL",
+        now,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE users SET tokens_valid_after = ? WHERE id = ?",
+        now,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[post("/auth/refresh")]
+pub async fn refresh(
+    pool: web::Data<DbPool>,
+    req: web::Json<RefreshTokenRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let token_hash = hash_token(&presented_refresh_token(&req, &http_req)?);
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(jwt_secret.as_ref()),
+    let stored = sqlx::query!(
+        r#"
+        SELECT r.id, r.user_id, r.family_id, r.expires_at, r.revoked_at, r.replaced_by, u.username
+        FROM refresh_tokens r
+        JOIN users u ON u.id = r.user_id
+        WHERE r.token_hash = ?
+        "#,
+        token_hash
     )
-    .map_err(|_| ServiceError::InternalServerError)?;
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::Unauthorized("Invalid refresh token".into()))?;
+
+    if stored.revoked_at.is_some() {
+        if stored.replaced_by.is_some() {
+            // A token that was already rotated is being replayed: assume it leaked
+            // and kill every token descended from the same login.
+            sessions::revoke_session(pool.get_ref(), &stored.family_id).await?;
+            return Err(ServiceError::Unauthorized(
+                "Refresh token reuse detected".into(),
+            ));
+        }
+        return Err(ServiceError::Unauthorized("Refresh token revoked".into()));
+    }
+
+    let now = Utc::now().naive_utc();
+    if stored.expires_at < now {
+        return Err(ServiceError::Unauthorized("Refresh token expired".into()));
+    }
+    ensure_active(pool.get_ref(), &stored.user_id).await?;
 
-    Ok(HttpResponse::Ok().json(AuthResponse {
+    let (new_id, new_token) =
+        issue_refresh_token(pool.get_ref(), &stored.user_id, &stored.family_id).await?;
+
+    // Guard against two concurrent refreshes with the same token: only one of
+    // them may flip `revoked_at`, the other is treated as reuse.
+    let rotated = sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = ?, replaced_by = ? WHERE id = ? AND revoked_at IS NULL",
+        now,
+        new_id,
+        stored.id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    if rotated.rows_affected() == 0 {
+        sessions::revoke_session(pool.get_ref(), &stored.family_id).await?;
+        return Err(ServiceError::Unauthorized(
+            "Refresh token reuse detected".into(),
+        ));
+    }
+
+    sessions::record_activity(
+        pool.get_ref(),
+        &stored.family_id,
+        &stored.user_id,
+        &http_req,
+    )
+    .await?;
+
+    let token = issue_access_token(&stored.user_id, &stored.family_id)?;
+    // A refresh token read from the cookie is answered with cookies.
+    let as_cookies = req.refresh_token.is_none() || cookie_auth::wants_cookies(&http_req);
+    Ok(auth_response(
+        &http_req,
+        as_cookies,
         token,
-        username: user.username,
-    }))
+        new_token,
+        stored.username,
+    ))
+}
+
+#[post("/auth/logout")]
+pub async fn logout(
+    pool: web::Data<DbPool>,
+    req: web::Json<RefreshTokenRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let token_hash = hash_token(&presented_refresh_token(&req, &http_req)?);
+
+    let family_id = sqlx::query_scalar!(
+        "SELECT family_id FROM refresh_tokens WHERE token_hash = ?",
+        token_hash
+    )
+    .fetch_optional(pool.get_ref())
+    .await?;
+
+    if let Some(family_id) = family_id {
+        sessions::revoke_session(pool.get_ref(), &family_id).await?;
+    }
+
+    let mut res = HttpResponse::Ok();
+    cookie_auth::clear_session_cookies(&mut res);
+    Ok(res.json(serde_json::json!({"message": "Logged out"})))
 }
@@ -0,0 +1,785 @@
+//! Real-time collaborative editing over WebSocket.
+//!
+//! `GET /collab/{id}` speaks the y-websocket protocol, so Tiptap's
+//! Collaboration extension can connect with a stock `WebsocketProvider`.
+//! While a document has editors, the server keeps its Yjs document in
+//! memory: updates from editors are applied to it and relayed to the others
+//! along with awareness (cursor) messages, and joining clients sync against
+//! it. Every update is also appended to `document_updates`, from which the
+//! next session starts; without any, the session starts from
+//! `documents.content`.
+//!
+//! Once editing pauses, and again when the last editor leaves, the server
+//! renders the document into `documents.content` as an autosave revision of
+//! the latest editor, and compacts `document_updates` to its encoded state.
+
+use actix_web::{
+    get,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse,
+};
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, CloseCode, CloseReason, Session};
+use chrono::Utc;
+use futures::lock::Mutex as AsyncMutex;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use yrs::{
+    updates::{decoder::Decode, encoder::Encode},
+    Doc, ReadTxn, StateVector, Transact, Update,
+};
+
+use crate::{
+    api_tokens::Scope,
+    auth::{get_user_id, token_user_id},
+    collab_html,
+    db::DbPool,
+    errors::ServiceError,
+    events::EventBus,
+    models::Document,
+    permissions::{authorize, Role},
+    revisions, workspaces,
+};
+
+const MESSAGE_SYNC: u64 = 0;
+const MESSAGE_AWARENESS: u64 = 1;
+const MESSAGE_QUERY_AWARENESS: u64 = 3;
+
+const SYNC_STEP1: u64 = 0;
+const SYNC_STEP2: u64 = 1;
+const SYNC_UPDATE: u64 = 2;
+
+/// Yjs encoding of an update without changes.
+const EMPTY_UPDATE: &[u8] = &[0, 0];
+
+/// Continuous editing is still saved once this many save delays have passed
+/// since the first unsaved change.
+const MAX_SAVE_DELAYS: u32 = 10;
+
+/// Close code telling editors their local state is stale and has to be
+/// discarded before reconnecting, e.g. after a revision was restored.
+pub const CLOSE_RESET: u16 = 4001;
+
+/// Largest WebSocket message accepted from an editor
+/// (`COLLAB_MAX_MESSAGE_BYTES`, default 1 MiB).
+fn max_message_bytes() -> usize {
+    std::env::var("COLLAB_MAX_MESSAGE_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1024 * 1024)
+}
+
+/// Seconds without changes after which a document is saved
+/// (`COLLAB_SAVE_DELAY_SECONDS`, default 2).
+fn save_delay() -> Duration {
+    let seconds = std::env::var("COLLAB_SAVE_DELAY_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2);
+    Duration::from_secs(seconds)
+}
+
+/// Editors currently connected, grouped by document.
+#[derive(Default)]
+pub struct Rooms {
+    rooms: Mutex<HashMap<String, Room>>,
+    next_connection: AtomicU64,
+}
+
+/// A document's editing session. It outlives its last connection until the
+/// final save is done, so a quick rejoin continues it.
+#[derive(Default)]
+struct Room {
+    connections: HashMap<u64, Session>,
+    /// Latest awareness state per Yjs client.
+    awareness: HashMap<u64, AwarenessState>,
+    shared: Arc<AsyncMutex<SharedDoc>>,
+}
+
+struct AwarenessState {
+    clock: u64,
+    /// JSON as sent by the client.
+    state: String,
+    connection: u64,
+}
+
+/// The server's copy of a document's Yjs state.
+#[derive(Default)]
+struct SharedDoc {
+    doc: Doc,
+    /// Whether `document_updates` has been applied to `doc` yet.
+    loaded: bool,
+    /// Set once the state was discarded; nothing is saved from it any more.
+    discarded: bool,
+    /// Rows in `document_updates`, which a save compacts to one.
+    log_len: usize,
+    /// Changes not yet saved to `documents.content`.
+    unsaved: Option<Unsaved>,
+}
+
+struct Unsaved {
+    /// Editor of the latest change, who the revision is credited to.
+    author: String,
+    since: Instant,
+    last: Instant,
+}
+
+impl Rooms {
+    /// The document of a document's session, starting one if needed.
+    fn open(&self, doc_id: &str) -> Arc<AsyncMutex<SharedDoc>> {
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.entry(doc_id.to_string()).or_default().shared.clone()
+    }
+
+    /// Adds a connection to the session of `shared`. Fails when that session
+    /// has ended since it was opened.
+    fn join(
+        &self,
+        doc_id: &str,
+        connection: u64,
+        session: Session,
+        shared: &Arc<AsyncMutex<SharedDoc>>,
+    ) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        match rooms.get_mut(doc_id) {
+            Some(room) if Arc::ptr_eq(&room.shared, shared) => {
+                room.connections.insert(connection, session);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn shared(&self, doc_id: &str) -> Option<Arc<AsyncMutex<SharedDoc>>> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms.get(doc_id).map(|room| room.shared.clone())
+    }
+
+    /// Ends the session of `shared` unless someone joined it again.
+    fn remove_idle(&self, doc_id: &str, shared: &Arc<AsyncMutex<SharedDoc>>) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms
+            .get(doc_id)
+            .is_some_and(|room| room.connections.is_empty() && Arc::ptr_eq(&room.shared, shared))
+        {
+            rooms.remove(doc_id);
+        }
+    }
+
+    /// Removes a connection. Returns the remaining editors and an awareness
+    /// message clearing the clients that connection announced. The room is
+    /// kept, see `remove_idle`.
+    fn leave(&self, doc_id: &str, connection: u64) -> (Vec<Session>, Option<Vec<u8>>) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(room) = rooms.get_mut(doc_id) else {
+            return (Vec::new(), None);
+        };
+        room.connections.remove(&connection);
+
+        let gone: Vec<u64> = room
+            .awareness
+            .iter()
+            .filter(|(_, a)| a.connection == connection)
+            .map(|(&client, _)| client)
+            .collect();
+        let removed: Vec<(u64, u64, &str)> = gone
+            .iter()
+            .filter_map(|client| {
+                let state = room.awareness.remove(client)?;
+                Some((*client, state.clock + 1, "null"))
+            })
+            .collect();
+        let message = (!removed.is_empty()).then(|| awareness_message(&removed));
+
+        let sessions = room.connections.values().cloned().collect();
+        (sessions, message)
+    }
+
+    /// Editors of a document, optionally leaving one out.
+    fn peers(&self, doc_id: &str, except: Option<u64>) -> Vec<Session> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .get(doc_id)
+            .map(|room| {
+                room.connections
+                    .iter()
+                    .filter(|(&id, _)| Some(id) != except)
+                    .map(|(_, session)| session.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn update_awareness(&self, doc_id: &str, connection: u64, entries: Vec<(u64, u64, String)>) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(room) = rooms.get_mut(doc_id) else {
+            return;
+        };
+        for (client, clock, state) in entries {
+            if state == "null" {
+                room.awareness.remove(&client);
+            } else {
+                room.awareness.insert(
+                    client,
+                    AwarenessState {
+                        clock,
+                        state,
+                        connection,
+                    },
+                );
+            }
+        }
+    }
+
+    /// An awareness message with every known state of the room, if any.
+    fn awareness_snapshot(&self, doc_id: &str) -> Option<Vec<u8>> {
+        let rooms = self.rooms.lock().unwrap();
+        let room = rooms.get(doc_id)?;
+        let entries: Vec<(u64, u64, &str)> = room
+            .awareness
+            .iter()
+            .map(|(&client, a)| (client, a.clock, a.state.as_str()))
+            .collect();
+        (!entries.is_empty()).then(|| awareness_message(&entries))
+    }
+
+    /// Disconnects every editor of a document, telling them to drop their
+    /// local state. Used when the document is changed outside the session.
+    pub async fn reset(&self, doc_id: &str) {
+        let room = self.rooms.lock().unwrap().remove(doc_id);
+        let Some(room) = room else {
+            return;
+        };
+        for session in room.connections.into_values() {
+            let _ = session
+                .close(Some(CloseReason {
+                    code: CloseCode::Other(CLOSE_RESET),
+                    description: Some("Document was reset".into()),
+                }))
+                .await;
+        }
+    }
+}
+
+/// Discards the stored collaboration state of a document and disconnects its
+/// editors, so the next session starts from `documents.content` again.
+pub async fn discard_state(pool: &DbPool, rooms: &Rooms, doc_id: &str) -> Result<(), ServiceError> {
+    // Locked while deleting, so a save in progress can't write the old state
+    // back afterwards.
+    let shared = rooms.shared(doc_id);
+    let mut guard = match &shared {
+        Some(shared) => Some(shared.lock().await),
+        None => None,
+    };
+    if let Some(shared) = guard.as_mut() {
+        shared.discarded = true;
+    }
+    sqlx::query!("DELETE FROM document_updates WHERE document_id = ?", doc_id)
+        .execute(pool)
+        .await?;
+    drop(guard);
+    rooms.reset(doc_id).await;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConnectQuery {
+    /// Browsers can't set headers on WebSocket requests, so the access token
+    /// may be passed here instead; the session cookie works as well.
+    pub token: Option<String>,
+}
+
+/// Upgrades to a WebSocket joining the document's editing session. Viewers
+/// and commenters follow along; changes are only accepted from editors.
+#[get("/collab/{id}")]
+pub async fn connect(
+    pool: web::Data<DbPool>,
+    rooms: web::Data<Rooms>,
+    bus: web::Data<EventBus>,
+    id: web::Path<String>,
+    query: web::Query<ConnectQuery>,
+    req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = match &query.token {
+        Some(token) => token_user_id(pool.get_ref(), token, Scope::DocsWrite).await?,
+        None => get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?,
+    };
+    let (doc, role) = authorize(pool.get_ref(), &id, &user_id, Role::Viewer).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+    if doc.is_folder {
+        return Err(ServiceError::BadRequest(
+            "Folders cannot be edited collaboratively".into(),
+        ));
+    }
+
+    let (response, session, stream) = actix_ws::handle(&req, body)
+        .map_err(|_| ServiceError::BadRequest("Expected a WebSocket upgrade".into()))?;
+    let max = max_message_bytes();
+    let stream = stream
+        .max_frame_size(max)
+        .aggregate_continuations()
+        .max_continuation_size(max);
+
+    let connection = Connection {
+        pool: pool.get_ref().clone(),
+        id: rooms.next_connection.fetch_add(1, Ordering::Relaxed),
+        shared: rooms.open(&doc.id),
+        rooms: rooms.into_inner(),
+        bus: bus.into_inner(),
+        doc_id: doc.id,
+        session,
+        user_id,
+        can_edit: role >= Role::Editor,
+    };
+    actix_web::rt::spawn(connection.run(stream));
+
+    Ok(response)
+}
+
+struct Connection {
+    pool: DbPool,
+    rooms: Arc<Rooms>,
+    bus: Arc<EventBus>,
+    doc_id: String,
+    id: u64,
+    session: Session,
+    shared: Arc<AsyncMutex<SharedDoc>>,
+    user_id: String,
+    can_edit: bool,
+}
+
+impl Connection {
+    async fn run(mut self, mut stream: AggregatedMessageStream) {
+        let result = self.serve(&mut stream).await;
+
+        let (peers, removed) = self.rooms.leave(&self.doc_id, self.id);
+        let last = peers.is_empty();
+        if let Some(message) = removed {
+            broadcast(peers, message).await;
+        }
+        let reason = result.err().map(|e| CloseReason {
+            code: CloseCode::Policy,
+            description: Some(e.to_string()),
+        });
+        let _ = self.session.close(reason).await;
+
+        if last {
+            let mut shared = self.shared.lock().await;
+            if let Err(e) = save(&self.pool, &self.bus, &self.doc_id, &mut shared).await {
+                eprintln!(
+                    "Failed to save collaborative edits of {}: {:?}",
+                    self.doc_id, e
+                );
+            }
+            drop(shared);
+            self.rooms.remove_idle(&self.doc_id, &self.shared);
+        }
+    }
+
+    async fn serve(&mut self, stream: &mut AggregatedMessageStream) -> Result<(), ServiceError> {
+        // The client answers with whatever the server is missing, and asks
+        // for the rest with its own sync step 1. Updates are relayed while
+        // the document is locked, so joining with it locked makes sure this
+        // is the first message.
+        loop {
+            let shared = self.shared.clone();
+            let mut shared = shared.lock().await;
+            if !shared.loaded {
+                load(&self.pool, &self.doc_id, &mut shared).await?;
+            }
+            if !self
+                .rooms
+                .join(&self.doc_id, self.id, self.session.clone(), &self.shared)
+            {
+                drop(shared);
+                self.shared = self.rooms.open(&self.doc_id);
+                continue;
+            }
+            let state_vector = shared.doc.transact().state_vector().encode_v1();
+            self.send(sync_message(SYNC_STEP1, &state_vector)).await?;
+            break;
+        }
+        if let Some(awareness) = self.rooms.awareness_snapshot(&self.doc_id) {
+            self.send(awareness).await?;
+        }
+
+        while let Some(message) = stream.recv().await {
+            match message {
+                Ok(AggregatedMessage::Binary(data)) => self.handle(&data).await?,
+                Ok(AggregatedMessage::Ping(data)) => {
+                    if self.session.pong(&data).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(AggregatedMessage::Close(_)) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle(&mut self, data: &[u8]) -> Result<(), ServiceError> {
+        let invalid = || ServiceError::BadRequest("Malformed message".into());
+        let mut reader = Reader::new(data);
+
+        match reader.var_uint().ok_or_else(invalid)? {
+            MESSAGE_SYNC => {
+                let step = reader.var_uint().ok_or_else(invalid)?;
+                let payload = reader.var_bytes().ok_or_else(invalid)?;
+                match step {
+                    SYNC_STEP1 => {
+                        let state_vector =
+                            StateVector::decode_v1(payload).map_err(|_| invalid())?;
+                        let missing = {
+                            let shared = self.shared.lock().await;
+                            let txn = shared.doc.transact();
+                            txn.encode_state_as_update_v1(&state_vector)
+                        };
+                        self.send(sync_message(SYNC_STEP2, &missing)).await?;
+                    }
+                    SYNC_STEP2 | SYNC_UPDATE if !self.can_edit => {}
+                    SYNC_STEP2 | SYNC_UPDATE => {
+                        if payload != EMPTY_UPDATE {
+                            self.apply(payload).await?;
+                        }
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            MESSAGE_AWARENESS => {
+                let payload = reader.var_bytes().ok_or_else(invalid)?;
+                let entries = parse_awareness(payload).ok_or_else(invalid)?;
+                self.rooms.update_awareness(&self.doc_id, self.id, entries);
+                // Echoed to the sender as well: y-websocket clients treat a
+                // silent connection as dead.
+                let peers = self.rooms.peers(&self.doc_id, None);
+                broadcast(peers, data.to_vec()).await;
+            }
+            MESSAGE_QUERY_AWARENESS => {
+                if let Some(awareness) = self.rooms.awareness_snapshot(&self.doc_id) {
+                    self.send(awareness).await?;
+                }
+            }
+            // Auth and custom messages are not used.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Appends an editor's update to the log, applies it to the shared
+    /// document and relays it, scheduling a save if none is pending.
+    async fn apply(&self, update: &[u8]) -> Result<(), ServiceError> {
+        let decoded = Update::decode_v1(update)
+            .map_err(|_| ServiceError::BadRequest("Malformed message".into()))?;
+        let mut shared = self.shared.lock().await;
+        // The editors are being disconnected.
+        if shared.discarded {
+            return Ok(());
+        }
+
+        let stored = sqlx::query!(
+            r#"
+            INSERT INTO document_updates (document_id, update_data)
+            SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM documents WHERE id = ?1 AND deleted_at IS NULL)
+            "#,
+            self.doc_id,
+            update
+        )
+        .execute(&self.pool)
+        .await?;
+        if stored.rows_affected() == 0 {
+            return Err(ServiceError::BadRequest("Document not found".into()));
+        }
+        shared.log_len += 1;
+        shared
+            .doc
+            .transact_mut()
+            .apply_update(decoded)
+            .map_err(|_| ServiceError::BadRequest("Malformed message".into()))?;
+        let peers = self.rooms.peers(&self.doc_id, Some(self.id));
+        broadcast(peers, sync_message(SYNC_UPDATE, update)).await;
+
+        let now = Instant::now();
+        match &mut shared.unsaved {
+            Some(unsaved) => {
+                unsaved.author.clone_from(&self.user_id);
+                unsaved.last = now;
+            }
+            None => {
+                shared.unsaved = Some(Unsaved {
+                    author: self.user_id.clone(),
+                    since: now,
+                    last: now,
+                });
+                actix_web::rt::spawn(save_when_idle(
+                    self.pool.clone(),
+                    self.bus.clone(),
+                    self.doc_id.clone(),
+                    self.shared.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, message: Vec<u8>) -> Result<(), ServiceError> {
+        self.session
+            .binary(message)
+            .await
+            .map_err(|_| ServiceError::BadRequest("Connection closed".into()))
+    }
+}
+
+/// Applies the stored updates of a document to the session's copy. Without
+/// any, the copy is built from `documents.content` and stored as the first
+/// update, so the editors all start from the same state.
+async fn load(pool: &DbPool, doc_id: &str, shared: &mut SharedDoc) -> Result<(), ServiceError> {
+    let updates = sqlx::query_scalar!(
+        "SELECT update_data FROM document_updates WHERE document_id = ? ORDER BY id",
+        doc_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut txn = shared.doc.transact_mut();
+    for update in &updates {
+        let applied = Update::decode_v1(update)
+            .map_err(|e| e.to_string())
+            .and_then(|u| txn.apply_update(u).map_err(|e| e.to_string()));
+        if let Err(e) = applied {
+            eprintln!("Skipping unreadable update of {}: {}", doc_id, e);
+        }
+    }
+    drop(txn);
+    shared.log_len = updates.len();
+
+    if updates.is_empty() {
+        let content = sqlx::query_scalar!("SELECT content FROM documents WHERE id = ?", doc_id)
+            .fetch_optional(pool)
+            .await?
+            .flatten()
+            .filter(|c| !c.trim().is_empty());
+        if let Some(content) = content {
+            let fragment = shared.doc.get_or_insert_xml_fragment(collab_html::FRAGMENT);
+            let update = {
+                let mut txn = shared.doc.transact_mut();
+                collab_html::seed(&mut txn, &fragment, &content);
+                txn.encode_update_v1()
+            };
+            sqlx::query!(
+                "INSERT INTO document_updates (document_id, update_data) VALUES (?, ?)",
+                doc_id,
+                update
+            )
+            .execute(pool)
+            .await?;
+            shared.log_len = 1;
+        }
+    }
+    shared.loaded = true;
+    Ok(())
+}
+
+/// Saves the document once no changes arrived for the save delay.
+async fn save_when_idle(
+    pool: DbPool,
+    bus: Arc<EventBus>,
+    doc_id: String,
+    shared: Arc<AsyncMutex<SharedDoc>>,
+) {
+    let delay = save_delay();
+    loop {
+        let mut guard = shared.lock().await;
+        // Saved when the last editor left.
+        let Some(unsaved) = &guard.unsaved else {
+            return;
+        };
+        let due = (unsaved.last + delay).min(unsaved.since + delay * MAX_SAVE_DELAYS);
+        let now = Instant::now();
+        if due > now {
+            drop(guard);
+            actix_web::rt::time::sleep(due - now).await;
+            continue;
+        }
+        if let Err(e) = save(&pool, &bus, &doc_id, &mut guard).await {
+            eprintln!("Failed to save collaborative edits of {}: {:?}", doc_id, e);
+        }
+        return;
+    }
+}
+
+/// Renders unsaved changes into `documents.content` and compacts the log to
+/// the encoded state of the shared document.
+async fn save(
+    pool: &DbPool,
+    bus: &EventBus,
+    doc_id: &str,
+    shared: &mut SharedDoc,
+) -> Result<(), ServiceError> {
+    if shared.discarded || !shared.loaded || (shared.unsaved.is_none() && shared.log_len <= 1) {
+        return Ok(());
+    }
+    let fragment = shared.doc.get_or_insert_xml_fragment(collab_html::FRAGMENT);
+    let (state, content) = {
+        let txn = shared.doc.transact();
+        (
+            txn.encode_state_as_update_v1(&StateVector::default()),
+            collab_html::render(&txn, &fragment),
+        )
+    };
+    let now = Utc::now().naive_utc();
+
+    // Trashed documents are saved as well, so restoring one keeps the edits.
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM document_updates WHERE document_id = ?", doc_id)
+        .execute(&mut *tx)
+        .await?;
+    let stored = sqlx::query!(
+        r#"
+        INSERT INTO document_updates (document_id, update_data)
+        SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM documents WHERE id = ?1)
+        "#,
+        doc_id,
+        state
+    )
+    .execute(&mut *tx)
+    .await?;
+    let mut updated = None;
+    if let Some(unsaved) = &shared.unsaved {
+        let changed = sqlx::query!(
+            r#"
+            UPDATE documents SET content = ?1, updated_at = ?2, version = version + 1
+            WHERE id = ?3 AND content IS NOT ?1
+            "#,
+            content,
+            now,
+            doc_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if changed.rows_affected() > 0 {
+            let doc = sqlx::query_as!(Document, "SELECT * FROM documents WHERE id = ?", doc_id)
+                .fetch_one(&mut *tx)
+                .await?;
+            revisions::record(
+                &mut tx,
+                doc_id,
+                &unsaved.author,
+                &doc.title,
+                doc.content.as_deref(),
+                None,
+                true,
+            )
+            .await?;
+            updated = Some(doc);
+        }
+    }
+    tx.commit().await?;
+
+    shared.log_len = stored.rows_affected() as usize;
+    shared.unsaved = None;
+    if let Some(doc) = updated {
+        bus.document(pool, "document.updated", &doc).await;
+    }
+    Ok(())
+}
+
+async fn broadcast(sessions: Vec<Session>, message: Vec<u8>) {
+    let message = Bytes::from(message);
+    for mut session in sessions {
+        // Closed connections are cleaned up by their own task.
+        let _ = session.binary(message.clone()).await;
+    }
+}
+
+fn sync_message(step: u64, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 8);
+    write_var_uint(&mut out, MESSAGE_SYNC);
+    write_var_uint(&mut out, step);
+    write_var_bytes(&mut out, payload);
+    out
+}
+
+/// Encodes awareness entries of `(client id, clock, state JSON)`.
+fn awareness_message(entries: &[(u64, u64, &str)]) -> Vec<u8> {
+    let mut update = Vec::new();
+    write_var_uint(&mut update, entries.len() as u64);
+    for (client, clock, state) in entries {
+        write_var_uint(&mut update, *client);
+        write_var_uint(&mut update, *clock);
+        write_var_bytes(&mut update, state.as_bytes());
+    }
+
+    let mut out = Vec::with_capacity(update.len() + 8);
+    write_var_uint(&mut out, MESSAGE_AWARENESS);
+    write_var_bytes(&mut out, &update);
+    out
+}
+
+fn parse_awareness(update: &[u8]) -> Option<Vec<(u64, u64, String)>> {
+    let mut reader = Reader::new(update);
+    let count = reader.var_uint()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let client = reader.var_uint()?;
+        let clock = reader.var_uint()?;
+        let state = std::str::from_utf8(reader.var_bytes()?).ok()?;
+        entries.push((client, clock, state.to_string()));
+    }
+    Some(entries)
+}
+
+/// Reader for lib0 encoding, which Yjs uses on the wire.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn var_uint(&mut self) -> Option<u64> {
+        let mut value: u64 = 0;
+        for (i, &byte) in self.data.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.data = &self.data[i + 1..];
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn var_bytes(&mut self) -> Option<&'a [u8]> {
+        let len = usize::try_from(self.var_uint()?).ok()?;
+        if len > self.data.len() {
+            return None;
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(bytes)
+    }
+}
+
+fn write_var_uint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_var_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_var_uint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
@@ -0,0 +1,568 @@
+//! HTML rendering of the shared document edited over `/collab`.
+//!
+//! Tiptap's Collaboration extension keeps the ProseMirror document in an XML
+//! fragment: elements are named after node types and carry the node
+//! attributes, and marks are formatting attributes on the text. This turns
+//! that back into the markup the editor itself would save, and seeds that
+//! structure from saved markup for documents without collaboration state.
+
+use std::{collections::HashMap, sync::Arc};
+use yrs::{
+    types::Attrs, Any, Out, ReadTxn, Text, TransactionMut, Xml, XmlElementPrelim, XmlElementRef,
+    XmlFragment, XmlFragmentRef, XmlOut, XmlTextPrelim, XmlTextRef,
+};
+
+use crate::html_text::decode_entities;
+
+/// Fragment the Collaboration extension binds to unless told otherwise.
+pub const FRAGMENT: &str = "default";
+
+/// Marks and their tags, outermost first.
+const MARKS: &[(&str, &str)] = &[
+    ("link", "a"),
+    ("bold", "strong"),
+    ("italic", "em"),
+    ("underline", "u"),
+    ("strike", "s"),
+    ("code", "code"),
+];
+
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Renders the children of `fragment` as HTML.
+pub fn render<T: ReadTxn, F: XmlFragment>(txn: &T, fragment: &F) -> String {
+    let mut out = String::new();
+    children(txn, fragment, &mut out);
+    out
+}
+
+fn children<T: ReadTxn, F: XmlFragment>(txn: &T, parent: &F, out: &mut String) {
+    for child in parent.children(txn) {
+        match child {
+            XmlOut::Element(element) => render_element(txn, &element, out),
+            XmlOut::Fragment(fragment) => children(txn, &fragment, out),
+            XmlOut::Text(text) => render_text(text.diff(txn, |_| ()), out),
+        }
+    }
+}
+
+fn render_element<T: ReadTxn>(txn: &T, element: &XmlElementRef, out: &mut String) {
+    // Unset node attributes are stored as null.
+    let attribute = |name: &str| match element.get_attribute(txn, name) {
+        None | Some(Out::Any(Any::Null | Any::Undefined)) => None,
+        Some(value) => Some(value.to_string(txn)),
+    };
+
+    let tag = match element.tag().as_ref() {
+        "paragraph" => "p",
+        "heading" => {
+            let level: usize = attribute("level").and_then(|l| l.parse().ok()).unwrap_or(1);
+            HEADINGS[level.clamp(1, 6) - 1]
+        }
+        "blockquote" => "blockquote",
+        "bulletList" => "ul",
+        "orderedList" => "ol",
+        "listItem" => "li",
+        "codeBlock" => {
+            out.push_str("<pre><code");
+            if let Some(language) = attribute("language").filter(|l| !l.is_empty()) {
+                out.push_str(" class=\"language-");
+                escape_into(&language, out);
+                out.push('"');
+            }
+            out.push('>');
+            children(txn, element, out);
+            out.push_str("</code></pre>");
+            return;
+        }
+        "horizontalRule" => {
+            out.push_str("<hr>");
+            return;
+        }
+        "hardBreak" => {
+            out.push_str("<br>");
+            return;
+        }
+        // Nodes of extensions we don't know keep at least their text.
+        _ => {
+            children(txn, element, out);
+            return;
+        }
+    };
+
+    out.push('<');
+    out.push_str(tag);
+    if tag == "ol" {
+        if let Some(start) = attribute("start").filter(|s| s != "1") {
+            out.push_str(" start=\"");
+            escape_into(&start, out);
+            out.push('"');
+        }
+    }
+    out.push('>');
+    children(txn, element, out);
+    out.push_str("</");
+    out.push_str(tag);
+    out.push('>');
+}
+
+fn render_text(runs: Vec<yrs::types::text::Diff<()>>, out: &mut String) {
+    // Marks shared with the previous run stay open, like the editor nests
+    // them.
+    let mut open: Vec<(&str, &str, Option<&str>)> = Vec::new();
+    for run in &runs {
+        // Embeds have no HTML of their own.
+        let Out::Any(Any::String(text)) = &run.insert else {
+            continue;
+        };
+        let attrs = run.attributes.as_deref();
+        let marks: Vec<(&str, &str, Option<&str>)> = MARKS
+            .iter()
+            .filter(|(name, _)| {
+                attrs.is_some_and(|a| a.get(*name).is_some_and(|v| *v != Any::Null))
+            })
+            .map(|&(name, tag)| {
+                (
+                    name,
+                    tag,
+                    (name == "link").then(|| link_href(attrs)).flatten(),
+                )
+            })
+            .collect();
+
+        let kept = open.iter().zip(&marks).take_while(|(a, b)| a == b).count();
+        close_marks(&mut open, kept, out);
+        for &(name, tag, href) in &marks[kept..] {
+            out.push('<');
+            out.push_str(tag);
+            if let Some(href) = href {
+                out.push_str(" href=\"");
+                escape_into(href, out);
+                out.push('"');
+            }
+            out.push('>');
+            open.push((name, tag, href));
+        }
+        escape_into(text, out);
+    }
+    close_marks(&mut open, 0, out);
+}
+
+fn close_marks(open: &mut Vec<(&str, &str, Option<&str>)>, keep: usize, out: &mut String) {
+    while open.len() > keep {
+        let (_, tag, _) = open.pop().unwrap();
+        out.push_str("</");
+        out.push_str(tag);
+        out.push('>');
+    }
+}
+
+fn link_href(attrs: Option<&Attrs>) -> Option<&str> {
+    match attrs?.get("link")? {
+        Any::Map(link) => match link.get("href")? {
+            Any::String(href) => Some(href),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Elements whose content is never text.
+const SKIPPED_TAGS: &[&str] = &["script", "style", "template"];
+
+/// Builds the children of `fragment` from HTML, mapping the tags `render`
+/// produces back to their nodes and marks. Other elements are dropped but
+/// keep their text, which goes into a paragraph where a block is expected.
+pub fn seed(txn: &mut TransactionMut, fragment: &XmlFragmentRef, html: &str) {
+    let mut builder = Builder {
+        txn,
+        stack: vec![Parent {
+            node: Node::Root(fragment.clone()),
+            tag: String::new(),
+            textblock: false,
+        }],
+        text: None,
+        marks: Vec::new(),
+    };
+    let mut skipping: Option<String> = None;
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if skipping.is_none() {
+            builder.text(&rest[..start]);
+        }
+        let Some(end) = rest[start..].find('>') else {
+            // An unterminated tag is treated as text.
+            if skipping.is_none() {
+                builder.text(&rest[start..]);
+            }
+            rest = "";
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        if tag.starts_with('!') {
+            continue;
+        }
+
+        let closing = tag.starts_with('/');
+        let tag = tag.trim_start_matches('/').trim_end_matches('/');
+        let (name, attributes) = tag
+            .split_once(|c: char| c.is_whitespace())
+            .unwrap_or((tag, ""));
+        let name = name.to_ascii_lowercase();
+
+        if let Some(skipped) = &skipping {
+            if closing && name == *skipped {
+                skipping = None;
+            }
+            continue;
+        }
+        if closing {
+            builder.end(&name);
+        } else if SKIPPED_TAGS.contains(&name.as_str()) {
+            skipping = Some(name);
+        } else {
+            builder.start(&name, attributes);
+        }
+    }
+    if skipping.is_none() {
+        builder.text(rest);
+    }
+}
+
+enum Node {
+    Root(XmlFragmentRef),
+    Element(XmlElementRef),
+}
+
+struct Parent {
+    node: Node,
+    /// Tag that closes it; empty for the root and implied paragraphs.
+    tag: String,
+    /// Whether it holds text rather than blocks.
+    textblock: bool,
+}
+
+struct Builder<'a, 'doc> {
+    txn: &'a mut TransactionMut<'doc>,
+    stack: Vec<Parent>,
+    /// Text node being appended to, until an inline node or block follows.
+    text: Option<XmlTextRef>,
+    /// Marks applied to text, in the order they were opened.
+    marks: Vec<(&'static str, Any)>,
+}
+
+impl Builder<'_, '_> {
+    fn start(&mut self, tag: &str, attributes: &str) {
+        let node = match tag {
+            "p" => "paragraph",
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => "heading",
+            "blockquote" => "blockquote",
+            "ul" => "bulletList",
+            "ol" => "orderedList",
+            "li" => "listItem",
+            "pre" => "codeBlock",
+            "hr" => {
+                self.close_textblock();
+                self.push_element("horizontalRule");
+                return;
+            }
+            "br" => {
+                self.open_textblock();
+                self.push_element("hardBreak");
+                return;
+            }
+            "code" if self.in_code_block() => {
+                let language = attribute(attributes, "class")
+                    .and_then(|c| c.strip_prefix("language-").map(str::to_string));
+                if let (Some(language), Node::Element(block)) =
+                    (language, &self.stack.last().unwrap().node)
+                {
+                    block.insert_attribute(self.txn, "language", Any::from(language));
+                }
+                return;
+            }
+            _ => {
+                let mark = match tag {
+                    "strong" | "b" => "bold",
+                    "em" | "i" => "italic",
+                    "u" => "underline",
+                    "s" | "strike" | "del" => "strike",
+                    "code" => "code",
+                    "a" => "link",
+                    _ => return,
+                };
+                let mut value = HashMap::new();
+                if let Some(href) = attribute(attributes, "href").filter(|_| mark == "link") {
+                    value.insert("href".to_string(), Any::from(href));
+                }
+                self.marks.push((mark, Any::Map(Arc::new(value))));
+                return;
+            }
+        };
+
+        self.close_textblock();
+        let element = self.push_element(node);
+        match node {
+            "heading" => {
+                let level = i64::from(tag.as_bytes()[1] - b'0');
+                element.insert_attribute(self.txn, "level", Any::from(level));
+            }
+            "orderedList" => {
+                if let Some(start) = attribute(attributes, "start")
+                    .and_then(|s| s.parse::<i64>().ok())
+                    .filter(|&s| s != 1)
+                {
+                    element.insert_attribute(self.txn, "start", Any::from(start));
+                }
+            }
+            _ => {}
+        }
+        self.stack.push(Parent {
+            node: Node::Element(element),
+            tag: tag.to_string(),
+            textblock: matches!(node, "paragraph" | "heading" | "codeBlock"),
+        });
+    }
+
+    fn end(&mut self, tag: &str) {
+        if let Some(index) = self.stack.iter().rposition(|p| p.tag == tag) {
+            if index > 0 {
+                self.stack.truncate(index);
+                self.text = None;
+            }
+            return;
+        }
+        let mark = match tag {
+            "strong" | "b" => "bold",
+            "em" | "i" => "italic",
+            "u" => "underline",
+            "s" | "strike" | "del" => "strike",
+            "code" => "code",
+            "a" => "link",
+            _ => return,
+        };
+        if let Some(index) = self.marks.iter().rposition(|(name, _)| *name == mark) {
+            self.marks.remove(index);
+        }
+    }
+
+    fn text(&mut self, raw: &str) {
+        let mut text = decode_entities(raw);
+        if !self.in_code_block() {
+            text = collapse_whitespace(&text);
+        }
+        if text.is_empty() || (text == " " && !self.stack.last().unwrap().textblock) {
+            return;
+        }
+        self.open_textblock();
+
+        let node = match self.text.take() {
+            Some(node) => node,
+            None => match &self.stack.last().unwrap().node {
+                Node::Root(fragment) => fragment.push_back(self.txn, XmlTextPrelim::new("")),
+                Node::Element(element) => element.push_back(self.txn, XmlTextPrelim::new("")),
+            },
+        };
+        // Inserted text takes on the formatting before it unless the marks
+        // that end there are cleared.
+        let attrs: Attrs = MARKS
+            .iter()
+            .map(|(name, _)| {
+                let value = self
+                    .marks
+                    .iter()
+                    .rfind(|(mark, _)| mark == name)
+                    .map_or(Any::Null, |(_, value)| value.clone());
+                (Arc::from(*name), value)
+            })
+            .collect();
+        let len = node.len(self.txn);
+        node.insert_with_attributes(self.txn, len, &text, attrs);
+        self.text = Some(node);
+    }
+
+    fn in_code_block(&self) -> bool {
+        matches!(&self.stack.last().unwrap().node,
+            Node::Element(element) if element.tag().as_ref() == "codeBlock")
+    }
+
+    /// Makes sure text can be added, wrapping it in a paragraph if needed.
+    fn open_textblock(&mut self) {
+        if !self.stack.last().unwrap().textblock {
+            let paragraph = self.push_element("paragraph");
+            self.stack.push(Parent {
+                node: Node::Element(paragraph),
+                tag: String::new(),
+                textblock: true,
+            });
+        }
+    }
+
+    /// Ends the current textblock, as blocks can't be nested in one.
+    fn close_textblock(&mut self) {
+        while self.stack.len() > 1 && self.stack.last().unwrap().textblock {
+            self.stack.pop();
+        }
+        self.text = None;
+    }
+
+    fn push_element(&mut self, name: &str) -> XmlElementRef {
+        self.text = None;
+        let element = XmlElementPrelim::empty(name);
+        match &self.stack.last().unwrap().node {
+            Node::Root(fragment) => fragment.push_back(self.txn, element),
+            Node::Element(parent) => parent.push_back(self.txn, element),
+        }
+    }
+}
+
+/// The decoded value of an attribute in the source of a start tag.
+fn attribute(source: &str, name: &str) -> Option<String> {
+    let mut rest = source;
+    loop {
+        rest = rest.trim_start();
+        let end = rest.find(|c: char| c == '=' || c.is_whitespace())?;
+        let (key, after) = rest.split_at(end);
+        let after = after.trim_start();
+        let Some(after) = after.strip_prefix('=') else {
+            rest = after;
+            continue;
+        };
+        let after = after.trim_start();
+        let (value, next) = match after.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let close = after[1..].find(quote)? + 1;
+                (&after[1..close], &after[close + 1..])
+            }
+            _ => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                after.split_at(end)
+            }
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(decode_entities(value));
+        }
+        rest = next;
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !out.ends_with(' ') {
+                out.push(' ');
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn escape_into(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use yrs::{Doc, Transact, XmlElementPrelim, XmlTextPrelim};
+
+    #[test]
+    fn renders_tiptap_nodes_and_marks() {
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment(FRAGMENT);
+        let mut txn = doc.transact_mut();
+
+        let heading = fragment.push_back(&mut txn, XmlElementPrelim::empty("heading"));
+        heading.insert_attribute(&mut txn, "level", 2);
+        heading.push_back(&mut txn, XmlTextPrelim::new("Q&A"));
+
+        let paragraph = fragment.push_back(&mut txn, XmlElementPrelim::empty("paragraph"));
+        let text = paragraph.push_back(&mut txn, XmlTextPrelim::new("Say "));
+        let bold: Attrs = HashMap::from([("bold".into(), Any::Map(Default::default()))]);
+        text.insert_with_attributes(&mut txn, 4, "<hi>", bold);
+        let link: Attrs = HashMap::from([(
+            "link".into(),
+            Any::Map(
+                HashMap::from([("href".to_string(), Any::from("https://a.example/?x=\"1\""))])
+                    .into(),
+            ),
+        )]);
+        text.insert_with_attributes(&mut txn, 8, " here", link);
+        paragraph.push_back(&mut txn, XmlElementPrelim::empty("hardBreak"));
+
+        let list = fragment.push_back(&mut txn, XmlElementPrelim::empty("orderedList"));
+        list.insert_attribute(&mut txn, "start", 3);
+        let item = list.push_back(&mut txn, XmlElementPrelim::empty("listItem"));
+        let inner = item.push_back(&mut txn, XmlElementPrelim::empty("paragraph"));
+        inner.push_back(&mut txn, XmlTextPrelim::new("one"));
+
+        let code = fragment.push_back(&mut txn, XmlElementPrelim::empty("codeBlock"));
+        code.insert_attribute(&mut txn, "language", Any::Null);
+        code.push_back(&mut txn, XmlTextPrelim::new("a < b"));
+
+        assert_eq!(
+            render(&txn, &fragment),
+            concat!(
+                "<h2>Q&amp;A</h2>",
+                "<p>Say <strong>&lt;hi&gt;</strong>",
+                "<a href=\"https://a.example/?x=&quot;1&quot;\"> here</a><br></p>",
+                "<ol start=\"3\"><li><p>one</p></li></ol>",
+                "<pre><code>a &lt; b</code></pre>",
+            )
+        );
+    }
+
+    fn seeded(html: &str) -> String {
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment(FRAGMENT);
+        let mut txn = doc.transact_mut();
+        seed(&mut txn, &fragment, html);
+        render(&txn, &fragment)
+    }
+
+    #[test]
+    fn seeding_round_trips_editor_markup() {
+        let html = concat!(
+            "<h2>Q&amp;A</h2>",
+            "<p>Say <strong>&lt;hi&gt;</strong>",
+            "<a href=\"https://a.example/?x=&quot;1&quot;\"> <em>here</em></a><br>again</p>",
+            "<ol start=\"3\"><li><p>one</p></li><li><p>two</p></li></ol>",
+            "<blockquote><p>quoted</p></blockquote><hr>",
+            "<pre><code class=\"language-rust\">fn main() {\n    a &lt; b\n}</code></pre>",
+        );
+        assert_eq!(seeded(html), html);
+    }
+
+    #[test]
+    fn seeding_wraps_loose_text_in_paragraphs() {
+        assert_eq!(
+            seeded("Hello<br>world\n<div><b>bold</b>  text</div><script>x()</script><!-- c -->"),
+            "<p>Hello<br>world <strong>bold</strong> text</p>"
+        );
+        assert_eq!(
+            seeded("<ul>\n  <li>item</li>\n</ul>"),
+            "<ul><li><p>item</p></li></ul>"
+        );
+    }
+
+    #[test]
+    fn empty_fragment_renders_nothing() {
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment(FRAGMENT);
+        assert_eq!(render(&doc.transact(), &fragment), "");
+    }
+}
@@ -0,0 +1,146 @@
+//! Cookie transport for session tokens, used by the bundled frontend so that
+//! tokens never reach JavaScript.
+//!
+//! A client opts in by sending `X-Auth-Mode: cookie` when it logs in. The
+//! access and refresh tokens are then set as HttpOnly cookies instead of being
+//! returned in the body, together with a readable `adoc_csrf` cookie. Every
+//! unsafe request authenticated by cookie must echo that value in the
+//! `X-CSRF-Token` header (double-submit). Requests with an `Authorization`
+//! header are unaffected.
+
+use actix_web::{
+    cookie::{time, Cookie, SameSite},
+    http::Method,
+    HttpRequest, HttpResponseBuilder,
+};
+use chrono::Duration;
+
+use crate::{
+    auth::{generate_token, hash_token},
+    errors::ServiceError,
+};
+
+pub const ACCESS_COOKIE: &str = "adoc_access";
+pub const REFRESH_COOKIE: &str = "adoc_refresh";
+pub const CSRF_COOKIE: &str = "adoc_csrf";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+pub const MODE_HEADER: &str = "X-Auth-Mode";
+
+/// The refresh cookie is only sent to the endpoints that consume it.
+const REFRESH_COOKIE_PATH: &str = "/auth";
+
+/// Whether cookies are marked `Secure` (`AUTH_COOKIE_SECURE`, default true).
+/// Browsers accept secure cookies from `http://localhost`, so this only needs
+/// turning off when testing over plain HTTP on another host.
+pub fn cookie_secure() -> bool {
+    std::env::var("AUTH_COOKIE_SECURE")
+        .map(|v| v != "false" && v != "0")
+        .unwrap_or(true)
+}
+
+/// SameSite policy of the session cookies (`AUTH_COOKIE_SAMESITE`, `strict`
+/// or `lax`, default `strict`).
+fn same_site() -> SameSite {
+    match std::env::var("AUTH_COOKIE_SAMESITE").as_deref() {
+        Ok("lax") => SameSite::Lax,
+        _ => SameSite::Strict,
+    }
+}
+
+fn build_cookie(
+    name: &'static str,
+    value: String,
+    path: &'static str,
+    max_age: Duration,
+) -> Cookie<'static> {
+    Cookie::build(name, value)
+        .path(path)
+        .secure(cookie_secure())
+        .same_site(same_site())
+        .max_age(time::Duration::seconds(max_age.num_seconds()))
+        .finish()
+}
+
+/// Whether the client asked for tokens to be delivered as cookies.
+pub fn wants_cookies(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(MODE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("cookie"))
+}
+
+pub fn access_token(req: &HttpRequest) -> Option<String> {
+    req.cookie(ACCESS_COOKIE).map(|c| c.value().to_string())
+}
+
+pub fn refresh_token(req: &HttpRequest) -> Option<String> {
+    req.cookie(REFRESH_COOKIE).map(|c| c.value().to_string())
+}
+
+/// Sets the session cookies on a login or refresh response. The CSRF token
+/// is kept for the lifetime of the session so that requests already in
+/// flight during a refresh still pass.
+pub fn set_session_cookies(
+    res: &mut HttpResponseBuilder,
+    req: &HttpRequest,
+    access_token: String,
+    access_ttl: Duration,
+    refresh_token: String,
+    refresh_ttl: Duration,
+) {
+    let csrf = req
+        .cookie(CSRF_COOKIE)
+        .map(|c| c.value().to_string())
+        .unwrap_or_else(generate_token);
+
+    let mut access = build_cookie(ACCESS_COOKIE, access_token, "/", access_ttl);
+    access.set_http_only(true);
+    let mut refresh = build_cookie(
+        REFRESH_COOKIE,
+        refresh_token,
+        REFRESH_COOKIE_PATH,
+        refresh_ttl,
+    );
+    refresh.set_http_only(true);
+    // Readable by the frontend, which copies it into the CSRF header.
+    let csrf = build_cookie(CSRF_COOKIE, csrf, "/", refresh_ttl);
+
+    res.cookie(access).cookie(refresh).cookie(csrf);
+}
+
+/// Expires every session cookie, e.g. on logout.
+pub fn clear_session_cookies(res: &mut HttpResponseBuilder) {
+    for (name, path) in [
+        (ACCESS_COOKIE, "/"),
+        (REFRESH_COOKIE, REFRESH_COOKIE_PATH),
+        (CSRF_COOKIE, "/"),
+    ] {
+        let mut cookie = Cookie::build(name, "").path(path).finish();
+        cookie.make_removal();
+        res.cookie(cookie);
+    }
+}
+
+/// Double-submit check for requests authenticated by cookie: unsafe methods
+/// must carry the CSRF cookie's value in the `X-CSRF-Token` header.
+pub fn verify_csrf(req: &HttpRequest) -> Result<(), ServiceError> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+
+    let cookie = req.cookie(CSRF_COOKIE);
+    let header = req.headers().get(CSRF_HEADER).and_then(|v| v.to_str().ok());
+
+    // Comparing digests keeps the comparison time independent of where the
+    // values differ.
+    match (cookie, header) {
+        (Some(cookie), Some(header))
+            if !header.is_empty() && hash_token(cookie.value()) == hash_token(header) =>
+        {
+            Ok(())
+        }
+        _ => Err(ServiceError::Forbidden(
+            "CSRF token missing or invalid".into(),
+        )),
+    }
+}
@@ -0,0 +1,340 @@
+//! Diffs between document revisions and per-block blame.
+//!
+//! Both work on the text of the stored HTML split into blocks (see
+//! `html_text`), so markup-only changes don't show up as edits.
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use similar::{Algorithm, ChangeTag, DiffTag, TextDiff};
+
+use crate::{
+    api_tokens::Scope,
+    auth::get_user_id,
+    db::DbPool,
+    errors::ServiceError,
+    html_text,
+    permissions::{authorize, Role},
+    revisions::load_revision,
+    workspaces,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    /// Defaults to the revision before `to`; 0 is the empty document.
+    pub from: Option<i64>,
+    /// Defaults to the latest revision.
+    pub to: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Equal,
+    Insert,
+    Delete,
+    Replace,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WordChange {
+    pub op: Op,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockChange {
+    pub op: Op,
+    /// Position of the block in the `from` revision, if it exists there.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_index: Option<usize>,
+    /// Position of the block in the `to` revision, if it exists there.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_index: Option<usize>,
+    /// Block text; for `replace`, the new text.
+    pub text: String,
+    /// Word-level changes of a replaced block.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub words: Option<Vec<WordChange>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DiffStats {
+    pub inserted: usize,
+    pub deleted: usize,
+    pub replaced: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TitleChange {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevisionDiff {
+    pub from: i64,
+    pub to: i64,
+    pub title: Option<TitleChange>,
+    pub blocks: Vec<BlockChange>,
+    pub stats: DiffStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlameBlock {
+    pub text: String,
+    /// `null` when the block isn't covered by the history.
+    pub revision: Option<i64>,
+    pub author_id: Option<String>,
+    pub author_name: Option<String>,
+    pub changed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Blame {
+    pub revision: i64,
+    pub blocks: Vec<BlameBlock>,
+}
+
+/// Block-level diff; paired-up replaced blocks also get a word-level diff.
+fn diff_blocks(old: &[String], new: &[String]) -> (Vec<BlockChange>, DiffStats) {
+    let old_refs: Vec<&str> = old.iter().map(String::as_str).collect();
+    let new_refs: Vec<&str> = new.iter().map(String::as_str).collect();
+    let diff = TextDiff::configure()
+        .algorithm(Algorithm::Patience)
+        .diff_slices(&old_refs, &new_refs);
+
+    let mut changes = Vec::new();
+    let mut stats = DiffStats::default();
+    let deleted = |i: usize| BlockChange {
+        op: Op::Delete,
+        old_index: Some(i),
+        new_index: None,
+        text: old[i].clone(),
+        words: None,
+    };
+    let inserted = |i: usize| BlockChange {
+        op: Op::Insert,
+        old_index: None,
+        new_index: Some(i),
+        text: new[i].clone(),
+        words: None,
+    };
+
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        match tag {
+            DiffTag::Equal => {
+                for (o, n) in old_range.zip(new_range) {
+                    stats.unchanged += 1;
+                    changes.push(BlockChange {
+                        op: Op::Equal,
+                        old_index: Some(o),
+                        new_index: Some(n),
+                        text: new[n].clone(),
+                        words: None,
+                    });
+                }
+            }
+            DiffTag::Delete => {
+                stats.deleted += old_range.len();
+                changes.extend(old_range.map(deleted));
+            }
+            DiffTag::Insert => {
+                stats.inserted += new_range.len();
+                changes.extend(new_range.map(inserted));
+            }
+            DiffTag::Replace => {
+                let paired = old_range.len().min(new_range.len());
+                for (o, n) in old_range.clone().zip(new_range.clone()) {
+                    stats.replaced += 1;
+                    changes.push(BlockChange {
+                        op: Op::Replace,
+                        old_index: Some(o),
+                        new_index: Some(n),
+                        text: new[n].clone(),
+                        words: Some(diff_words(&old[o], &new[n])),
+                    });
+                }
+                stats.deleted += old_range.len() - paired;
+                stats.inserted += new_range.len() - paired;
+                changes.extend(old_range.skip(paired).map(deleted));
+                changes.extend(new_range.skip(paired).map(inserted));
+            }
+        }
+    }
+    (changes, stats)
+}
+
+/// Word-level diff of two blocks, with runs of the same kind merged.
+fn diff_words(old: &str, new: &str) -> Vec<WordChange> {
+    let diff = TextDiff::configure()
+        .algorithm(Algorithm::Patience)
+        .diff_words(old, new);
+
+    let mut words: Vec<WordChange> = Vec::new();
+    for change in diff.iter_all_changes() {
+        let op = match change.tag() {
+            ChangeTag::Equal => Op::Equal,
+            ChangeTag::Insert => Op::Insert,
+            ChangeTag::Delete => Op::Delete,
+        };
+        match words.last_mut() {
+            Some(last) if last.op == op => last.text.push_str(change.value()),
+            _ => words.push(WordChange {
+                op,
+                text: change.value().to_string(),
+            }),
+        }
+    }
+    words
+}
+
+async fn latest_revision(pool: &DbPool, doc_id: &str) -> Result<i64, ServiceError> {
+    sqlx::query_scalar!(
+        r#"SELECT MAX(revision) AS "revision?: i64" FROM document_revisions WHERE document_id = ?"#,
+        doc_id
+    )
+    .fetch_one(pool)
+    .await?
+    .ok_or(ServiceError::BadRequest("Revision not found".into()))
+}
+
+/// Compares two revisions of a document.
+#[get("/documents/{id}/diff")]
+pub async fn diff_revisions(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    query: web::Query<DiffQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let (doc, _) = authorize(pool.get_ref(), &id, &user_id, Role::Viewer).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+
+    let to = match query.to {
+        Some(to) => to,
+        None => latest_revision(pool.get_ref(), &doc.id).await?,
+    };
+    let from = query.from.unwrap_or(to - 1).max(0);
+
+    let new = load_revision(pool.get_ref(), &doc.id, to).await?;
+    let (old_title, old_content) = if from == 0 {
+        (String::new(), None)
+    } else {
+        let old = load_revision(pool.get_ref(), &doc.id, from).await?;
+        (old.info.title, old.content)
+    };
+
+    let old_blocks = html_text::blocks(old_content.as_deref().unwrap_or(""));
+    let new_blocks = html_text::blocks(new.content.as_deref().unwrap_or(""));
+    let (blocks, stats) = diff_blocks(&old_blocks, &new_blocks);
+
+    let title = (old_title != new.info.title).then_some(TitleChange {
+        from: old_title,
+        to: new.info.title,
+    });
+
+    Ok(HttpResponse::Ok().json(RevisionDiff {
+        from,
+        to,
+        title,
+        blocks,
+        stats,
+    }))
+}
+
+/// Attributes each block of the current content to the revision, and so the
+/// author, that last changed it.
+#[get("/documents/{id}/blame")]
+pub async fn blame(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let (doc, _) = authorize(pool.get_ref(), &id, &user_id, Role::Viewer).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+
+    let revisions = sqlx::query!(
+        r#"
+        SELECT r.revision, r.content, r.author_id, u.username AS "author_name?", r.updated_at
+        FROM document_revisions r
+        LEFT JOIN users u ON u.id = r.author_id
+        WHERE r.document_id = ?
+        ORDER BY r.revision ASC
+        "#,
+        doc.id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    // Carry attributions forward: unchanged blocks keep theirs, replaced and
+    // inserted blocks take the revision that introduced them.
+    let mut blocks: Vec<BlameBlock> = Vec::new();
+    let mut latest = 0;
+    for revision in &revisions {
+        let texts = html_text::blocks(revision.content.as_deref().unwrap_or(""));
+        blocks = carry_forward(blocks, texts, |text| BlameBlock {
+            text,
+            revision: Some(revision.revision),
+            author_id: revision.author_id.clone(),
+            author_name: revision.author_name.clone(),
+            changed_at: Some(revision.updated_at),
+        });
+        latest = revision.revision;
+    }
+
+    // The content should match the latest revision, but anything changed
+    // outside the history is reported without an author.
+    let current = html_text::blocks(doc.content.as_deref().unwrap_or(""));
+    let blocks = carry_forward(blocks, current, |text| BlameBlock {
+        text,
+        revision: None,
+        author_id: None,
+        author_name: None,
+        changed_at: None,
+    });
+
+    Ok(HttpResponse::Ok().json(Blame {
+        revision: latest,
+        blocks,
+    }))
+}
+
+fn carry_forward(
+    previous: Vec<BlameBlock>,
+    texts: Vec<String>,
+    attribute: impl Fn(String) -> BlameBlock,
+) -> Vec<BlameBlock> {
+    let old_refs: Vec<&str> = previous.iter().map(|b| b.text.as_str()).collect();
+    let new_refs: Vec<&str> = texts.iter().map(String::as_str).collect();
+    let diff = TextDiff::configure()
+        .algorithm(Algorithm::Patience)
+        .diff_slices(&old_refs, &new_refs);
+
+    let mut kept: Vec<Option<usize>> = vec![None; texts.len()];
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            for (o, n) in old_range.zip(new_range) {
+                kept[n] = Some(o);
+            }
+        }
+    }
+
+    let mut previous: Vec<Option<BlameBlock>> = previous.into_iter().map(Some).collect();
+    texts
+        .into_iter()
+        .zip(kept)
+        .map(|(text, kept)| match kept.and_then(|o| previous[o].take()) {
+            Some(block) => block,
+            None => attribute(text),
+        })
+        .collect()
+}
@@ -1,13 +1,21 @@
-use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use actix_web::{delete, get, http::header, post, put, web, HttpRequest, HttpResponse};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
 use crate::{
+    api_tokens::Scope,
+    auth::get_user_id,
+    collab::{self, Rooms},
     db::DbPool,
     errors::ServiceError,
+    events::EventBus,
     models::{tag::Tag, Document, DocumentWithTags},
+    permissions::{self, authorize, Role},
+    revisions,
+    workspaces::{self, ActiveWorkspace},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,21 +33,131 @@ pub struct UpdateDocRequest {
     pub content: Option<String>,
     pub parent_id: Option<String>,
     pub tags: Option<Vec<String>>,
+    /// Shown in the revision history.
+    pub reason: Option<String>,
+    /// Set by editor autosaves, which may be squashed into one revision.
+    #[serde(default)]
+    pub autosave: bool,
 }
 
-fn get_user_id(req: &HttpRequest) -> Result<String, ServiceError> {
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .ok_or(ServiceError::Unauthorized("No token provided".into()))?
-        .to_str()
-        .map_err(|_| ServiceError::Unauthorized("Invalid token format".into()))?;
+/// A row of `permissions::VISIBLE_DOCUMENTS`.
+#[derive(sqlx::FromRow)]
+struct VisibleDocument {
+    #[sqlx(flatten)]
+    document: Document,
+    access: String,
+}
+
+/// Entity tag for a document version.
+pub fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
 
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or(ServiceError::Unauthorized("Invalid token format".into()))?;
+/// Versions accepted by the request's `If-Match` header, or `None` when
+/// there is no precondition (no header, or `*`). Weak tags never match.
+pub fn if_match(req: &HttpRequest) -> Option<Vec<i64>> {
+    let header = req.headers().get(header::IF_MATCH)?.to_str().unwrap_or("");
+    if header.trim() == "*" {
+        return None;
+    }
+    Some(
+        header
+            .split(',')
+            .filter_map(|tag| {
+                tag.trim()
+                    .strip_prefix('"')?
+                    .strip_suffix('"')?
+                    .parse()
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+/// Fails with 412 unless the request's `If-Match` allows `version`.
+pub fn check_if_match(req: &HttpRequest, version: i64) -> Result<(), ServiceError> {
+    match if_match(req) {
+        Some(versions) if !versions.contains(&version) => Err(ServiceError::PreconditionFailed(
+            "Document has been modified".into(),
+            version,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `parent_id` can hold `doc_id` (`None` for a new document):
+/// it has to be a live folder in the active workspace that the caller may
+/// add to, other than the document itself or anything below it.
+async fn check_parent(
+    pool: &DbPool,
+    active: &ActiveWorkspace,
+    user_id: &str,
+    parent_id: &str,
+    doc_id: Option<&str>,
+) -> Result<(), ServiceError> {
+    let invalid = |message: &str| {
+        ServiceError::ValidationError(BTreeMap::from([(
+            "parent_id".to_string(),
+            vec![message.to_string()],
+        )]))
+    };
+    if doc_id == Some(parent_id) {
+        return Err(invalid("A document can't be its own parent"));
+    }
 
-    crate::auth::validate_token(token)
+    let parent = query_as!(
+        Document,
+        "SELECT * FROM documents WHERE id = ? AND deleted_at IS NULL",
+        parent_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| invalid("Parent folder not found"))?;
+    if !parent.is_folder {
+        return Err(invalid("Parent must be a folder"));
+    }
+    if parent.workspace_id != active.id {
+        return Err(invalid("Parent folder is in another workspace"));
+    }
+    // In the personal space only the caller's own folders; in a workspace
+    // any folder they can edit.
+    let allowed = match parent.workspace_id {
+        None => parent.owner_id == user_id,
+        Some(_) => permissions::role_of(pool, &parent, user_id)
+            .await?
+            .is_some_and(|role| role >= Role::Editor),
+    };
+    if !allowed {
+        return Err(invalid("You can't add documents to this folder"));
+    }
+
+    if let Some(doc_id) = doc_id {
+        // Walks up from the new parent; finding the document there means the
+        // move would put it inside itself.
+        let cycle = sqlx::query_scalar!(
+            r#"
+            WITH RECURSIVE ancestors(id, parent_id, depth) AS (
+                SELECT id, parent_id, 0 FROM documents WHERE id = ?1
+                UNION ALL
+                SELECT d.id, d.parent_id, a.depth + 1
+                FROM documents d
+                JOIN ancestors a ON d.id = a.parent_id
+                WHERE a.depth < 100
+            )
+            SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = ?2) AS "cycle!: bool"
+            "#,
+            parent_id,
+            doc_id
+        )
+        .fetch_one(pool)
+        .await?;
+        if cycle {
+            return Err(invalid(
+                "A folder can't be moved into itself or one of its subfolders",
+            ));
+        }
+    }
+    Ok(())
 }
 
 #[get("/documents")]
@@ -47,13 +165,16 @@ pub async fn list_docs(
     pool: web::Data<DbPool>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&req)?;
-
-    let docs = query_as!(
-        Document,
-        "SELECT * FROM documents WHERE owner_id = ? AND deleted_at IS NULL ORDER BY is_folder DESC, title ASC",
-        user_id
-    )
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let active = workspaces::active(&req, pool.get_ref(), &user_id).await?;
+
+    let docs = sqlx::query_as::<_, VisibleDocument>(&format!(
+        "WITH RECURSIVE {} SELECT * FROM visible ORDER BY is_folder DESC, title ASC",
+        permissions::VISIBLE_DOCUMENTS
+    ))
+    .bind(&user_id)
+    .bind(&active.id)
+    .bind(active.role.as_str())
     .fetch_all(pool.get_ref())
     .await?;
 
@@ -63,7 +184,11 @@ pub async fn list_docs(
     // Let's iterate and map.
 
     let mut docs_with_tags = Vec::new();
-    for doc in docs {
+    for VisibleDocument {
+        document: doc,
+        access,
+    } in docs
+    {
         let tags = query_as!(
             Tag,
             r#"
@@ -77,10 +202,10 @@ pub async fn list_docs(
         )
         .fetch_all(pool.get_ref())
         .await?;
-
         docs_with_tags.push(DocumentWithTags {
             document: doc,
             tags,
+            access: Role::parse(&access).unwrap_or(Role::Viewer),
         });
     }
 
@@ -93,21 +218,11 @@ pub async fn get_doc(
     id: web::Path<String>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&req)?;
-    let doc_id = id.into_inner();
-
-    let doc = query_as!(
-        Document,
-        "SELECT * FROM documents WHERE id = ? AND deleted_at IS NULL",
-        doc_id
-    )
-    .fetch_optional(pool.get_ref())
-    .await?
-    .ok_or(ServiceError::BadRequest("Document not found".into()))?;
-
-    if doc.owner_id != user_id {
-        return Err(ServiceError::Forbidden("Permission denied".into()));
-    }
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let (doc, access) = authorize(pool.get_ref(), &id, &user_id, Role::Viewer).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
 
     let tags = query_as!(
         Tag,
@@ -123,32 +238,58 @@ pub async fn get_doc(
     .fetch_all(pool.get_ref())
     .await?;
 
-    Ok(HttpResponse::Ok().json(DocumentWithTags {
-        document: doc,
-        tags,
-    }))
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(doc.version)))
+        .json(DocumentWithTags {
+            document: doc,
+            tags,
+            access,
+        }))
 }
 
 #[post("/documents")]
 pub async fn create_doc(
     pool: web::Data<DbPool>,
+    bus: web::Data<EventBus>,
     req: web::Json<CreateDocRequest>,
     http_req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&http_req)?;
+    let user_id = get_user_id(&http_req, pool.get_ref(), Scope::DocsWrite).await?;
+    let active = workspaces::active(&http_req, pool.get_ref(), &user_id).await?;
+    if !active.can_create() {
+        return Err(ServiceError::Forbidden(
+            "Guests can't create documents".into(),
+        ));
+    }
+    if let Some(parent_id) = &req.parent_id {
+        check_parent(pool.get_ref(), &active, &user_id, parent_id, None).await?;
+    }
     let id = Uuid::new_v4().to_string();
 
+    let mut tx = pool.begin().await?;
     let _ = query!(
-        "INSERT INTO documents (id, title, content, parent_id, owner_id, is_folder) VALUES (?, ?, ?, ?, ?, ?)",
+        "INSERT INTO documents (id, title, content, parent_id, owner_id, is_folder, workspace_id) VALUES (?, ?, ?, ?, ?, ?, ?)",
         id,
         req.title,
         req.content,
         req.parent_id,
         user_id,
-        req.is_folder
+        req.is_folder,
+        active.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    revisions::record(
+        &mut tx,
+        &id,
+        &user_id,
+        &req.title,
+        req.content.as_deref(),
+        None,
+        false,
     )
-    .execute(pool.get_ref())
     .await?;
+    tx.commit().await?;
 
     if let Some(tags) = &req.tags {
         for tag_id in tags {
@@ -156,9 +297,10 @@ pub async fn create_doc(
             // But if we want to support creating tags by name on the fly, we need more logic.
             // Assuming IDs for now as per plan logic.
             let _ = query!(
-                "INSERT OR IGNORE INTO document_tags (document_id, tag_id) VALUES (?, ?)",
+                "INSERT OR IGNORE INTO document_tags (document_id, tag_id) SELECT ?, id FROM tags WHERE id = ? AND workspace_id IS ?",
                 id,
-                tag_id
+                tag_id,
+                active.id
             )
             .execute(pool.get_ref())
             .await;
@@ -183,53 +325,115 @@ pub async fn create_doc(
     .fetch_all(pool.get_ref())
     .await?;
 
+    bus.document(pool.get_ref(), "document.created", &doc).await;
+
     Ok(HttpResponse::Ok().json(DocumentWithTags {
         document: doc,
         tags,
+        access: Role::Owner,
     }))
 }
 
 #[put("/documents/{id}")]
 pub async fn update_doc(
     pool: web::Data<DbPool>,
+    rooms: web::Data<Rooms>,
+    bus: web::Data<EventBus>,
     id: web::Path<String>,
     req: web::Json<UpdateDocRequest>,
     http_req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&http_req)?;
+    let user_id = get_user_id(&http_req, pool.get_ref(), Scope::DocsWrite).await?;
     let doc_id = id.into_inner();
     let now = Utc::now().naive_utc();
 
-    let mut doc = query_as!(Document, "SELECT * FROM documents WHERE id = ?", doc_id)
-        .fetch_optional(pool.get_ref())
-        .await?
-        .ok_or(ServiceError::BadRequest("Document not found".into()))?;
-
-    if doc.owner_id != user_id {
-        return Err(ServiceError::Forbidden("Permission denied".into()));
-    }
+    let (mut doc, access) = authorize(pool.get_ref(), &doc_id, &user_id, Role::Editor).await?;
+    let active = workspaces::active(&http_req, pool.get_ref(), &user_id).await?;
+    active.check_document(&doc)?;
+    check_if_match(&http_req, doc.version)?;
+    let reason = revisions::validate_reason(req.reason.as_deref())?;
 
+    let mut changed = false;
     if let Some(title) = &req.title {
+        changed |= *title != doc.title;
         doc.title = title.clone();
     }
+    let mut content_changed = false;
     if let Some(content) = &req.content {
+        content_changed = doc.content.as_ref() != Some(content);
         doc.content = Some(content.clone());
     }
+    changed |= content_changed;
+    let mut moved = false;
     if let Some(parent_id) = &req.parent_id {
+        moved = doc.parent_id.as_ref() != Some(parent_id);
         doc.parent_id = Some(parent_id.clone());
     }
+    if moved && access < Role::Owner {
+        return Err(ServiceError::Forbidden(
+            "Only the owner can move a document".into(),
+        ));
+    }
+    if moved {
+        if let Some(parent_id) = &req.parent_id {
+            check_parent(pool.get_ref(), &active, &user_id, parent_id, Some(&doc_id)).await?;
+        }
+    }
+    // Moving out of a shared folder hides the document from that folder's
+    // grantees, who still need to hear about it.
+    let mut move_audience = if moved {
+        permissions::audience(pool.get_ref(), &doc).await?
+    } else {
+        Vec::new()
+    };
     doc.updated_at = now;
 
-    let _ = query!(
-        "UPDATE documents SET title = ?, content = ?, parent_id = ?, updated_at = ? WHERE id = ?",
+    // With If-Match, the update only applies to the version checked above, so
+    // a concurrent save in between still fails.
+    let expected_version = if_match(&http_req).map(|_| doc.version);
+
+    let mut tx = pool.begin().await?;
+    let updated = query!(
+        r#"
+        UPDATE documents SET title = ?, content = ?, parent_id = ?, updated_at = ?, version = version + 1
+        WHERE id = ? AND (?6 IS NULL OR version = ?6)
+        "#,
         doc.title,
         doc.content,
         doc.parent_id,
         doc.updated_at,
-        doc_id
+        doc_id,
+        expected_version
     )
-    .execute(pool.get_ref())
+    .execute(&mut *tx)
     .await?;
+    if updated.rows_affected() == 0 {
+        let current = sqlx::query_scalar!("SELECT version FROM documents WHERE id = ?", doc_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        return Err(ServiceError::PreconditionFailed(
+            "Document has been modified".into(),
+            current,
+        ));
+    }
+    // Moves and tag changes aren't part of the revision history.
+    if changed {
+        revisions::record(
+            &mut tx,
+            &doc_id,
+            &user_id,
+            &doc.title,
+            doc.content.as_deref(),
+            reason.as_deref(),
+            req.autosave,
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    // An open collaborative session would write its own content back.
+    if content_changed {
+        collab::discard_state(pool.get_ref(), &rooms, &doc_id).await?;
+    }
 
     if let Some(tags) = &req.tags {
         // Replace all tags.
@@ -241,9 +445,10 @@ pub async fn update_doc(
         // 2. Insert new
         for tag_id in tags {
             let _ = query!(
-                "INSERT OR IGNORE INTO document_tags (document_id, tag_id) VALUES (?, ?)",
+                "INSERT OR IGNORE INTO document_tags (document_id, tag_id) SELECT ?, id FROM tags WHERE id = ? AND workspace_id IS ?",
                 doc_id,
-                tag_id
+                tag_id,
+                active.id
             )
             .execute(pool.get_ref())
             .await;
@@ -269,29 +474,40 @@ pub async fn update_doc(
     .fetch_all(pool.get_ref())
     .await?;
 
-    Ok(HttpResponse::Ok().json(DocumentWithTags {
-        document: doc,
-        tags,
-    }))
+    if moved {
+        move_audience.extend(permissions::audience(pool.get_ref(), &doc).await?);
+        move_audience.sort();
+        move_audience.dedup();
+        bus.document_to("document.moved", &doc, move_audience);
+    }
+    // A plain move is reported only as such.
+    if changed || req.tags.is_some() || !moved {
+        bus.document(pool.get_ref(), "document.updated", &doc).await;
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(doc.version)))
+        .json(DocumentWithTags {
+            document: doc,
+            tags,
+            access,
+        }))
 }
 
 #[delete("/documents/{id}")]
 pub async fn delete_doc(
     pool: web::Data<DbPool>,
+    bus: web::Data<EventBus>,
     id: web::Path<String>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&req)?;
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
     let doc_id = id.into_inner();
 
-    let doc = query_as!(Document, "SELECT * FROM documents WHERE id = ?", doc_id)
-        .fetch_optional(pool.get_ref())
+    let (doc, _) = authorize(pool.get_ref(), &doc_id, &user_id, Role::Owner).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
         .await?
-        .ok_or(ServiceError::BadRequest("Document not found".into()))?;
-
-    if doc.owner_id != user_id {
-        return Err(ServiceError::Forbidden("Permission denied".into()));
-    }
+        .check_document(&doc)?;
 
     let result = query!(
         "UPDATE documents SET deleted_at = CURRENT_TIMESTAMP WHERE id = ?",
@@ -304,5 +520,7 @@ pub async fn delete_doc(
         return Err(ServiceError::BadRequest("Document not found".into()));
     }
 
+    bus.document(pool.get_ref(), "document.trashed", &doc).await;
+
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Document moved to trash"})))
 }
@@ -1,38 +1,38 @@
 use crate::{
+    api_tokens::Scope,
+    audit::{self, Event},
+    auth::get_user_id,
     db::DbPool,
     errors::ServiceError,
+    events::EventBus,
     models::{tag::Tag, Document, DocumentWithTags},
+    permissions::{self, Role},
+    workspaces::{self, WorkspaceRole},
 };
 use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
 use sqlx::query;
 use sqlx::query_as;
 
-fn get_user_id(req: &HttpRequest) -> Result<String, ServiceError> {
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .ok_or(ServiceError::Unauthorized("No token provided".into()))?
-        .to_str()
-        .map_err(|_| ServiceError::Unauthorized("Invalid token format".into()))?;
-
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or(ServiceError::Unauthorized("Invalid token format".into()))?;
-
-    crate::auth::validate_token(token)
-}
-
 #[get("/trash")]
 pub async fn get_trash(
     pool: web::Data<DbPool>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&req)?;
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let active = workspaces::active(&req, pool.get_ref(), &user_id).await?;
+    // Workspace owners and admins manage everyone's trash.
+    let sees_all = active.id.is_some() && active.role >= WorkspaceRole::Admin;
 
     let docs = query_as!(
         Document,
-        "SELECT * FROM documents WHERE owner_id = ? AND deleted_at IS NOT NULL ORDER BY deleted_at DESC",
-        user_id
+        r#"
+        SELECT * FROM documents
+        WHERE deleted_at IS NOT NULL AND workspace_id IS ?2 AND (owner_id = ?1 OR ?3)
+        ORDER BY deleted_at DESC
+        "#,
+        user_id,
+        active.id,
+        sees_all
     )
     .fetch_all(pool.get_ref())
     .await?;
@@ -56,6 +56,7 @@ pub async fn get_trash(
         docs_with_tags.push(DocumentWithTags {
             document: doc,
             tags,
+            access: Role::Owner,
         });
     }
 
@@ -65,10 +66,11 @@ pub async fn get_trash(
 #[post("/documents/{id}/restore")]
 pub async fn restore_doc(
     pool: web::Data<DbPool>,
+    bus: web::Data<EventBus>,
     id: web::Path<String>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&req)?;
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
     let doc_id = id.into_inner();
 
     let doc = query_as!(Document, "SELECT * FROM documents WHERE id = ?", doc_id)
@@ -76,9 +78,12 @@ pub async fn restore_doc(
         .await?
         .ok_or(ServiceError::BadRequest("Document not found".into()))?;
 
-    if doc.owner_id != user_id {
+    if permissions::role_of(pool.get_ref(), &doc, &user_id).await? != Some(Role::Owner) {
         return Err(ServiceError::Forbidden("Permission denied".into()));
     }
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
 
     let _ = query!(
         "UPDATE documents SET deleted_at = NULL WHERE id = ?",
@@ -87,16 +92,20 @@ pub async fn restore_doc(
     .execute(pool.get_ref())
     .await?;
 
+    bus.document(pool.get_ref(), "document.restored", &doc)
+        .await;
+
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Document restored"})))
 }
 
 #[delete("/documents/{id}/permanent")]
 pub async fn delete_doc_permanent(
     pool: web::Data<DbPool>,
+    bus: web::Data<EventBus>,
     id: web::Path<String>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&req)?;
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
     let doc_id = id.into_inner();
 
     let doc = query_as!(Document, "SELECT * FROM documents WHERE id = ?", doc_id)
@@ -104,13 +113,29 @@ pub async fn delete_doc_permanent(
         .await?
         .ok_or(ServiceError::BadRequest("Document not found".into()))?;
 
-    if doc.owner_id != user_id {
+    if permissions::role_of(pool.get_ref(), &doc, &user_id).await? != Some(Role::Owner) {
         return Err(ServiceError::Forbidden("Permission denied".into()));
     }
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+    // The grants go with the document.
+    let audience = permissions::audience(pool.get_ref(), &doc).await?;
 
     let _ = query!("DELETE FROM documents WHERE id = ?", doc_id)
         .execute(pool.get_ref())
         .await?;
 
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("document.delete_permanent")
+            .actor(&user_id)
+            .target("document", &doc_id)
+            .details(serde_json::json!({"title": doc.title, "is_folder": doc.is_folder})),
+    )
+    .await;
+    bus.document_purged(&doc, audience);
+
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Document permanently deleted"})))
 }
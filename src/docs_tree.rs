@@ -0,0 +1,156 @@
+use crate::{
+    api_tokens::Scope,
+    auth::get_user_id,
+    db::DbPool,
+    errors::ServiceError,
+    permissions::{self, authorize, Role},
+    workspaces,
+};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Deepest nesting returned, which also stops the query on a cycle left in
+/// older data.
+const MAX_TREE_DEPTH: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct TreeQuery {
+    /// Folder whose contents form the top level; the whole space by default.
+    pub root: Option<String>,
+    /// Levels to return, the top level being 1; all by default.
+    pub depth: Option<i64>,
+}
+
+#[derive(sqlx::FromRow)]
+struct TreeRow {
+    id: String,
+    title: String,
+    parent_id: Option<String>,
+    is_folder: bool,
+    owner_id: String,
+    updated_at: NaiveDateTime,
+    child_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TreeNode {
+    pub id: String,
+    pub title: String,
+    pub parent_id: Option<String>,
+    pub is_folder: bool,
+    pub owner_id: String,
+    pub updated_at: NaiveDateTime,
+    /// All visible children, including those cut off by `depth`.
+    pub child_count: i64,
+    pub children: Vec<TreeNode>,
+}
+
+/// The documents of the active workspace the caller can see, nested by
+/// folder. Without a root the top level holds everything whose parent isn't
+/// visible, like a folder shared on its own.
+#[get("/documents/tree")]
+pub async fn get_tree(
+    pool: web::Data<DbPool>,
+    query: web::Query<TreeQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let active = workspaces::active(&req, pool.get_ref(), &user_id).await?;
+
+    let depth = match query.depth {
+        Some(depth) if depth < 1 => {
+            return Err(ServiceError::ValidationError(BTreeMap::from([(
+                "depth".to_string(),
+                vec!["Depth must be at least 1".to_string()],
+            )])));
+        }
+        Some(depth) => depth.min(MAX_TREE_DEPTH),
+        None => MAX_TREE_DEPTH,
+    };
+    if let Some(root) = &query.root {
+        let (doc, _) = authorize(pool.get_ref(), root, &user_id, Role::Viewer).await?;
+        active.check_document(&doc)?;
+        if !doc.is_folder {
+            return Err(ServiceError::ValidationError(BTreeMap::from([(
+                "root".to_string(),
+                vec!["Root must be a folder".to_string()],
+            )])));
+        }
+    }
+
+    // `visible` is what `docs::list_docs` returns; `tree` walks down from
+    // the top level, one level per step.
+    let rows = sqlx::query_as::<_, TreeRow>(&format!(
+        r#"
+        WITH RECURSIVE {},
+        tree(id, depth) AS (
+            SELECT id, 1 FROM visible
+            WHERE CASE WHEN ?4 IS NULL
+                THEN parent_id IS NULL OR parent_id NOT IN (SELECT id FROM visible)
+                ELSE parent_id = ?4
+            END
+            UNION
+            SELECT v.id, t.depth + 1
+            FROM visible v
+            JOIN tree t ON v.parent_id = t.id
+            WHERE t.depth < ?5
+        )
+        SELECT v.id, v.title, v.parent_id, v.is_folder, v.owner_id, v.updated_at,
+            (SELECT COUNT(*) FROM visible c WHERE c.parent_id = v.id) AS child_count
+        FROM visible v
+        WHERE v.id IN (SELECT id FROM tree)
+        ORDER BY v.is_folder DESC, v.title ASC
+        "#,
+        permissions::VISIBLE_DOCUMENTS
+    ))
+    .bind(&user_id)
+    .bind(&active.id)
+    .bind(active.role.as_str())
+    .bind(&query.root)
+    .bind(depth)
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(build_tree(rows)))
+}
+
+/// Nests the rows under their parents, keeping their order. Rows whose
+/// parent wasn't returned form the top level.
+fn build_tree(rows: Vec<TreeRow>) -> Vec<TreeNode> {
+    let ids: HashSet<String> = rows.iter().map(|r| r.id.clone()).collect();
+    let mut top = Vec::new();
+    let mut children: HashMap<String, Vec<TreeRow>> = HashMap::new();
+    for row in rows {
+        match &row.parent_id {
+            Some(parent) if ids.contains(parent) => {
+                children.entry(parent.clone()).or_default().push(row)
+            }
+            _ => top.push(row),
+        }
+    }
+    top.into_iter()
+        .map(|row| attach(row, &mut children))
+        .collect()
+}
+
+fn attach(row: TreeRow, children: &mut HashMap<String, Vec<TreeRow>>) -> TreeNode {
+    // Taking the children out means a cycle in the data can't recurse forever.
+    let nested = children
+        .remove(&row.id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|child| attach(child, children))
+        .collect();
+    TreeNode {
+        id: row.id,
+        title: row.title,
+        parent_id: row.parent_id,
+        is_folder: row.is_folder,
+        owner_id: row.owner_id,
+        updated_at: row.updated_at,
+        child_count: row.child_count,
+        children: nested,
+    }
+}
@@ -1,6 +1,7 @@
 use actix_web::{HttpResponse, ResponseError};
 use derive_more::Display;
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 #[derive(Debug, Display)]
 pub enum ServiceError {
@@ -13,6 +14,15 @@ pub enum ServiceError {
     Unauthorized(String),
     #[display("Forbidden: {}", _0)]
     Forbidden(String),
+    /// Request was throttled; carries the number of seconds until a retry may succeed.
+    #[display("TooManyRequests: {}", _0)]
+    TooManyRequests(String, i64),
+    /// `If-Match` didn't match; carries the current version of the resource.
+    #[display("PreconditionFailed: {}", _0)]
+    PreconditionFailed(String, i64),
+    /// Request body failed validation; maps field names to their problems.
+    #[display("ValidationError: {:?}", _0)]
+    ValidationError(BTreeMap<String, Vec<String>>),
 }
 
 #[derive(Debug, Serialize)]
@@ -20,6 +30,18 @@ struct ErrorResponse {
     error: String,
 }
 
+#[derive(Debug, Serialize)]
+struct PreconditionFailedResponse {
+    error: String,
+    current_version: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct ValidationErrorResponse<'a> {
+    error: String,
+    fields: &'a BTreeMap<String, Vec<String>>,
+}
+
 impl ResponseError for ServiceError {
     fn error_response(&self) -> HttpResponse {
         match self {
@@ -41,6 +63,27 @@ impl ResponseError for ServiceError {
             ServiceError::Forbidden(ref message) => HttpResponse::Forbidden().json(ErrorResponse {
                 error: message.into(),
             }),
+            ServiceError::TooManyRequests(ref message, ref retry_after) => {
+                HttpResponse::TooManyRequests()
+                    .insert_header(("Retry-After", (*retry_after).max(1).to_string()))
+                    .json(ErrorResponse {
+                        error: message.into(),
+                    })
+            }
+            ServiceError::PreconditionFailed(ref message, ref current_version) => {
+                HttpResponse::PreconditionFailed()
+                    .insert_header(("ETag", format!("\"{}\"", current_version)))
+                    .json(PreconditionFailedResponse {
+                        error: message.into(),
+                        current_version: *current_version,
+                    })
+            }
+            ServiceError::ValidationError(ref fields) => {
+                HttpResponse::BadRequest().json(ValidationErrorResponse {
+                    error: "Validation failed".into(),
+                    fields,
+                })
+            }
         }
     }
 }
@@ -0,0 +1,339 @@
+//! Live change notifications over Server-Sent Events.
+//!
+//! Handlers publish document and tag changes to the in-process `EventBus`;
+//! `GET /events` streams the ones a user may see. The most recent events are
+//! kept in a bounded log so a reconnecting client can resume from its
+//! `Last-Event-ID`. Event ids start with a per-process epoch: after a restart,
+//! or when the requested id has already left the log, the client gets a
+//! `reset` event and should reload instead.
+
+use actix_web::{get, web, web::Bytes, HttpRequest, HttpResponse};
+use chrono::{NaiveDateTime, Utc};
+use futures::{channel::mpsc, future, stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Arc, sync::Mutex, time::Duration};
+
+use crate::{
+    api_tokens::Scope,
+    auth::{get_user_id, token_user_id},
+    db::DbPool,
+    errors::ServiceError,
+    models::{tag::Tag, Document},
+    permissions, workspaces,
+};
+
+/// Events queued for a client that isn't reading; a client falling further
+/// behind is disconnected and resumes from the log.
+const SUBSCRIBER_BUFFER: usize = 64;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of events kept for resuming (`EVENT_LOG_SIZE`, default 1000).
+fn log_size() -> usize {
+    std::env::var("EVENT_LOG_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1000)
+}
+
+/// What clients need to update a document listing; the content is left out.
+#[derive(Debug, Serialize)]
+pub struct DocumentSummary {
+    pub id: String,
+    pub title: String,
+    pub parent_id: Option<String>,
+    pub is_folder: bool,
+    pub version: i64,
+    pub updated_at: NaiveDateTime,
+}
+
+impl From<&Document> for DocumentSummary {
+    fn from(doc: &Document) -> Self {
+        DocumentSummary {
+            id: doc.id.clone(),
+            title: doc.title.clone(),
+            parent_id: doc.parent_id.clone(),
+            is_folder: doc.is_folder,
+            version: doc.version,
+            updated_at: doc.updated_at,
+        }
+    }
+}
+
+struct Entry {
+    seq: u64,
+    kind: &'static str,
+    data: String,
+    /// Users the event is for; `None` for everyone.
+    recipients: Option<Vec<String>>,
+}
+
+impl Entry {
+    fn visible_to(&self, user_id: &str) -> bool {
+        match &self.recipients {
+            Some(recipients) => recipients.iter().any(|id| id == user_id),
+            None => true,
+        }
+    }
+}
+
+struct Subscriber {
+    user_id: String,
+    sender: mpsc::Sender<Arc<Entry>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_seq: u64,
+    log: VecDeque<Arc<Entry>>,
+    subscribers: Vec<Subscriber>,
+}
+
+/// In-process broadcast of changes to connected clients.
+pub struct EventBus {
+    epoch: i64,
+    log_size: usize,
+    inner: Mutex<Inner>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus {
+            epoch: Utc::now().timestamp_millis(),
+            log_size: log_size(),
+            inner: Mutex::new(Inner {
+                next_seq: 1,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// Where a subscription starts.
+enum Resume {
+    /// Only new events.
+    Live,
+    /// The events after this sequence number, followed by new ones.
+    After(u64),
+    /// The requested events are gone; the client has to reload.
+    Reset,
+}
+
+impl EventBus {
+    fn publish(&self, kind: &'static str, data: String, recipients: Option<Vec<String>>) {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = Arc::new(Entry {
+            seq: inner.next_seq,
+            kind,
+            data,
+            recipients,
+        });
+        inner.next_seq += 1;
+
+        if self.log_size > 0 {
+            if inner.log.len() >= self.log_size {
+                inner.log.pop_front();
+            }
+            inner.log.push_back(entry.clone());
+        }
+        // A full or closed channel drops the subscriber. Closed ones go on
+        // every publish, not just those meant for them, so a user who hardly
+        // gets events doesn't leave their dropped streams behind.
+        inner.subscribers.retain_mut(|s| {
+            !s.sender.is_closed()
+                && (!entry.visible_to(&s.user_id) || s.sender.try_send(entry.clone()).is_ok())
+        });
+    }
+
+    /// Publishes a change to a document to everyone who can see it.
+    pub async fn document(&self, pool: &DbPool, kind: &'static str, doc: &Document) {
+        let recipients = match permissions::audience(pool, doc).await {
+            Ok(recipients) => recipients,
+            Err(e) => {
+                eprintln!("Failed to look up who can see {}: {:?}", doc.id, e);
+                vec![doc.owner_id.clone()]
+            }
+        };
+        self.document_to(kind, doc, recipients);
+    }
+
+    /// Publishes a change to a document to the given users, for changes
+    /// that also affect who can see it.
+    pub fn document_to(&self, kind: &'static str, doc: &Document, recipients: Vec<String>) {
+        let data = serde_json::to_string(&DocumentSummary::from(doc)).unwrap_or_default();
+        self.publish(kind, data, Some(recipients));
+    }
+
+    /// Publishes the permanent deletion of a document, of which only the id
+    /// is left.
+    pub fn document_purged(&self, doc: &Document, recipients: Vec<String>) {
+        let data = serde_json::json!({ "id": doc.id }).to_string();
+        self.publish("document.purged", data, Some(recipients));
+    }
+
+    /// Tells a user their access to a document was granted, changed or
+    /// revoked.
+    pub fn access_changed(&self, doc: &Document, user_id: &str) {
+        self.document_to("document.access_changed", doc, vec![user_id.to_string()]);
+    }
+
+    /// Publishes a change to a tag to the members of its workspace. Tags
+    /// outside workspaces are shared, so everyone gets those.
+    pub async fn tag(
+        &self,
+        pool: &DbPool,
+        kind: &'static str,
+        tag: &Tag,
+        workspace_id: Option<&str>,
+    ) {
+        let recipients = match workspace_id {
+            Some(workspace_id) => match workspaces::members(pool, workspace_id, true).await {
+                Ok(members) => Some(members),
+                Err(e) => {
+                    eprintln!("Failed to look up members of {}: {:?}", workspace_id, e);
+                    return;
+                }
+            },
+            None => None,
+        };
+        let data = serde_json::to_string(tag).unwrap_or_default();
+        self.publish(kind, data, recipients);
+    }
+
+    fn parse_last_id(&self, last_id: Option<&str>) -> Resume {
+        let Some(last_id) = last_id else {
+            return Resume::Live;
+        };
+        let parsed = last_id
+            .split_once('-')
+            .and_then(|(epoch, seq)| Some((epoch.parse::<i64>().ok()?, seq.parse::<u64>().ok()?)));
+        match parsed {
+            Some((epoch, seq)) if epoch == self.epoch => Resume::After(seq),
+            _ => Resume::Reset,
+        }
+    }
+
+    /// Registers a client, returning the events it missed and the receiver
+    /// for new ones. Both happen under one lock so nothing falls in between.
+    fn subscribe(
+        &self,
+        user_id: &str,
+        last_id: Option<&str>,
+    ) -> (Vec<String>, mpsc::Receiver<Arc<Entry>>) {
+        let resume = self.parse_last_id(last_id);
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+
+        let mut inner = self.inner.lock().unwrap();
+        let backlog = match resume {
+            Resume::Live => Vec::new(),
+            Resume::After(seq) => {
+                let oldest = inner.log.front().map_or(inner.next_seq, |e| e.seq);
+                if seq + 1 < oldest || seq >= inner.next_seq {
+                    vec![self.reset_frame(inner.next_seq - 1)]
+                } else {
+                    inner
+                        .log
+                        .iter()
+                        .filter(|e| e.seq > seq && e.visible_to(user_id))
+                        .map(|e| self.frame(e))
+                        .collect()
+                }
+            }
+            Resume::Reset => vec![self.reset_frame(inner.next_seq - 1)],
+        };
+        inner.subscribers.push(Subscriber {
+            user_id: user_id.to_string(),
+            sender,
+        });
+        (backlog, receiver)
+    }
+
+    fn frame(&self, entry: &Entry) -> String {
+        format!(
+            "id: {}-{}\nevent: {}\ndata: {}\n\n",
+            self.epoch, entry.seq, entry.kind, entry.data
+        )
+    }
+
+    /// Tells the client to reload. Carries the current position as its id so
+    /// the next reconnect resumes from there.
+    fn reset_frame(&self, seq: u64) -> String {
+        format!("id: {}-{}\nevent: reset\ndata: {{}}\n\n", self.epoch, seq)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// `EventSource` can't set headers, so the access token may be passed
+    /// here instead; the session cookie works as well.
+    pub token: Option<String>,
+    /// Resume point for the first connection; reconnects send the
+    /// `Last-Event-ID` header, which takes precedence.
+    pub last_event_id: Option<String>,
+}
+
+/// Streams changes to the documents the caller can see and to tags.
+#[get("/events")]
+pub async fn stream_events(
+    pool: web::Data<DbPool>,
+    bus: web::Data<EventBus>,
+    query: web::Query<EventsQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = match &query.token {
+        Some(token) => token_user_id(pool.get_ref(), token, Scope::DocsRead).await?,
+        None => get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?,
+    };
+    let last_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .or(query.last_event_id.as_deref());
+
+    let (backlog, receiver) = bus.subscribe(&user_id, last_id);
+
+    let bus = bus.into_inner();
+    // Ends with `None` when the bus drops the subscriber, which closes the
+    // response so the client reconnects.
+    let live = receiver
+        .map(move |entry| Some(bus.frame(&entry)))
+        .chain(stream::once(future::ready(None)));
+    let keepalive = stream::unfold(
+        actix_web::rt::time::interval(KEEPALIVE_INTERVAL),
+        |mut interval| async move {
+            interval.tick().await;
+            Some((Some(": keepalive\n\n".to_string()), interval))
+        },
+    );
+    let body = stream::iter(backlog)
+        .chain(
+            stream::select(live, keepalive)
+                .take_while(|frame| future::ready(frame.is_some()))
+                .filter_map(future::ready),
+        )
+        .map(|frame| Ok::<_, std::convert::Infallible>(Bytes::from(frame)));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publishing_drops_closed_subscribers() {
+        let bus = EventBus::default();
+        let (_, gone) = bus.subscribe("alice", None);
+        let (_, mut listening) = bus.subscribe("bob", None);
+        drop(gone);
+
+        bus.publish("document.updated", "{}".into(), Some(vec!["bob".into()]));
+
+        assert_eq!(bus.inner.lock().unwrap().subscribers.len(), 1);
+        assert_eq!(listening.try_recv().unwrap().seq, 1);
+    }
+}
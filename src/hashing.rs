@@ -0,0 +1,122 @@
+//! Argon2id parameters for password hashes.
+//!
+//! The cost is read from the environment at startup. Hashes made with weaker
+//! settings keep verifying, since a PHC string carries its own parameters,
+//! and are upgraded on the next successful password login.
+
+use argon2::{password_hash::PasswordHash, Algorithm, Argon2, Params, Version};
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use crate::db::DbPool;
+
+static PARAMS: OnceLock<Params> = OnceLock::new();
+
+fn env_u32(name: &str, default: u32) -> Result<u32, String> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| format!("{} must be a positive integer", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Memory cost in KiB (`ARGON2_MEMORY_KIB`, default 19456), iterations
+/// (`ARGON2_ITERATIONS`, default 2) and lanes (`ARGON2_PARALLELISM`, default 1).
+fn params_from_env() -> Result<Params, String> {
+    let m_cost = env_u32("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST)?;
+    let t_cost = env_u32("ARGON2_ITERATIONS", Params::DEFAULT_T_COST)?;
+    let p_cost = env_u32("ARGON2_PARALLELISM", Params::DEFAULT_P_COST)?;
+    Params::new(m_cost, t_cost, p_cost, None).map_err(|e| format!("Argon2 parameters: {}", e))
+}
+
+/// Reads the parameters from the environment. Called once at startup so that
+/// a misconfiguration stops the server instead of failing every login.
+pub fn init() -> Result<(), String> {
+    let params = params_from_env()?;
+    PARAMS
+        .set(params)
+        .map_err(|_| "Argon2 parameters already initialised".to_string())
+}
+
+fn params() -> &'static Params {
+    PARAMS.get().expect("hashing::init must run at startup")
+}
+
+/// Hasher with the configured parameters.
+pub fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params().clone())
+}
+
+/// Whether `hash` was made with a different algorithm or any cost lower than
+/// the configured one.
+pub fn needs_rehash(hash: &PasswordHash) -> bool {
+    let current = params();
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(hash) {
+        Ok(p) => {
+            p.m_cost() < current.m_cost()
+                || p.t_cost() < current.t_cost()
+                || p.p_cost() < current.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+/// Describes the parameters of a stored hash, e.g. `argon2id v19 m=19456 t=2 p=1`.
+fn describe(hash: &PasswordHash) -> String {
+    let version = hash.version.map(|v| format!(" v{}", v)).unwrap_or_default();
+    match Params::try_from(hash) {
+        Ok(p) => format!(
+            "{}{} m={} t={} p={}",
+            hash.algorithm,
+            version,
+            p.m_cost(),
+            p.t_cost(),
+            p.p_cost()
+        ),
+        Err(_) => format!("{}{}", hash.algorithm, version),
+    }
+}
+
+/// `actix-doc password-hash-report`: prints how many users have each kind of
+/// password hash and how many are still below the configured parameters.
+pub async fn report(pool: &DbPool) -> Result<(), sqlx::Error> {
+    let hashes = sqlx::query_scalar!("SELECT password_hash FROM users")
+        .fetch_all(pool)
+        .await?;
+
+    let mut by_params: BTreeMap<String, (usize, bool)> = BTreeMap::new();
+    let mut outdated = 0;
+    for stored in &hashes {
+        let (label, old) = match PasswordHash::new(stored) {
+            Ok(hash) => (describe(&hash), needs_rehash(&hash)),
+            Err(_) => ("unparseable".to_string(), true),
+        };
+        if old {
+            outdated += 1;
+        }
+        let entry = by_params.entry(label).or_insert((0, old));
+        entry.0 += 1;
+    }
+
+    let current = params();
+    println!(
+        "Configured: argon2id v19 m={} t={} p={}",
+        current.m_cost(),
+        current.t_cost(),
+        current.p_cost()
+    );
+    for (label, (count, old)) in &by_params {
+        let marker = if *old { "  (outdated)" } else { "" };
+        println!("{:>8}  {}{}", count, label, marker);
+    }
+    println!(
+        "{} of {} users will be rehashed on their next password login",
+        outdated,
+        hashes.len()
+    );
+    Ok(())
+}
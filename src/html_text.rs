@@ -0,0 +1,162 @@
+//! Plain text extraction from the HTML stored in `documents.content`.
+//!
+//! The editor produces simple, well-formed markup, so a small scanner is
+//! enough: block-level tags start a new block, other tags are dropped and
+//! whitespace inside a block is collapsed.
+
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+/// Elements whose content is never text.
+const SKIPPED_TAGS: &[&str] = &["script", "style", "template"];
+
+/// Splits HTML into the text of its blocks (paragraphs, headings, list
+/// items, ...), skipping empty ones.
+pub fn blocks(html: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current = String::new();
+    let mut skipping: Option<String> = None;
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if skipping.is_none() {
+            push_text(&mut current, &rest[..start]);
+        }
+        let Some(end) = rest[start..].find('>') else {
+            // An unterminated tag is treated as text.
+            if skipping.is_none() {
+                push_text(&mut current, &rest[start..]);
+            }
+            rest = "";
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        if let Some(skipped) = &skipping {
+            if closing && name == *skipped {
+                skipping = None;
+            }
+            continue;
+        }
+        if !closing && SKIPPED_TAGS.contains(&name.as_str()) {
+            skipping = Some(name);
+            continue;
+        }
+        if BLOCK_TAGS.contains(&name.as_str()) {
+            flush(&mut blocks, &mut current);
+        }
+    }
+    if skipping.is_none() {
+        push_text(&mut current, rest);
+    }
+    flush(&mut blocks, &mut current);
+    blocks
+}
+
+fn flush(blocks: &mut Vec<String>, current: &mut String) {
+    let block = current.trim();
+    if !block.is_empty() {
+        blocks.push(block.to_string());
+    }
+    current.clear();
+}
+
+/// Appends decoded text, collapsing runs of whitespace to one space.
+fn push_text(out: &mut String, raw: &str) {
+    for c in decode_entities(raw).chars() {
+        if c.is_whitespace() {
+            if !out.is_empty() && !out.ends_with(' ') {
+                out.push(' ');
+            }
+        } else {
+            out.push(c);
+        }
+    }
+}
+
+/// Decodes character references; unknown ones are left as they are.
+pub fn decode_entities(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(hex) = entity
+        .strip_prefix("#x")
+        .or_else(|| entity.strip_prefix("#X"))
+    {
+        return u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+    }
+    if let Some(decimal) = entity.strip_prefix('#') {
+        return decimal.parse().ok().and_then(char::from_u32);
+    }
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        _ => return None,
+    })
+}
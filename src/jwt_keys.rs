@@ -0,0 +1,575 @@
+use actix_web::{get, HttpResponse};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode, jwk::Jwk, Algorithm, DecodingKey, EncodingKey, Header,
+    Validation,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+use crate::errors::ServiceError;
+
+const DEV_FALLBACK_SECRET: &str = "dev_fallback_secret_key_change_me";
+
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+static KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+/// Keys used to sign and verify access tokens.
+///
+/// Configuration:
+///
+/// - `JWT_PRIVATE_KEY_FILE`: PEM private key (RSA for RS256, Ed25519 for
+///   EdDSA) that signs new tokens
+/// - `JWT_KEY_ID`: `kid` of that key, defaulting to its RFC 7638 thumbprint
+/// - `JWT_PUBLIC_KEY_FILES`: comma-separated PEM keys that are still accepted
+///   for verification, as `path` or `kid=path`; used while rotating keys
+/// - `JWT_SECRET`: legacy HS256 secret, only used without a private key
+/// - `JWT_ISSUER`, `JWT_AUDIENCE`: `iss` and `aud` put in tokens and required
+///   when verifying them (both default to `actix-doc`)
+///
+/// With `APP_ENV=production` a private key is mandatory; otherwise a fixed
+/// development secret is used when nothing is configured.
+pub struct JwtKeys {
+    signing_kid: Option<String>,
+    signing_alg: Algorithm,
+    signing_key: EncodingKey,
+    verifying: Vec<VerifyingKey>,
+    issuer: String,
+    audience: String,
+}
+
+struct VerifyingKey {
+    kid: Option<String>,
+    alg: Algorithm,
+    key: DecodingKey,
+    /// Public JWK, absent for the symmetric development key.
+    jwk: Option<serde_json::Value>,
+}
+
+enum PublicKeyMaterial {
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+    Ed25519 { x: Vec<u8> },
+}
+
+impl PublicKeyMaterial {
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            PublicKeyMaterial::Rsa { .. } => Algorithm::RS256,
+            PublicKeyMaterial::Ed25519 { .. } => Algorithm::EdDSA,
+        }
+    }
+
+    /// RFC 7638 thumbprint: the hash of the required members in lexical order.
+    fn thumbprint(&self) -> String {
+        let canonical = match self {
+            PublicKeyMaterial::Rsa { n, e } => format!(
+                r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+                URL_SAFE_NO_PAD.encode(e),
+                URL_SAFE_NO_PAD.encode(n)
+            ),
+            PublicKeyMaterial::Ed25519 { x } => format!(
+                r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#,
+                URL_SAFE_NO_PAD.encode(x)
+            ),
+        };
+        URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+    }
+
+    fn to_jwk(&self, kid: &str) -> serde_json::Value {
+        match self {
+            PublicKeyMaterial::Rsa { n, e } => serde_json::json!({
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": kid,
+                "n": URL_SAFE_NO_PAD.encode(n),
+                "e": URL_SAFE_NO_PAD.encode(e),
+            }),
+            PublicKeyMaterial::Ed25519 { x } => serde_json::json!({
+                "kty": "OKP",
+                "use": "sig",
+                "alg": "EdDSA",
+                "crv": "Ed25519",
+                "kid": kid,
+                "x": URL_SAFE_NO_PAD.encode(x),
+            }),
+        }
+    }
+
+    fn verifying_key(&self, kid: Option<String>) -> Result<VerifyingKey, String> {
+        let kid = kid.unwrap_or_else(|| self.thumbprint());
+        let jwk_value = self.to_jwk(&kid);
+        let jwk: Jwk = serde_json::from_value(jwk_value.clone()).map_err(|e| e.to_string())?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| e.to_string())?;
+
+        Ok(VerifyingKey {
+            kid: Some(kid),
+            alg: self.algorithm(),
+            key,
+            jwk: Some(jwk_value),
+        })
+    }
+}
+
+/// Splits one DER TLV off the front of `input`: (tag, contents, rest).
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || input.len() < count {
+            return None;
+        }
+        let len = input[..count]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        input = &input[count..];
+        len
+    };
+
+    if input.len() < len {
+        return None;
+    }
+    Some((tag, &input[..len], &input[len..]))
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+/// Reads a SubjectPublicKeyInfo (`-----BEGIN PUBLIC KEY-----`).
+fn parse_spki(der: &[u8]) -> Option<PublicKeyMaterial> {
+    let (0x30, spki, _) = der_next(der)? else {
+        return None;
+    };
+    let (0x30, algorithm, rest) = der_next(spki)? else {
+        return None;
+    };
+    let (0x06, oid, _) = der_next(algorithm)? else {
+        return None;
+    };
+    let (0x03, bits, _) = der_next(rest)? else {
+        return None;
+    };
+    let (&0, key) = bits.split_first()? else {
+        return None;
+    };
+
+    if oid == OID_ED25519 && key.len() == 32 {
+        return Some(PublicKeyMaterial::Ed25519 { x: key.to_vec() });
+    }
+    if oid == OID_RSA_ENCRYPTION {
+        let (0x30, rsa, _) = der_next(key)? else {
+            return None;
+        };
+        let (0x02, n, rest) = der_next(rsa)? else {
+            return None;
+        };
+        let (0x02, e, _) = der_next(rest)? else {
+            return None;
+        };
+        return Some(PublicKeyMaterial::Rsa {
+            n: strip_leading_zeros(n),
+            e: strip_leading_zeros(e),
+        });
+    }
+    None
+}
+
+struct Pem {
+    label: String,
+    der: Vec<u8>,
+    text: String,
+}
+
+fn read_pem(path: &str) -> Result<Pem, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+    let begin = text
+        .lines()
+        .find_map(|line| line.strip_prefix("-----BEGIN "))
+        .and_then(|rest| rest.strip_suffix("-----"))
+        .ok_or_else(|| format!("{}: not a PEM file", path))?
+        .to_string();
+    let body: String = text
+        .lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN "))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END "))
+        .collect();
+    let der = STANDARD
+        .decode(body.trim())
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    Ok(Pem {
+        label: begin,
+        der,
+        text,
+    })
+}
+
+/// Derives the public half of a PKCS#8 or PKCS#1 private key.
+fn private_key_material(pem: &Pem) -> Option<PublicKeyMaterial> {
+    match pem.label.as_str() {
+        "RSA PRIVATE KEY" => RsaKeyPair::from_der(&pem.der)
+            .ok()
+            .map(|key| rsa_material(&key)),
+        "PRIVATE KEY" => {
+            if let Ok(key) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pem.der) {
+                return Some(PublicKeyMaterial::Ed25519 {
+                    x: key.public_key().as_ref().to_vec(),
+                });
+            }
+            RsaKeyPair::from_pkcs8(&pem.der)
+                .ok()
+                .map(|key| rsa_material(&key))
+        }
+        _ => None,
+    }
+}
+
+fn rsa_material(key: &RsaKeyPair) -> PublicKeyMaterial {
+    let components = RsaPublicKeyComponents::<Vec<u8>>::from(key.public());
+    PublicKeyMaterial::Rsa {
+        n: components.n,
+        e: components.e,
+    }
+}
+
+fn load_signing_key(path: &str) -> Result<(EncodingKey, PublicKeyMaterial), String> {
+    let pem = read_pem(path)?;
+    let material = private_key_material(&pem)
+        .ok_or_else(|| format!("{}: expected an RSA or Ed25519 private key", path))?;
+
+    let key = match material {
+        PublicKeyMaterial::Rsa { .. } => EncodingKey::from_rsa_pem(pem.text.as_bytes()),
+        PublicKeyMaterial::Ed25519 { .. } => EncodingKey::from_ed_pem(pem.text.as_bytes()),
+    }
+    .map_err(|e| format!("{}: {}", path, e))?;
+
+    Ok((key, material))
+}
+
+fn load_public_key(path: &str) -> Result<PublicKeyMaterial, String> {
+    let pem = read_pem(path)?;
+    let material = if pem.label == "PUBLIC KEY" {
+        parse_spki(&pem.der)
+    } else {
+        private_key_material(&pem)
+    };
+    material.ok_or_else(|| format!("{}: expected an RSA or Ed25519 key", path))
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+/// Whether `APP_ENV` is `production`, where development fallbacks are refused.
+pub fn is_production() -> bool {
+    std::env::var("APP_ENV").is_ok_and(|v| v.eq_ignore_ascii_case("production"))
+}
+
+impl JwtKeys {
+    fn from_env() -> Result<JwtKeys, String> {
+        let keys = match std::env::var("JWT_PRIVATE_KEY_FILE") {
+            Ok(path) => JwtKeys::from_files(
+                &path,
+                std::env::var("JWT_KEY_ID").ok(),
+                &std::env::var("JWT_PUBLIC_KEY_FILES").unwrap_or_default(),
+            )?,
+            Err(_) if is_production() => {
+                return Err("APP_ENV=production requires JWT_PRIVATE_KEY_FILE".into());
+            }
+            Err(_) => {
+                let secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| {
+                    eprintln!("⚠️  No JWT key configured, using the development fallback secret");
+                    DEV_FALLBACK_SECRET.to_string()
+                });
+                JwtKeys::from_secret(&secret)
+            }
+        };
+        keys.self_check()?;
+        Ok(keys)
+    }
+
+    fn from_secret(secret: &str) -> JwtKeys {
+        JwtKeys {
+            signing_kid: None,
+            signing_alg: Algorithm::HS256,
+            signing_key: EncodingKey::from_secret(secret.as_bytes()),
+            verifying: vec![VerifyingKey {
+                kid: None,
+                alg: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+                jwk: None,
+            }],
+            issuer: env_or("JWT_ISSUER", "actix-doc"),
+            audience: env_or("JWT_AUDIENCE", "actix-doc"),
+        }
+    }
+
+    /// Signs with the private key at `path`; `previous` lists the extra
+    /// verification keys as in `JWT_PUBLIC_KEY_FILES`.
+    fn from_files(path: &str, kid: Option<String>, previous: &str) -> Result<JwtKeys, String> {
+        let (signing_key, material) = load_signing_key(path)?;
+        let current = material.verifying_key(kid)?;
+
+        let mut keys = JwtKeys {
+            signing_kid: current.kid.clone(),
+            signing_alg: current.alg,
+            signing_key,
+            verifying: vec![current],
+            issuer: env_or("JWT_ISSUER", "actix-doc"),
+            audience: env_or("JWT_AUDIENCE", "actix-doc"),
+        };
+
+        for entry in previous.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (kid, path) = match entry.split_once('=') {
+                Some((kid, path)) => (Some(kid.trim().to_string()), path.trim()),
+                None => (None, entry),
+            };
+            let key = load_public_key(path)?.verifying_key(kid)?;
+            if keys.verifying.iter().any(|k| k.kid == key.kid) {
+                return Err(format!("duplicate JWT key id {:?}", key.kid));
+            }
+            keys.verifying.push(key);
+        }
+
+        Ok(keys)
+    }
+
+    /// Signs and verifies a throwaway token, so a key that parses but can't
+    /// be used stops the server at startup rather than failing every login.
+    fn self_check(&self) -> Result<(), String> {
+        let claims = serde_json::json!({
+            "sub": "startup-check",
+            "iss": self.issuer,
+            "aud": self.audience,
+            "exp": chrono::Utc::now().timestamp() + 60,
+        });
+        let token = self
+            .encode(&claims)
+            .map_err(|_| "the signing key can't sign tokens".to_string())?;
+        self.decode::<serde_json::Value>(&token)
+            .map(|_| ())
+            .map_err(|_| "tokens from the signing key don't verify".to_string())
+    }
+
+    /// `iss` of the tokens issued here.
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    /// `aud` of the tokens issued here.
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, ServiceError> {
+        let mut header = Header::new(self.signing_alg);
+        header.kid = self.signing_kid.clone();
+
+        encode(&header, claims, &self.signing_key).map_err(|_| ServiceError::InternalServerError)
+    }
+
+    /// Verifies a token against the key named by its `kid` header, using
+    /// that key's algorithm only, and checks its issuer and audience.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T, ServiceError> {
+        let invalid = || ServiceError::Unauthorized("Invalid token".into());
+
+        let header = decode_header(token).map_err(|_| invalid())?;
+        let key = self
+            .verifying
+            .iter()
+            .find(|k| k.kid == header.kid)
+            .ok_or_else(invalid)?;
+        if header.alg != key.alg {
+            return Err(invalid());
+        }
+
+        let mut validation = Validation::new(key.alg);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+
+        decode::<T>(token, &key.key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| invalid())
+    }
+}
+
+/// Loads the keys from the environment. Called once at startup so that a
+/// misconfiguration stops the server instead of failing every request.
+pub fn init() -> Result<(), String> {
+    let keys = JwtKeys::from_env()?;
+    if let Some(kid) = &keys.signing_kid {
+        println!("🔑 Signing access tokens with key {}", kid);
+    }
+    KEYS.set(keys)
+        .map_err(|_| "JWT keys already initialised".to_string())
+}
+
+pub fn keys() -> &'static JwtKeys {
+    KEYS.get().expect("jwt_keys::init must run at startup")
+}
+
+#[get("/.well-known/jwks.json")]
+pub async fn jwks() -> HttpResponse {
+    let keys: Vec<_> = keys()
+        .verifying
+        .iter()
+        .filter_map(|k| k.jwk.clone())
+        .collect();
+
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(serde_json::json!({ "keys": keys }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Throwaway keys generated for these tests only.
+    const RSA_PKCS8: &str = include_str!("../tests/fixtures/jwt/rsa.pem");
+    const RSA_PKCS1: &str = include_str!("../tests/fixtures/jwt/rsa_pkcs1.pem");
+    const RSA_PUBLIC: &str = include_str!("../tests/fixtures/jwt/rsa.pub.pem");
+    const ED25519: &str = include_str!("../tests/fixtures/jwt/ed25519.pem");
+    const ED25519_PUBLIC: &str = include_str!("../tests/fixtures/jwt/ed25519.pub.pem");
+
+    /// A PEM file removed again when the test ends.
+    struct KeyFile(PathBuf);
+
+    impl KeyFile {
+        fn new(pem: &str) -> KeyFile {
+            let path = std::env::temp_dir().join(format!("jwt-{}.pem", uuid::Uuid::new_v4()));
+            std::fs::write(&path, pem).unwrap();
+            KeyFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for KeyFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn load(pem: &str, previous: &str) -> Result<JwtKeys, String> {
+        let file = KeyFile::new(pem);
+        let keys = JwtKeys::from_files(file.path(), None, previous)?;
+        keys.self_check()?;
+        Ok(keys)
+    }
+
+    /// Cuts the body short on a base64 boundary, keeping the armour lines,
+    /// so it still decodes but to incomplete DER.
+    fn truncated(pem: &str) -> String {
+        let lines: Vec<&str> = pem.lines().collect();
+        let body = lines[1..lines.len() - 1].concat();
+        let keep = body.len() / 2 / 4 * 4;
+        format!(
+            "{}\n{}\n{}\n",
+            lines[0],
+            &body[..keep],
+            lines[lines.len() - 1]
+        )
+    }
+
+    fn claims(keys: &JwtKeys) -> serde_json::Value {
+        serde_json::json!({
+            "sub": "user",
+            "iss": keys.issuer,
+            "aud": keys.audience,
+            "exp": chrono::Utc::now().timestamp() + 60,
+        })
+    }
+
+    #[test]
+    fn loads_known_good_private_keys() {
+        for (pem, alg) in [
+            (RSA_PKCS8, Algorithm::RS256),
+            (RSA_PKCS1, Algorithm::RS256),
+            (ED25519, Algorithm::EdDSA),
+        ] {
+            let keys = load(pem, "").unwrap();
+            assert_eq!(keys.signing_alg, alg);
+            assert!(keys.signing_kid.is_some());
+            let token = keys.encode(&claims(&keys)).unwrap();
+            let decoded: serde_json::Value = keys.decode(&token).unwrap();
+            assert_eq!(decoded["sub"], "user");
+        }
+    }
+
+    #[test]
+    fn both_rsa_encodings_have_the_same_thumbprint() {
+        let pkcs8 = load(RSA_PKCS8, "").unwrap();
+        let pkcs1 = load(RSA_PKCS1, "").unwrap();
+        assert_eq!(pkcs8.signing_kid, pkcs1.signing_kid);
+    }
+
+    #[test]
+    fn previous_public_keys_still_verify() {
+        let old = load(RSA_PKCS8, "").unwrap();
+        let token = old.encode(&claims(&old)).unwrap();
+
+        let public = KeyFile::new(RSA_PUBLIC);
+        let rotated = load(ED25519, public.path()).unwrap();
+        assert_eq!(rotated.verifying.len(), 2);
+        assert_eq!(rotated.verifying[1].kid, old.signing_kid);
+        rotated.decode::<serde_json::Value>(&token).unwrap();
+    }
+
+    #[test]
+    fn rejects_truncated_and_malformed_keys() {
+        for pem in [
+            truncated(RSA_PKCS8),
+            truncated(RSA_PKCS1),
+            truncated(ED25519),
+            RSA_PKCS8[..RSA_PKCS8.len() / 2].to_string(),
+            RSA_PUBLIC.to_string(),
+            "not a key".to_string(),
+            String::new(),
+        ] {
+            assert!(load(&pem, "").is_err(), "accepted {:?}", pem);
+        }
+
+        for pem in [truncated(RSA_PUBLIC), truncated(ED25519_PUBLIC)] {
+            let public = KeyFile::new(&pem);
+            assert!(load(ED25519, public.path()).is_err(), "accepted {:?}", pem);
+        }
+        assert!(load(ED25519, "/nonexistent/key.pem").is_err());
+    }
+
+    #[test]
+    fn requires_the_configured_issuer_and_audience() {
+        let keys = load(ED25519, "").unwrap();
+
+        let mut wrong_issuer = claims(&keys);
+        wrong_issuer["iss"] = "someone-else".into();
+        let mut wrong_audience = claims(&keys);
+        wrong_audience["aud"] = "another-service".into();
+        let mut missing = claims(&keys);
+        missing.as_object_mut().unwrap().remove("aud");
+
+        for claims in [wrong_issuer, wrong_audience, missing] {
+            let token = keys.encode(&claims).unwrap();
+            assert!(keys.decode::<serde_json::Value>(&token).is_err());
+        }
+    }
+}
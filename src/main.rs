@@ -2,20 +2,45 @@ use actix_cors::Cors;
 use actix_web::{middleware::Logger, web, App, HttpServer};
 use dotenv::dotenv;
 use env_logger::Env;
+use rand::{distributions::Alphanumeric, Rng};
 
+mod account;
+mod admin;
+mod api_tokens;
+mod audit;
 mod auth;
+mod collab;
+mod collab_html;
+mod cookie_auth;
 mod db;
+mod diff;
 mod docs;
 mod docs_trash;
+mod docs_tree;
 mod errors;
+mod events;
+mod hashing;
+mod html_text;
+mod jwt_keys;
 mod models;
+mod notifier;
+mod oidc;
+mod password;
+mod permissions;
+mod profile;
+mod registration;
+mod revisions;
 mod search;
+mod sessions;
+mod share_links;
 mod tags;
+mod throttle;
+mod totp_secrets;
+mod two_factor;
+mod webauthn;
+mod workspaces;
+mod zip;
 
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
 use sqlx::query;
 
 async fn create_default_user(pool: &db::DbPool) -> Result<(), Box<dyn std::error::Error>> {
@@ -29,19 +54,25 @@ async fn create_default_user(pool: &db::DbPool) -> Result<(), Box<dyn std::error
         return Ok(());
     }
 
-    // Create admin user with password "admin"
-    let password = "admin";
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let password_hash = argon2
-        .hash_password(password.as_bytes(), &salt)
-        .map_err(|e| format!("Password hashing failed: {}", e))?
-        .to_string();
+    // Create admin user with ADMIN_PASSWORD, or a random password that is
+    // shown only this once
+    let configured = std::env::var("ADMIN_PASSWORD")
+        .ok()
+        .filter(|p| !p.is_empty());
+    let password = configured.clone().unwrap_or_else(|| {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(20)
+            .map(char::from)
+            .collect()
+    });
+    let password_hash =
+        auth::hash_password(&password).map_err(|_| "Password hashing failed".to_string())?;
 
     let user_id = uuid::Uuid::new_v4().to_string();
 
     query!(
-        "INSERT INTO users (id, username, password_hash) VALUES (?, ?, ?)",
+        "INSERT INTO users (id, username, password_hash, role) VALUES (?, ?, ?, 'admin')",
         user_id,
         "admin",
         password_hash
@@ -49,21 +80,41 @@ async fn create_default_user(pool: &db::DbPool) -> Result<(), Box<dyn std::error
     .execute(pool)
     .await?;
 
-    println!("✅ Default admin user created (username: admin, password: admin)");
-    println!("⚠️  Please change the default password in production!");
+    if configured.is_some() {
+        println!("✅ Default admin user created (username: admin, password from ADMIN_PASSWORD)");
+    } else {
+        println!(
+            "✅ Default admin user created (username: admin, password: {})",
+            password
+        );
+        println!("⚠️  This password is not shown again; sign in and change it now.");
+    }
 
-    // Create demo user for development (matches frontend api.ts)
+    Ok(())
+}
+
+/// Whether to create the `demo`/`demo` account for local development
+/// (`DEMO_USER`, off by default).
+fn demo_user_enabled() -> bool {
+    std::env::var("DEMO_USER").is_ok_and(|v| v == "true" || v == "1")
+}
+
+async fn create_demo_user(pool: &db::DbPool) -> Result<(), Box<dyn std::error::Error>> {
     let demo_existing = query!("SELECT id FROM users WHERE id = ?", "demo-user")
         .fetch_optional(pool)
         .await?;
 
+    if !demo_user_enabled() {
+        if demo_existing.is_some() {
+            println!("⚠️  The demo account (demo/demo) exists; delete it unless this is a development setup.");
+        }
+        return Ok(());
+    }
+
+    // Create demo user for development (matches frontend api.ts)
     if demo_existing.is_none() {
-        let password = "demo";
-        let salt = SaltString::generate(&mut OsRng);
-        let password_hash = Argon2::default()
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|e| format!("Password hashing failed: {}", e))?
-            .to_string();
+        let password_hash =
+            auth::hash_password("demo").map_err(|_| "Password hashing failed".to_string())?;
 
         query!(
             "INSERT INTO users (id, username, password_hash) VALUES (?, ?, ?)",
@@ -79,32 +130,165 @@ async fn create_default_user(pool: &db::DbPool) -> Result<(), Box<dyn std::error
     Ok(())
 }
 
+/// Access log in the format of `Logger::default()`, but with the path
+/// instead of the request line: the query string may hold an access token
+/// (`?token=` on `/events` and the collaboration socket).
+fn access_log() -> Logger {
+    Logger::new(r#"%a "%{method}xi %U" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
+        .custom_request_replace("method", |req| req.method().to_string())
+}
+
+/// Address the server listens on (`BIND_ADDR`, default `127.0.0.1:8080`).
+fn bind_addr() -> String {
+    std::env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string())
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
+    if let Err(e) = hashing::init() {
+        eprintln!("❌ Invalid Argon2 configuration: {}", e);
+        std::process::exit(1);
+    }
+
     let pool = db::init_pool().await;
 
+    if std::env::args().nth(1).as_deref() == Some("password-hash-report") {
+        if let Err(e) = hashing::report(&pool).await {
+            eprintln!("❌ Failed to read users: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Err(e) = jwt_keys::init() {
+        eprintln!("❌ Invalid JWT key configuration: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = totp_secrets::init() {
+        eprintln!("❌ Invalid TOTP encryption key: {}", e);
+        std::process::exit(1);
+    }
+    match totp_secrets::seal_plaintext(&pool).await {
+        Ok(0) => {}
+        Ok(count) => println!("🔒 Encrypted {} stored TOTP secrets", count),
+        Err(e) => eprintln!("Warning: Failed to encrypt stored TOTP secrets: {:?}", e),
+    }
+
     // Create default user if not exists
     if let Err(e) = create_default_user(&pool).await {
         eprintln!("Warning: Failed to create default user: {}", e);
     }
+    if let Err(e) = create_demo_user(&pool).await {
+        eprintln!("Warning: Failed to create demo user: {}", e);
+    }
+
+    let notifier = notifier::from_env();
+    let rooms = web::Data::new(collab::Rooms::default());
+    let bus = web::Data::new(events::EventBus::default());
+
+    actix_web::rt::spawn(audit::run_retention(pool.clone()));
 
     HttpServer::new(move || {
         let cors = Cors::permissive(); // For dev
 
         App::new()
-            .wrap(Logger::default())
+            .wrap(access_log())
             .wrap(cors)
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(notifier.clone()))
+            .app_data(rooms.clone())
+            .app_data(bus.clone())
+            .service(jwt_keys::jwks)
             .service(auth::register)
             .service(auth::login)
+            .service(registration::registration_info)
+            .service(oidc::authorize)
+            .service(oidc::callback)
+            .service(oidc::list_identities)
+            .service(oidc::unlink_identity)
+            .service(password::change_password)
+            .service(password::forgot_password)
+            .service(password::reset_password)
+            .service(auth::refresh)
+            .service(auth::logout)
+            .service(sessions::list_sessions)
+            .service(sessions::revoke)
+            .service(sessions::revoke_all)
+            .service(profile::get_profile)
+            .service(profile::update_profile)
+            .service(profile::upload_avatar)
+            .service(profile::delete_avatar)
+            .service(profile::get_avatar)
+            .service(account::export)
+            .service(account::delete_account)
+            .service(two_factor::enroll)
+            .service(two_factor::confirm)
+            .service(two_factor::disable)
+            .service(two_factor::login_2fa)
+            .service(webauthn::start_registration)
+            .service(webauthn::finish_registration)
+            .service(webauthn::start_login)
+            .service(webauthn::finish_login)
+            .service(webauthn::list_credentials)
+            .service(webauthn::delete_credential)
+            .service(api_tokens::create_token)
+            .service(api_tokens::list_tokens)
+            .service(api_tokens::revoke_token)
             .service(docs::list_docs)
+            // Before `get_doc`, which would take "tree" for an id.
+            .service(docs_tree::get_tree)
             .service(docs::get_doc)
             .service(docs::create_doc)
             .service(docs::update_doc)
             .service(docs::delete_doc)
+            .service(revisions::list_revisions)
+            .service(revisions::get_revision)
+            .service(revisions::restore_revision)
+            .service(permissions::list_grants)
+            .service(permissions::grant)
+            .service(permissions::revoke)
+            .service(permissions::shared_with_me)
+            .service(share_links::create_link)
+            .service(share_links::list_links)
+            .service(share_links::revoke_link)
+            .service(share_links::view)
+            .service(share_links::view_child)
+            .service(share_links::unlock)
+            .service(share_links::unlock_child)
+            .service(workspaces::list_workspaces)
+            .service(workspaces::create_workspace)
+            .service(workspaces::get_active)
+            .service(workspaces::switch_workspace)
+            .service(workspaces::update_workspace)
+            .service(workspaces::delete_workspace)
+            .service(workspaces::list_members)
+            .service(workspaces::update_member)
+            .service(workspaces::remove_member)
+            .service(workspaces::list_workspace_invitations)
+            .service(workspaces::invite)
+            .service(workspaces::cancel_invitation)
+            .service(workspaces::my_invitations)
+            .service(workspaces::accept_invitation)
+            .service(workspaces::decline_invitation)
+            .service(collab::connect)
+            .service(events::stream_events)
+            .service(diff::diff_revisions)
+            .service(diff::blame)
+            .service(audit::list_events)
+            .service(admin::list_users)
+            .service(admin::disable_user)
+            .service(admin::enable_user)
+            .service(admin::reset_password)
+            .service(admin::change_role)
+            .service(admin::delete_user)
+            .service(registration::create_invite)
+            .service(registration::list_invites)
+            .service(registration::revoke_invite)
+            .service(throttle::list_lockouts)
+            .service(throttle::clear_lockout)
             .service(tags::list_tags)
             .service(tags::create_tag)
             .service(docs_trash::get_trash)
@@ -113,7 +297,7 @@ async fn main() -> std::io::Result<()> {
             .service(search::search_docs)
             .service(actix_files::Files::new("/", "./static").index_file("index.html"))
     })
-    .bind(("127.0.0.1", 8080))?
+    .bind(bind_addr())?
     .run()
     .await
 }
@@ -3,6 +3,11 @@ use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+use crate::permissions::Role;
+
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_USER: &str = "user";
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct User {
     pub id: String,
@@ -23,6 +28,10 @@ pub struct Document {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
+    /// Bumped on every update; sent as the ETag, see `docs::etag`.
+    pub version: i64,
+    /// `None` in the owner's personal space.
+    pub workspace_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -30,12 +39,15 @@ pub struct DocumentWithTags {
     #[serde(flatten)]
     pub document: Document,
     pub tags: Vec<tag::Tag>,
+    /// The caller's access to the document.
+    pub access: Role,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateUserRequest {
     pub username: String,
     pub password: String,
+    pub invite_code: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,3 +55,10 @@ pub struct LoginRequest {
     pub username: String,
     pub password: String,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenRequest {
+    /// Omitted by cookie-mode clients, whose refresh token is in a cookie.
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
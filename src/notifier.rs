@@ -0,0 +1,117 @@
+use actix_web::web;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::{io::Write, path::PathBuf, sync::Arc};
+
+use crate::errors::ServiceError;
+
+/// A message for a single user, such as a password reset link.
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub user_id: String,
+    pub username: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers notifications to users. Implementations decide the channel;
+/// handlers only see `web::Data<dyn Notifier>`.
+pub trait Notifier: Send + Sync {
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), ServiceError>>;
+}
+
+/// Prints notifications to stdout. Meant for development.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), ServiceError>> {
+        Box::pin(async move {
+            println!(
+                "📬 To {} ({}): {}\n{}",
+                notification.username,
+                notification.user_id,
+                notification.subject,
+                notification.body
+            );
+            Ok(())
+        })
+    }
+}
+
+/// Appends notifications as JSON lines to a file, so tests and local setups
+/// can read what would have been sent.
+pub struct FileOutbox {
+    path: PathBuf,
+}
+
+impl FileOutbox {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileOutbox { path: path.into() }
+    }
+}
+
+#[derive(Serialize)]
+struct OutboxEntry<'a> {
+    sent_at: String,
+    #[serde(flatten)]
+    notification: &'a Notification,
+}
+
+impl Notifier for FileOutbox {
+    fn send<'a>(
+        &'a self,
+        notification: &'a Notification,
+    ) -> BoxFuture<'a, Result<(), ServiceError>> {
+        Box::pin(async move {
+            let entry = OutboxEntry {
+                sent_at: Utc::now().to_rfc3339(),
+                notification,
+            };
+            let mut line =
+                serde_json::to_string(&entry).map_err(|_| ServiceError::InternalServerError)?;
+            line.push('\n');
+
+            let path = self.path.clone();
+            web::block(move || {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?
+                    .write_all(line.as_bytes())
+            })
+            .await
+            .map_err(|_| ServiceError::InternalServerError)?
+            .map_err(|e| {
+                eprintln!("Failed to write notification: {}", e);
+                ServiceError::InternalServerError
+            })
+        })
+    }
+}
+
+/// Builds the notifier selected by `NOTIFIER` (`log` or `file`, default `log`).
+/// The file outbox writes to `NOTIFIER_OUTBOX_PATH` (default `./data/outbox.jsonl`).
+pub fn from_env() -> Arc<dyn Notifier> {
+    match std::env::var("NOTIFIER").as_deref() {
+        Ok("file") => {
+            let path = std::env::var("NOTIFIER_OUTBOX_PATH")
+                .unwrap_or_else(|_| "./data/outbox.jsonl".to_string());
+            Arc::new(FileOutbox::new(path))
+        }
+        Ok("log") | Err(_) => Arc::new(LogNotifier),
+        Ok(other) => {
+            eprintln!("Unknown NOTIFIER {:?}, falling back to log", other);
+            Arc::new(LogNotifier)
+        }
+    }
+}
@@ -0,0 +1,501 @@
+use actix_web::{
+    cookie::{time, Cookie, SameSite},
+    delete, get, post, web, HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, NaiveDateTime, Utc};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{complete_login, generate_token, get_session_user_id, hash_password, hash_token},
+    cookie_auth::cookie_secure,
+    db::DbPool,
+    errors::ServiceError,
+    registration::validate_username,
+};
+
+const STATE_TTL_MINUTES: i64 = 10;
+
+/// Holds a hash of the pending `state`, so the callback only succeeds in the
+/// browser that started the flow.
+const STATE_COOKIE: &str = "adoc_oidc_state";
+const STATE_COOKIE_PATH: &str = "/auth/oidc";
+
+fn state_cookie(value: String) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, value)
+        .path(STATE_COOKIE_PATH)
+        .http_only(true)
+        .secure(cookie_secure())
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(STATE_TTL_MINUTES))
+        .finish()
+}
+
+/// Provider settings, read from the environment:
+///
+/// - `OIDC_ISSUER`: issuer URL; `/.well-known/openid-configuration` is fetched from it
+/// - `OIDC_CLIENT_ID`, `OIDC_CLIENT_SECRET` (optional for public clients)
+/// - `OIDC_REDIRECT_URI`: where the provider sends the browser back to
+/// - `OIDC_SCOPES` (default `openid profile email`)
+/// - `OIDC_USERNAME_CLAIM` (default `preferred_username`)
+/// - `OIDC_AUTO_PROVISION` (default `true`): create users on first login
+struct OidcConfig {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String,
+    username_claim: String,
+    auto_provision: bool,
+}
+
+impl OidcConfig {
+    fn from_env() -> Result<OidcConfig, ServiceError> {
+        let required = |name: &str| {
+            std::env::var(name)
+                .map_err(|_| ServiceError::BadRequest("Single sign-on is not configured".into()))
+        };
+
+        Ok(OidcConfig {
+            issuer: required("OIDC_ISSUER")?.trim_end_matches('/').to_string(),
+            client_id: required("OIDC_CLIENT_ID")?,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_uri: required("OIDC_REDIRECT_URI")?,
+            scopes: std::env::var("OIDC_SCOPES")
+                .unwrap_or_else(|_| "openid profile email".to_string()),
+            username_claim: std::env::var("OIDC_USERNAME_CLAIM")
+                .unwrap_or_else(|_| "preferred_username".to_string()),
+            auto_provision: std::env::var("OIDC_AUTO_PROVISION")
+                .map(|v| v != "false" && v != "0")
+                .unwrap_or(true),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkedIdentity {
+    pub id: String,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_login_at: Option<NaiveDateTime>,
+}
+
+fn provider_error(context: &str, error: impl std::fmt::Display) -> ServiceError {
+    eprintln!("OIDC {}: {}", context, error);
+    ServiceError::Unauthorized("Single sign-on failed".into())
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, ServiceError> {
+    reqwest::get(url)
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| provider_error(url, e))?
+        .json()
+        .await
+        .map_err(|e| provider_error(url, e))
+}
+
+async fn discover(config: &OidcConfig) -> Result<ProviderMetadata, ServiceError> {
+    let url = format!("{}/.well-known/openid-configuration", config.issuer);
+    let metadata: ProviderMetadata = fetch_json(&url).await?;
+
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(provider_error("discovery", "issuer mismatch"));
+    }
+    Ok(metadata)
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Checks the ID token signature against the provider's published keys, and
+/// its issuer, audience, expiry and nonce.
+async fn verify_id_token(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, ServiceError> {
+    let header = decode_header(id_token).map_err(|e| provider_error("id_token header", e))?;
+    // Symmetric algorithms would let anyone holding the client secret mint tokens.
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(provider_error(
+            "id_token",
+            "symmetric algorithm not accepted",
+        ));
+    }
+
+    let jwks: JwkSet = fetch_json(&metadata.jwks_uri).await?;
+    let jwk: &Jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| provider_error("id_token", "no matching signing key"))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|e| provider_error("jwk", e))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map_err(|e| provider_error("id_token", e))?
+        .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(provider_error("id_token", "nonce mismatch"));
+    }
+    Ok(claims)
+}
+
+/// Turns the provider's username claim into a free local username that
+/// passes the registration rules, adding a numeric suffix on collisions.
+async fn provision_username(
+    pool: &DbPool,
+    config: &OidcConfig,
+    claims: &IdTokenClaims,
+) -> Result<String, ServiceError> {
+    let raw = claims
+        .extra
+        .get(&config.username_claim)
+        .and_then(|v| v.as_str())
+        .or_else(|| claims.email.as_deref().and_then(|e| e.split('@').next()))
+        .unwrap_or("user");
+
+    let mut base: String = raw
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !validate_username(&base)?.is_empty() {
+        base = "user".to_string();
+    }
+
+    for n in 1.. {
+        let candidate = if n == 1 {
+            base.clone()
+        } else {
+            format!("{}-{}", base, n)
+        };
+        let taken = sqlx::query_scalar!("SELECT id FROM users WHERE username = ?", candidate)
+            .fetch_optional(pool)
+            .await?
+            .is_some();
+        if !taken {
+            return Ok(candidate);
+        }
+    }
+    unreachable!()
+}
+
+/// Starts an authorization-code + PKCE flow and returns the provider URL the
+/// browser should be sent to, binding the flow to this browser with a
+/// cookie. When called with a session token, the external identity is linked
+/// to the caller instead of signing in.
+#[get("/auth/oidc/authorize")]
+pub async fn authorize(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let config = OidcConfig::from_env()?;
+
+    let link_user_id = if req.headers().contains_key("Authorization") {
+        Some(get_session_user_id(&req, pool.get_ref()).await?)
+    } else {
+        None
+    };
+
+    let metadata = discover(&config).await?;
+
+    let state = generate_token();
+    let nonce = generate_token();
+    let code_verifier = generate_token();
+    let expires_at = (Utc::now() + Duration::minutes(STATE_TTL_MINUTES)).naive_utc();
+
+    sqlx::query!(
+        "INSERT INTO oidc_states (state, nonce, code_verifier, link_user_id, expires_at) VALUES (?, ?, ?, ?, ?)",
+        state,
+        nonce,
+        code_verifier,
+        link_user_id,
+        expires_at
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+        .map_err(|e| provider_error("authorization_endpoint", e))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_uri)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &pkce_challenge(&code_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    Ok(HttpResponse::Ok()
+        .cookie(state_cookie(hash_token(&state)))
+        .json(serde_json::json!({ "authorization_url": url.as_str() })))
+}
+
+/// Completes the flow with the `code` and `state` the provider redirected
+/// back with, then signs in (or links) the matching local user. The state
+/// must be the one whose cookie `authorize` set in this browser.
+#[post("/auth/oidc/callback")]
+pub async fn callback(
+    pool: web::Data<DbPool>,
+    req: web::Json<CallbackRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let mut response = finish_callback(pool, req, &http_req).await?;
+    response
+        .add_removal_cookie(&state_cookie(String::new()))
+        .map_err(|_| ServiceError::InternalServerError)?;
+    Ok(response)
+}
+
+async fn finish_callback(
+    pool: web::Data<DbPool>,
+    req: web::Json<CallbackRequest>,
+    http_req: &HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let config = OidcConfig::from_env()?;
+
+    let bound = http_req
+        .cookie(STATE_COOKIE)
+        .is_some_and(|cookie| cookie.value() == hash_token(&req.state));
+    if !bound {
+        return Err(ServiceError::BadRequest(
+            "State does not belong to this browser".into(),
+        ));
+    }
+
+    let pending = sqlx::query!(
+        "SELECT nonce, code_verifier, link_user_id, expires_at FROM oidc_states WHERE state = ?",
+        req.state
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::BadRequest("Unknown or expired state".into()))?;
+
+    sqlx::query!("DELETE FROM oidc_states WHERE state = ?", req.state)
+        .execute(pool.get_ref())
+        .await?;
+
+    if pending.expires_at < Utc::now().naive_utc() {
+        return Err(ServiceError::BadRequest("Unknown or expired state".into()));
+    }
+
+    let metadata = discover(&config).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", req.code.as_str()),
+        ("redirect_uri", config.redirect_uri.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", pending.code_verifier.as_str()),
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+
+    let tokens: TokenResponse = reqwest::Client::new()
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| provider_error("token request", e))?
+        .json()
+        .await
+        .map_err(|e| provider_error("token response", e))?;
+
+    let claims = verify_id_token(&config, &metadata, &tokens.id_token, &pending.nonce).await?;
+    let now = Utc::now().naive_utc();
+
+    let linked = sqlx::query!(
+        "SELECT i.id, i.user_id, u.username FROM oidc_identities i JOIN users u ON u.id = i.user_id WHERE i.issuer = ? AND i.subject = ?",
+        metadata.issuer,
+        claims.sub
+    )
+    .fetch_optional(pool.get_ref())
+    .await?;
+
+    if let Some(link_user_id) = pending.link_user_id {
+        if let Some(linked) = linked {
+            if linked.user_id != link_user_id {
+                return Err(ServiceError::BadRequest(
+                    "This identity is already linked to another account".into(),
+                ));
+            }
+        } else {
+            let id = Uuid::new_v4().to_string();
+            sqlx::query!(
+                "INSERT INTO oidc_identities (id, user_id, issuer, subject, email) VALUES (?, ?, ?, ?, ?)",
+                id,
+                link_user_id,
+                metadata.issuer,
+                claims.sub,
+                claims.email
+            )
+            .execute(pool.get_ref())
+            .await?;
+        }
+        return Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Identity linked"})));
+    }
+
+    let (user_id, username) = match linked {
+        Some(linked) => {
+            sqlx::query!(
+                "UPDATE oidc_identities SET last_login_at = ?, email = ? WHERE id = ?",
+                now,
+                claims.email,
+                linked.id
+            )
+            .execute(pool.get_ref())
+            .await?;
+            (linked.user_id, linked.username)
+        }
+        None if config.auto_provision => {
+            let user_id = Uuid::new_v4().to_string();
+            let identity_id = Uuid::new_v4().to_string();
+            let username = provision_username(pool.get_ref(), &config, &claims).await?;
+            // SSO users sign in through the provider; the local password is
+            // random until they set one through the reset flow.
+            let password_hash = hash_password(&generate_token())?;
+
+            let mut tx = pool.begin().await?;
+
+            sqlx::query!(
+                "INSERT INTO users (id, username, password_hash) VALUES (?, ?, ?)",
+                user_id,
+                username,
+                password_hash
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!(
+                "INSERT INTO oidc_identities (id, user_id, issuer, subject, email, last_login_at) VALUES (?, ?, ?, ?, ?, ?)",
+                identity_id,
+                user_id,
+                metadata.issuer,
+                claims.sub,
+                claims.email,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+            (user_id, username)
+        }
+        None => {
+            return Err(ServiceError::Forbidden(
+                "No account is linked to this identity".into(),
+            ))
+        }
+    };
+
+    complete_login(pool.get_ref(), http_req, &user_id, username, "oidc").await
+}
+
+#[get("/auth/oidc/identities")]
+pub async fn list_identities(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+
+    let identities = sqlx::query_as!(
+        LinkedIdentity,
+        "SELECT id, issuer, subject, email, created_at, last_login_at FROM oidc_identities WHERE user_id = ? ORDER BY created_at ASC",
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(identities))
+}
+
+#[delete("/auth/oidc/identities/{id}")]
+pub async fn unlink_identity(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+    let identity_id = id.into_inner();
+
+    let result = sqlx::query!(
+        "DELETE FROM oidc_identities WHERE id = ? AND user_id = ?",
+        identity_id,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::BadRequest("Identity not found".into()));
+    }
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("oidc.unlink")
+            .actor(&user_id)
+            .target("oidc_identity", &identity_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Identity unlinked"})))
+}
@@ -0,0 +1,252 @@
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{
+        client_ip, complete_login, generate_token, get_session_user_id, hash_password, hash_token,
+        revoke_user_sessions, verify_password,
+    },
+    db::DbPool,
+    errors::ServiceError,
+    notifier::{Notification, Notifier},
+    registration::validate_password,
+    throttle,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// Lifetime of a password reset token in minutes (`PASSWORD_RESET_TTL_MINUTES`, default 30).
+fn reset_token_ttl() -> Duration {
+    let minutes = std::env::var("PASSWORD_RESET_TTL_MINUTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+    Duration::minutes(minutes)
+}
+
+/// Builds the text sent to the user. When `PASSWORD_RESET_URL` is set the
+/// token is appended to it as a link, e.g. `https://docs.example.com/reset?token=`.
+fn reset_message(token: &str, ttl: Duration) -> String {
+    let action = match std::env::var("PASSWORD_RESET_URL") {
+        Ok(url) => format!("Open this link to choose a new password:\n{}{}", url, token),
+        Err(_) => format!("Use this code to choose a new password:\n{}", token),
+    };
+    format!(
+        "{}\n\nIt expires in {} minutes and can only be used once. If you did not ask for a reset, ignore this message.",
+        action,
+        ttl.num_minutes()
+    )
+}
+
+fn password_errors(password: &str, username: &str) -> Result<(), ServiceError> {
+    let problems = validate_password(password, username);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(ServiceError::ValidationError(BTreeMap::from([(
+        "new_password".to_string(),
+        problems,
+    )])))
+}
+
+/// Changes the caller's password. Every existing session is signed out and
+/// the response carries fresh tokens for the current client.
+#[post("/auth/password")]
+pub async fn change_password(
+    pool: web::Data<DbPool>,
+    req: web::Json<ChangePasswordRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&http_req, pool.get_ref()).await?;
+
+    let user = sqlx::query!(
+        "SELECT username, password_hash FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    verify_password(&user.password_hash, &req.current_password).map_err(|_| {
+        ServiceError::ValidationError(BTreeMap::from([(
+            "current_password".to_string(),
+            vec!["Current password is incorrect".to_string()],
+        )]))
+    })?;
+    password_errors(&req.new_password, &user.username)?;
+
+    let password_hash = hash_password(&req.new_password)?;
+    sqlx::query!(
+        "UPDATE users SET password_hash = ? WHERE id = ?",
+        password_hash,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+    revoke_user_sessions(pool.get_ref(), &user_id).await?;
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("auth.password_change")
+            .actor(&user_id)
+            .target("user", &user_id),
+    )
+    .await;
+
+    complete_login(
+        pool.get_ref(),
+        &http_req,
+        &user_id,
+        user.username,
+        "password_change",
+    )
+    .await
+}
+
+/// Sends a reset token to the account, if it exists. The response is the same
+/// either way so the endpoint can't be used to probe for usernames.
+#[post("/auth/password/forgot")]
+pub async fn forgot_password(
+    pool: web::Data<DbPool>,
+    notifier: web::Data<dyn Notifier>,
+    req: web::Json<ForgotPasswordRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    throttle::check_password_reset(pool.get_ref(), &client_ip(&http_req)).await?;
+
+    let user = sqlx::query!(
+        "SELECT id, username FROM users WHERE username = ? AND disabled_at IS NULL",
+        req.username
+    )
+    .fetch_optional(pool.get_ref())
+    .await?;
+
+    if let Some(user) = user {
+        let id = Uuid::new_v4().to_string();
+        let token = generate_token();
+        let token_hash = hash_token(&token);
+        let ttl = reset_token_ttl();
+        let expires_at = (Utc::now() + ttl).naive_utc();
+
+        // Only the most recent token is valid.
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM password_reset_tokens WHERE user_id = ? AND used_at IS NULL",
+            user.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO password_reset_tokens (id, user_id, token_hash, expires_at) VALUES (?, ?, ?, ?)",
+            id,
+            user.id,
+            token_hash,
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        notifier
+            .send(&Notification {
+                user_id: user.id,
+                username: user.username,
+                subject: "Reset your password".to_string(),
+                body: reset_message(&token, ttl),
+            })
+            .await?;
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "If the account exists, a password reset has been sent"
+    })))
+}
+
+#[post("/auth/password/reset")]
+pub async fn reset_password(
+    pool: web::Data<DbPool>,
+    req: web::Json<ResetPasswordRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let token_hash = hash_token(req.token.trim());
+    let now = Utc::now().naive_utc();
+
+    let stored = sqlx::query!(
+        r#"
+        SELECT r.id, r.user_id, u.username
+        FROM password_reset_tokens r
+        JOIN users u ON u.id = r.user_id
+        WHERE r.token_hash = ? AND r.used_at IS NULL AND r.expires_at > ? AND u.disabled_at IS NULL
+        "#,
+        token_hash,
+        now
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::BadRequest(
+        "Invalid or expired reset token".into(),
+    ))?;
+
+    password_errors(&req.new_password, &stored.username)?;
+    let password_hash = hash_password(&req.new_password)?;
+
+    let mut tx = pool.begin().await?;
+
+    // Conditional on used_at so two concurrent requests can't both use it.
+    let claimed = sqlx::query!(
+        "UPDATE password_reset_tokens SET used_at = ? WHERE id = ? AND used_at IS NULL",
+        now,
+        stored.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if claimed.rows_affected() != 1 {
+        return Err(ServiceError::BadRequest(
+            "Invalid or expired reset token".into(),
+        ));
+    }
+
+    sqlx::query!(
+        "UPDATE users SET password_hash = ? WHERE id = ?",
+        password_hash,
+        stored.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    revoke_user_sessions(pool.get_ref(), &stored.user_id).await?;
+    throttle::login_succeeded(pool.get_ref(), &stored.username).await?;
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("auth.password_reset")
+            .actor(&stored.user_id)
+            .target("user", &stored.user_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Password has been reset"})))
+}
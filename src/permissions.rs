@@ -0,0 +1,391 @@
+//! Sharing documents and folders with other users.
+//!
+//! Owners grant viewer, commenter or editor access per user in
+//! `document_permissions`. A grant on a folder applies to everything below
+//! it; the `document_grants` view resolves that inheritance. Every access
+//! check on a single document goes through `authorize`, and listings share
+//! the `VISIBLE_DOCUMENTS` fragment, which reads the same view and applies
+//! the same rules. In a workspace the member's workspace role applies as
+//! well, see `workspaces`.
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::query_as;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::{
+    api_tokens::Scope,
+    audit::{self, Event},
+    auth::get_user_id,
+    db::DbPool,
+    errors::ServiceError,
+    events::EventBus,
+    models::Document,
+    workspaces::{self, WorkspaceRole},
+};
+
+/// Access to a document, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can read the document.
+    Viewer,
+    /// Can read and comment; nothing beyond reading exists yet.
+    Commenter,
+    /// Can change the title, content and tags.
+    Editor,
+    /// Can also move, delete and share it.
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Commenter => "commenter",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "viewer" => Some(Role::Viewer),
+            "commenter" => Some(Role::Commenter),
+            "editor" => Some(Role::Editor),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// The caller's access to a document: owner, the strongest grant on it or
+/// one of its folders, or none. In a workspace, owners and admins own every
+/// document, members own the ones they created and edit the rest, and only
+/// members have access at all. `VISIBLE_DOCUMENTS` does the same for
+/// listings; keep the two in step.
+pub async fn role_of(
+    pool: &DbPool,
+    doc: &Document,
+    user_id: &str,
+) -> Result<Option<Role>, ServiceError> {
+    let base = match &doc.workspace_id {
+        None if doc.owner_id == user_id => return Ok(Some(Role::Owner)),
+        None => None,
+        Some(workspace_id) => match workspaces::membership(pool, workspace_id, user_id).await? {
+            None => return Ok(None),
+            Some(WorkspaceRole::Owner | WorkspaceRole::Admin) => return Ok(Some(Role::Owner)),
+            Some(WorkspaceRole::Member) if doc.owner_id == user_id => return Ok(Some(Role::Owner)),
+            Some(WorkspaceRole::Member) => Some(Role::Editor),
+            Some(WorkspaceRole::Guest) => None,
+        },
+    };
+    let roles = sqlx::query_scalar!(
+        r#"SELECT role AS "role!: String" FROM document_grants WHERE document_id = ? AND user_id = ?"#,
+        doc.id,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(roles
+        .iter()
+        .filter_map(|r| Role::parse(r))
+        .chain(base)
+        .max())
+}
+
+/// Loads a live document, checking that `user_id` has at least `required`
+/// access to it. Returns the document and the caller's role.
+pub async fn authorize(
+    pool: &DbPool,
+    doc_id: &str,
+    user_id: &str,
+    required: Role,
+) -> Result<(Document, Role), ServiceError> {
+    let doc = query_as!(
+        Document,
+        "SELECT * FROM documents WHERE id = ? AND deleted_at IS NULL",
+        doc_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(ServiceError::BadRequest("Document not found".into()))?;
+
+    match role_of(pool, &doc, user_id).await? {
+        Some(role) if role >= required => Ok((doc, role)),
+        _ => Err(ServiceError::Forbidden("Permission denied".into())),
+    }
+}
+
+/// Everyone who can see a document: the owner, or in a workspace all
+/// members but guests, and all grantees, including those of the folders
+/// above it.
+pub async fn audience(pool: &DbPool, doc: &Document) -> Result<Vec<String>, ServiceError> {
+    let mut users = sqlx::query_scalar!(
+        r#"SELECT DISTINCT user_id AS "user_id!: String" FROM document_grants WHERE document_id = ?"#,
+        doc.id
+    )
+    .fetch_all(pool)
+    .await?;
+    match &doc.workspace_id {
+        Some(workspace_id) => users.extend(workspaces::members(pool, workspace_id, false).await?),
+        None => users.push(doc.owner_id.clone()),
+    }
+    users.sort();
+    users.dedup();
+    Ok(users)
+}
+
+/// Common table expressions for listing what the caller can see in the
+/// active workspace, with the role `role_of` would give them as `access`:
+/// their own documents in the personal space, all of a workspace unless they
+/// are a guest, and whatever `document_grants` shares with them. Follows
+/// `WITH RECURSIVE`; the query binds the user id as `?1`, the active
+/// workspace id as `?2` and the caller's workspace role as `?3`, then
+/// selects from `visible`.
+pub const VISIBLE_DOCUMENTS: &str = r#"
+    granted(document_id, rank) AS (
+        SELECT document_id,
+            MAX(CASE role WHEN 'editor' THEN 3 WHEN 'commenter' THEN 2 ELSE 1 END)
+        FROM document_grants
+        WHERE user_id = ?1
+        GROUP BY document_id
+    ),
+    ranked AS (
+        SELECT d.*, MAX(
+            CASE
+                WHEN ?2 IS NULL THEN CASE WHEN d.owner_id = ?1 THEN 4 ELSE 0 END
+                WHEN ?3 IN ('owner', 'admin') THEN 4
+                WHEN ?3 = 'member' THEN CASE WHEN d.owner_id = ?1 THEN 4 ELSE 3 END
+                ELSE 0
+            END,
+            COALESCE(g.rank, 0)
+        ) AS access_rank
+        FROM documents d
+        LEFT JOIN granted g ON g.document_id = d.id
+        WHERE d.deleted_at IS NULL AND d.workspace_id IS ?2
+    ),
+    visible AS (
+        SELECT *,
+            CASE access_rank
+                WHEN 4 THEN 'owner' WHEN 3 THEN 'editor' WHEN 2 THEN 'commenter' ELSE 'viewer'
+            END AS access
+        FROM ranked
+        WHERE access_rank > 0
+    )
+"#;
+
+#[derive(Debug, Deserialize)]
+pub struct GrantRequest {
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Grant {
+    pub user_id: String,
+    pub username: String,
+    pub role: String,
+    pub granted_by: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedDocument {
+    pub id: String,
+    pub title: String,
+    pub is_folder: bool,
+    pub workspace_id: Option<String>,
+    pub owner_id: String,
+    pub owner_name: String,
+    pub role: String,
+    pub shared_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Lists the users a document is shared with directly.
+#[get("/documents/{id}/permissions")]
+pub async fn list_grants(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let (doc, _) = authorize(pool.get_ref(), &id, &user_id, Role::Owner).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+
+    let grants = query_as!(
+        Grant,
+        r#"
+        SELECT p.user_id, u.username, p.role, p.granted_by, p.created_at, p.updated_at
+        FROM document_permissions p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.document_id = ?
+        ORDER BY u.username ASC
+        "#,
+        doc.id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(grants))
+}
+
+/// Shares a document with a user, or changes the role they already have.
+#[post("/documents/{id}/permissions")]
+pub async fn grant(
+    pool: web::Data<DbPool>,
+    bus: web::Data<EventBus>,
+    id: web::Path<String>,
+    body: web::Json<GrantRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (doc, _) = authorize(pool.get_ref(), &id, &user_id, Role::Owner).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+
+    if body.role == Role::Owner {
+        return Err(ServiceError::ValidationError(BTreeMap::from([(
+            "role".to_string(),
+            vec!["Role must be viewer, commenter or editor".to_string()],
+        )])));
+    }
+    let grantee = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE username = ? AND disabled_at IS NULL",
+        body.username
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::BadRequest("User not found".into()))?;
+    if grantee == doc.owner_id {
+        return Err(ServiceError::BadRequest(
+            "The owner already has full access".into(),
+        ));
+    }
+    // Outsiders join a workspace as guests before anything in it can be
+    // shared with them.
+    if let Some(workspace_id) = &doc.workspace_id {
+        if workspaces::membership(pool.get_ref(), workspace_id, &grantee)
+            .await?
+            .is_none()
+        {
+            return Err(ServiceError::BadRequest(
+                "User is not a member of this workspace".into(),
+            ));
+        }
+    }
+
+    let grant_id = Uuid::new_v4().to_string();
+    let role = body.role.as_str();
+    let now = Utc::now().naive_utc();
+    sqlx::query!(
+        r#"
+        INSERT INTO document_permissions (id, document_id, user_id, role, granted_by, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+        ON CONFLICT (document_id, user_id) DO UPDATE SET role = ?4, granted_by = ?5, updated_at = ?6
+        "#,
+        grant_id,
+        doc.id,
+        grantee,
+        role,
+        user_id,
+        now
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("document.share")
+            .actor(&user_id)
+            .target("document", &doc.id)
+            .details(
+                serde_json::json!({"user_id": grantee, "username": body.username, "role": role}),
+            ),
+    )
+    .await;
+    bus.access_changed(&doc, &grantee);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "user_id": grantee,
+        "username": body.username,
+        "role": role,
+    })))
+}
+
+/// Stops sharing a document with a user. Access through a shared folder
+/// above it is not affected.
+#[delete("/documents/{id}/permissions/{user_id}")]
+pub async fn revoke(
+    pool: web::Data<DbPool>,
+    bus: web::Data<EventBus>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (doc_id, grantee) = path.into_inner();
+    let (doc, _) = authorize(pool.get_ref(), &doc_id, &user_id, Role::Owner).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+
+    let result = sqlx::query!(
+        "DELETE FROM document_permissions WHERE document_id = ? AND user_id = ?",
+        doc.id,
+        grantee
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::BadRequest("Permission not found".into()));
+    }
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("document.unshare")
+            .actor(&user_id)
+            .target("document", &doc.id)
+            .details(serde_json::json!({"user_id": grantee})),
+    )
+    .await;
+    bus.access_changed(&doc, &grantee);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Permission revoked"})))
+}
+
+/// Documents and folders other users shared with the caller directly,
+/// newest first. Their contents are listed by `GET /documents`.
+#[get("/shared")]
+pub async fn shared_with_me(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+
+    let shared = query_as!(
+        SharedDocument,
+        r#"
+        SELECT d.id, d.title, d.is_folder, d.workspace_id, d.owner_id, u.username AS owner_name, p.role,
+            p.updated_at AS shared_at, d.updated_at
+        FROM document_permissions p
+        JOIN documents d ON d.id = p.document_id
+        JOIN users u ON u.id = d.owner_id
+        WHERE p.user_id = ? AND d.deleted_at IS NULL
+        ORDER BY p.updated_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(shared))
+}
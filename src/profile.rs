@@ -0,0 +1,397 @@
+use actix_web::{delete, get, patch, put, web, HttpRequest, HttpResponse};
+use chrono::NaiveDateTime;
+use futures::StreamExt;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::{auth::get_session_user_id, db::DbPool, errors::ServiceError};
+
+const DISPLAY_NAME_MAX_LENGTH: usize = 64;
+const EMAIL_MAX_LENGTH: usize = 254;
+const TIMEZONE_MAX_LENGTH: usize = 64;
+
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub id: String,
+    pub username: String,
+    pub role: String,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+    /// Public URL of the avatar image; it changes whenever the avatar does.
+    pub avatar_url: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub preferences: Value,
+    pub two_factor_enabled: bool,
+    pub created_at: NaiveDateTime,
+}
+
+/// Omitted fields are left alone and `null` clears a field. `preferences` is
+/// merged into the stored object key by key, with `null` removing a key.
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfileRequest {
+    #[serde(default, deserialize_with = "present")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub email: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub locale: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub timezone: Option<Option<String>>,
+    pub preferences: Option<Map<String, Value>>,
+}
+
+/// Distinguishes an explicit `null` (`Some(None)`) from an absent field (`None`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Directory holding uploaded avatars (`AVATAR_DIR`, default `./data/avatars`).
+pub fn avatar_dir() -> PathBuf {
+    std::env::var("AVATAR_DIR")
+        .unwrap_or_else(|_| "./data/avatars".to_string())
+        .into()
+}
+
+/// Largest accepted avatar upload (`AVATAR_MAX_BYTES`, default 1 MiB).
+fn avatar_max_bytes() -> usize {
+    std::env::var("AVATAR_MAX_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1024 * 1024)
+}
+
+/// Largest stored preferences object in bytes (`PREFERENCES_MAX_BYTES`, default 16384).
+fn preferences_max_bytes() -> usize {
+    std::env::var("PREFERENCES_MAX_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(16 * 1024)
+}
+
+/// Identifies an image by its magic bytes; the declared content type is not trusted.
+/// Returns the file extension to store it under.
+fn sniff_image(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("jpg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+fn content_type(file: &str) -> &'static str {
+    match file.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("jpg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+fn matches(pattern: &str, value: &str) -> bool {
+    Regex::new(pattern).expect("valid pattern").is_match(value)
+}
+
+/// Deletes a stored avatar file; a file that is already gone is fine.
+pub async fn remove_avatar_file(file: String) {
+    let path = avatar_dir().join(file);
+    let result = web::block(move || std::fs::remove_file(path)).await;
+    if let Ok(Err(e)) = result {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Failed to remove avatar: {}", e);
+        }
+    }
+}
+
+pub async fn load_profile(pool: &DbPool, user_id: &str) -> Result<Profile, ServiceError> {
+    let user = sqlx::query!(
+        r#"
+        SELECT id, username, role, display_name, email, avatar_file, locale, timezone,
+               preferences, totp_enabled, created_at
+        FROM users WHERE id = ?
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Profile {
+        id: user.id,
+        username: user.username,
+        role: user.role,
+        display_name: user.display_name,
+        email: user.email,
+        avatar_url: user.avatar_file.map(|f| format!("/avatars/{}", f)),
+        locale: user.locale,
+        timezone: user.timezone,
+        preferences: serde_json::from_str(&user.preferences)
+            .unwrap_or_else(|_| Value::Object(Map::new())),
+        two_factor_enabled: user.totp_enabled,
+        created_at: user.created_at,
+    })
+}
+
+/// Trims a text field, treating blank as cleared.
+fn normalize(value: &Option<String>) -> Option<String> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+}
+
+#[get("/me")]
+pub async fn get_profile(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+    Ok(HttpResponse::Ok().json(load_profile(pool.get_ref(), &user_id).await?))
+}
+
+#[patch("/me")]
+pub async fn update_profile(
+    pool: web::Data<DbPool>,
+    req: web::Json<UpdateProfileRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&http_req, pool.get_ref()).await?;
+    let current = load_profile(pool.get_ref(), &user_id).await?;
+    let mut errors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut error = |field: &str, message: &str| {
+        errors
+            .entry(field.to_string())
+            .or_default()
+            .push(message.to_string());
+    };
+
+    let display_name = match &req.display_name {
+        Some(value) => normalize(value),
+        None => current.display_name,
+    };
+    if let Some(name) = &display_name {
+        if name.chars().count() > DISPLAY_NAME_MAX_LENGTH {
+            error(
+                "display_name",
+                &format!("Must be at most {} characters", DISPLAY_NAME_MAX_LENGTH),
+            );
+        }
+        if name.chars().any(char::is_control) {
+            error("display_name", "Must not contain control characters");
+        }
+    }
+
+    let email = match &req.email {
+        Some(value) => normalize(value),
+        None => current.email,
+    };
+    if let Some(email) = &email {
+        if email.len() > EMAIL_MAX_LENGTH || !matches(r"^[^@\s]+@[^@\s]+\.[^@\s]+$", email) {
+            error("email", "Must be a valid email address");
+        } else {
+            let taken = sqlx::query_scalar!(
+                "SELECT id FROM users WHERE lower(email) = lower(?) AND id != ?",
+                email,
+                user_id
+            )
+            .fetch_optional(pool.get_ref())
+            .await?;
+            if taken.is_some() {
+                error("email", "Already in use");
+            }
+        }
+    }
+
+    let locale = match &req.locale {
+        Some(value) => normalize(value),
+        None => current.locale,
+    };
+    if let Some(locale) = &locale {
+        if !matches(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{2,8})*$", locale) {
+            error("locale", "Must be a language tag such as en or zh-CN");
+        }
+    }
+
+    let timezone = match &req.timezone {
+        Some(value) => normalize(value),
+        None => current.timezone,
+    };
+    if let Some(timezone) = &timezone {
+        if timezone.len() > TIMEZONE_MAX_LENGTH
+            || !(timezone == "UTC" || matches(r"^[A-Za-z]+(/[A-Za-z0-9_+-]+)+$", timezone))
+        {
+            error(
+                "timezone",
+                "Must be an IANA time zone such as Asia/Shanghai",
+            );
+        }
+    }
+
+    let mut preferences = match current.preferences {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    if let Some(changes) = &req.preferences {
+        for (key, value) in changes {
+            if value.is_null() {
+                preferences.remove(key);
+            } else {
+                preferences.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    let preferences = Value::Object(preferences).to_string();
+    if preferences.len() > preferences_max_bytes() {
+        error("preferences", "Too large");
+    }
+
+    if !errors.is_empty() {
+        return Err(ServiceError::ValidationError(errors));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET display_name = ?, email = ?, locale = ?, timezone = ?, preferences = ?
+        WHERE id = ?
+        "#,
+        display_name,
+        email,
+        locale,
+        timezone,
+        preferences,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(load_profile(pool.get_ref(), &user_id).await?))
+}
+
+/// Replaces the avatar with the raw image in the request body (PNG, JPEG,
+/// GIF or WebP).
+#[put("/me/avatar")]
+pub async fn upload_avatar(
+    pool: web::Data<DbPool>,
+    mut payload: web::Payload,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+
+    let limit = avatar_max_bytes();
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|_| ServiceError::BadRequest("Upload failed".into()))?;
+        if body.len() + chunk.len() > limit {
+            return Err(ServiceError::BadRequest(format!(
+                "Avatar must be at most {} bytes",
+                limit
+            )));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let extension = sniff_image(&body).ok_or(ServiceError::BadRequest(
+        "Avatar must be a PNG, JPEG, GIF or WebP image".into(),
+    ))?;
+    // A fresh name per upload lets clients cache avatars indefinitely.
+    let file = format!(
+        "{}-{}.{}",
+        user_id,
+        hex::encode(rand::random::<[u8; 8]>()),
+        extension
+    );
+
+    let path = avatar_dir().join(&file);
+    web::block(move || {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, body)
+    })
+    .await
+    .map_err(|_| ServiceError::InternalServerError)?
+    .map_err(|e| {
+        eprintln!("Failed to store avatar: {}", e);
+        ServiceError::InternalServerError
+    })?;
+
+    let previous = sqlx::query_scalar!("SELECT avatar_file FROM users WHERE id = ?", user_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+    sqlx::query!(
+        "UPDATE users SET avatar_file = ? WHERE id = ?",
+        file,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if let Some(previous) = previous {
+        remove_avatar_file(previous).await;
+    }
+
+    Ok(HttpResponse::Ok().json(load_profile(pool.get_ref(), &user_id).await?))
+}
+
+#[delete("/me/avatar")]
+pub async fn delete_avatar(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+
+    let previous = sqlx::query_scalar!("SELECT avatar_file FROM users WHERE id = ?", user_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+    sqlx::query!("UPDATE users SET avatar_file = NULL WHERE id = ?", user_id)
+        .execute(pool.get_ref())
+        .await?;
+    if let Some(previous) = previous {
+        remove_avatar_file(previous).await;
+    }
+
+    Ok(HttpResponse::Ok().json(load_profile(pool.get_ref(), &user_id).await?))
+}
+
+/// Serves avatar images without authentication so they can be used in
+/// `<img>` tags; file names are unguessable and change on every upload.
+/// Only names that are some user's current avatar are read from disk.
+#[get("/avatars/{file}")]
+pub async fn get_avatar(
+    pool: web::Data<DbPool>,
+    file: web::Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let file = file.into_inner();
+    let stored = sqlx::query_scalar!("SELECT avatar_file FROM users WHERE avatar_file = ?", file)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .flatten();
+    if stored.as_deref() != Some(file.as_str()) {
+        return Err(ServiceError::BadRequest("Avatar not found".into()));
+    }
+
+    let path = avatar_dir().join(&file);
+    let bytes = web::block(move || std::fs::read(path))
+        .await
+        .map_err(|_| ServiceError::InternalServerError)?
+        .map_err(|_| ServiceError::BadRequest("Avatar not found".into()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type(&file))
+        .insert_header(("Cache-Control", "public, max-age=31536000, immutable"))
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        .body(bytes))
+}
@@ -0,0 +1,332 @@
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, Transaction};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{generate_token, hash_token, require_admin},
+    db::DbPool,
+    errors::ServiceError,
+};
+
+const DEFAULT_USERNAME_PATTERN: &str = "^[A-Za-z0-9][A-Za-z0-9_.-]*$";
+/// Upper bound on password length, so hashing stays cheap.
+const PASSWORD_MAX_LENGTH: usize = 128;
+const DEFAULT_INVITE_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+    Open,
+    InviteOnly,
+    Closed,
+}
+
+/// Who may create an account (`REGISTRATION_MODE`: `open`, `invite_only` or
+/// `closed`, default `open`). Unrecognised values close registration.
+pub fn registration_mode() -> RegistrationMode {
+    match std::env::var("REGISTRATION_MODE")
+        .unwrap_or_else(|_| "open".to_string())
+        .to_lowercase()
+        .as_str()
+    {
+        "open" => RegistrationMode::Open,
+        "invite_only" | "invite-only" | "invite" => RegistrationMode::InviteOnly,
+        "closed" => RegistrationMode::Closed,
+        other => {
+            eprintln!(
+                "Unknown REGISTRATION_MODE {:?}, registration is closed",
+                other
+            );
+            RegistrationMode::Closed
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsernameRules {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub pattern: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// How many of lowercase, uppercase, digits and symbols must appear.
+    pub min_classes: usize,
+}
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Username rules (`USERNAME_MIN_LENGTH` 3, `USERNAME_MAX_LENGTH` 32,
+/// `USERNAME_PATTERN` letters, digits, `_`, `.` and `-`).
+pub fn username_rules() -> UsernameRules {
+    UsernameRules {
+        min_length: env_or("USERNAME_MIN_LENGTH", 3),
+        max_length: env_or("USERNAME_MAX_LENGTH", 32),
+        pattern: std::env::var("USERNAME_PATTERN")
+            .unwrap_or_else(|_| DEFAULT_USERNAME_PATTERN.to_string()),
+    }
+}
+
+/// Password policy (`PASSWORD_MIN_LENGTH` 8, `PASSWORD_MIN_CLASSES` 2).
+pub fn password_policy() -> PasswordPolicy {
+    PasswordPolicy {
+        min_length: env_or("PASSWORD_MIN_LENGTH", 8),
+        max_length: PASSWORD_MAX_LENGTH,
+        min_classes: env_or("PASSWORD_MIN_CLASSES", 2).min(4),
+    }
+}
+
+pub fn validate_username(username: &str) -> Result<Vec<String>, ServiceError> {
+    let rules = username_rules();
+    let pattern = Regex::new(&rules.pattern).map_err(|e| {
+        eprintln!("Invalid USERNAME_PATTERN: {}", e);
+        ServiceError::InternalServerError
+    })?;
+
+    let mut errors = Vec::new();
+    let length = username.chars().count();
+    if length < rules.min_length {
+        errors.push(format!("Must be at least {} characters", rules.min_length));
+    }
+    if length > rules.max_length {
+        errors.push(format!("Must be at most {} characters", rules.max_length));
+    }
+    if !pattern.is_match(username) {
+        errors.push("Contains characters that are not allowed".to_string());
+    }
+    Ok(errors)
+}
+
+pub fn validate_password(password: &str, username: &str) -> Vec<String> {
+    let policy = password_policy();
+
+    let mut errors = Vec::new();
+    let length = password.chars().count();
+    if length < policy.min_length {
+        errors.push(format!("Must be at least {} characters", policy.min_length));
+    }
+    if length > policy.max_length {
+        errors.push(format!("Must be at most {} characters", policy.max_length));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .into_iter()
+    .filter(|present| *present)
+    .count();
+    if classes < policy.min_classes {
+        errors.push(format!(
+            "Must mix at least {} of lowercase letters, uppercase letters, digits and symbols",
+            policy.min_classes
+        ));
+    }
+
+    if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+        errors.push("Must not contain the username".to_string());
+    }
+    errors
+}
+
+/// Checks `username` and `password` against the configured rules, keyed by
+/// field name. An empty map means both are acceptable.
+pub fn validate_credentials(
+    username: &str,
+    password: &str,
+) -> Result<BTreeMap<String, Vec<String>>, ServiceError> {
+    let mut fields = BTreeMap::new();
+
+    let username_errors = validate_username(username)?;
+    if !username_errors.is_empty() {
+        fields.insert("username".to_string(), username_errors);
+    }
+    let password_errors = validate_password(password, username);
+    if !password_errors.is_empty() {
+        fields.insert("password".to_string(), password_errors);
+    }
+
+    Ok(fields)
+}
+
+/// Uses up one redemption of an invite code. Returns false when the code is
+/// unknown, expired or exhausted.
+pub async fn redeem_invite(
+    tx: &mut Transaction<'_, Sqlite>,
+    code: &str,
+) -> Result<bool, ServiceError> {
+    let code_hash = hash_token(code.trim());
+    let now = Utc::now().naive_utc();
+
+    let redeemed = sqlx::query!(
+        "UPDATE invite_codes SET uses = uses + 1 WHERE code_hash = ? AND uses < max_uses AND expires_at > ?",
+        code_hash,
+        now
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(redeemed.rows_affected() == 1)
+}
+
+#[derive(Debug, Serialize)]
+struct RegistrationInfo {
+    mode: RegistrationMode,
+    username: UsernameRules,
+    password: PasswordPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInviteRequest {
+    pub note: Option<String>,
+    pub max_uses: Option<i64>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteInfo {
+    pub id: String,
+    pub note: Option<String>,
+    pub created_by: Option<String>,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedInvite {
+    #[serde(flatten)]
+    info: InviteInfo,
+    /// Plaintext code, only ever returned once.
+    code: String,
+}
+
+/// Public description of the registration rules, so clients can validate
+/// forms before submitting them.
+#[get("/auth/registration")]
+pub async fn registration_info() -> Result<HttpResponse, ServiceError> {
+    Ok(HttpResponse::Ok().json(RegistrationInfo {
+        mode: registration_mode(),
+        username: username_rules(),
+        password: password_policy(),
+    }))
+}
+
+#[post("/admin/invites")]
+pub async fn create_invite(
+    pool: web::Data<DbPool>,
+    req: web::Json<CreateInviteRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin_id = require_admin(&http_req, pool.get_ref()).await?;
+
+    let max_uses = req.max_uses.unwrap_or(1);
+    if max_uses <= 0 {
+        return Err(ServiceError::BadRequest("max_uses must be positive".into()));
+    }
+    let days = req.expires_in_days.unwrap_or(DEFAULT_INVITE_DAYS);
+    if days <= 0 {
+        return Err(ServiceError::BadRequest(
+            "expires_in_days must be positive".into(),
+        ));
+    }
+    let expires_at = (Utc::now() + Duration::days(days)).naive_utc();
+
+    let id = Uuid::new_v4().to_string();
+    let code = generate_token();
+    let code_hash = hash_token(&code);
+
+    sqlx::query!(
+        "INSERT INTO invite_codes (id, code_hash, note, created_by, max_uses, expires_at) VALUES (?, ?, ?, ?, ?, ?)",
+        id,
+        code_hash,
+        req.note,
+        admin_id,
+        max_uses,
+        expires_at
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    let info = sqlx::query_as!(
+        InviteInfo,
+        "SELECT id, note, created_by, max_uses, uses, expires_at, created_at FROM invite_codes WHERE id = ?",
+        id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("admin.invite_create")
+            .actor(&admin_id)
+            .target("invite", &id)
+            .details(serde_json::json!({"max_uses": max_uses, "expires_at": expires_at})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(CreatedInvite { info, code }))
+}
+
+#[get("/admin/invites")]
+pub async fn list_invites(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    require_admin(&req, pool.get_ref()).await?;
+
+    let invites = sqlx::query_as!(
+        InviteInfo,
+        "SELECT id, note, created_by, max_uses, uses, expires_at, created_at FROM invite_codes ORDER BY created_at DESC"
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+#[delete("/admin/invites/{id}")]
+pub async fn revoke_invite(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin_id = require_admin(&req, pool.get_ref()).await?;
+    let invite_id = id.into_inner();
+
+    let result = sqlx::query!("DELETE FROM invite_codes WHERE id = ?", invite_id)
+        .execute(pool.get_ref())
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::BadRequest("Invite not found".into()));
+    }
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("admin.invite_revoke")
+            .actor(&admin_id)
+            .target("invite", &invite_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Invite revoked"})))
+}
@@ -0,0 +1,322 @@
+//! Revision history of documents.
+//!
+//! Every change to a document's title or content is stored as a numbered
+//! snapshot in `document_revisions`. Restoring a revision applies it as a new
+//! change, so history is only ever appended to.
+
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::{query, query_as, Sqlite, Transaction};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::{
+    api_tokens::Scope,
+    auth::get_user_id,
+    collab::{self, Rooms},
+    db::DbPool,
+    docs::{check_if_match, etag},
+    errors::ServiceError,
+    events::EventBus,
+    models::{tag::Tag, Document, DocumentWithTags},
+    permissions::{authorize, Role},
+    workspaces,
+};
+
+const REASON_MAX_LENGTH: usize = 200;
+
+/// Consecutive autosaves by the same author within this many minutes of the
+/// first one are folded into a single revision
+/// (`REVISION_AUTOSAVE_WINDOW_MINUTES`, default 10; 0 keeps every autosave).
+fn autosave_window() -> Duration {
+    let minutes = std::env::var("REVISION_AUTOSAVE_WINDOW_MINUTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10);
+    Duration::minutes(minutes)
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevisionInfo {
+    pub id: String,
+    pub revision: i64,
+    pub title: String,
+    pub author_id: Option<String>,
+    pub author_name: Option<String>,
+    pub reason: Option<String>,
+    pub autosave: bool,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Revision {
+    #[serde(flatten)]
+    pub info: RevisionInfo,
+    pub content: Option<String>,
+}
+
+/// Trims the reason given for a change; blank means none.
+pub fn validate_reason(reason: Option<&str>) -> Result<Option<String>, ServiceError> {
+    let Some(reason) = reason.map(str::trim).filter(|r| !r.is_empty()) else {
+        return Ok(None);
+    };
+    if reason.chars().count() > REASON_MAX_LENGTH {
+        return Err(ServiceError::ValidationError(BTreeMap::from([(
+            "reason".to_string(),
+            vec![format!(
+                "Reason must be at most {} characters",
+                REASON_MAX_LENGTH
+            )],
+        )])));
+    }
+    Ok(Some(reason.to_string()))
+}
+
+/// Records the given title and content as the document's newest revision and
+/// returns its number. An autosave replaces the latest revision instead when
+/// that is an autosave by the same author still inside the squash window.
+pub async fn record(
+    tx: &mut Transaction<'_, Sqlite>,
+    document_id: &str,
+    author_id: &str,
+    title: &str,
+    content: Option<&str>,
+    reason: Option<&str>,
+    autosave: bool,
+) -> Result<i64, ServiceError> {
+    let now = Utc::now().naive_utc();
+    let window = autosave_window();
+
+    if autosave && window > Duration::zero() {
+        let latest = query!(
+            r#"
+            SELECT id, revision, author_id, autosave, created_at
+            FROM document_revisions
+            WHERE document_id = ?
+            ORDER BY revision DESC
+            LIMIT 1
+            "#,
+            document_id
+        )
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        if let Some(latest) = latest.filter(|l| {
+            l.autosave && l.author_id.as_deref() == Some(author_id) && l.created_at + window > now
+        }) {
+            query!(
+                "UPDATE document_revisions SET title = ?, content = ?, updated_at = ? WHERE id = ?",
+                title,
+                content,
+                now,
+                latest.id
+            )
+            .execute(&mut **tx)
+            .await?;
+            return Ok(latest.revision);
+        }
+    }
+
+    let revision = sqlx::query_scalar!(
+        r#"SELECT COALESCE(MAX(revision), 0) + 1 AS "revision!: i64" FROM document_revisions WHERE document_id = ?"#,
+        document_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    let id = Uuid::new_v4().to_string();
+    query!(
+        r#"
+        INSERT INTO document_revisions
+            (id, document_id, revision, title, content, author_id, reason, autosave, created_at, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        id,
+        document_id,
+        revision,
+        title,
+        content,
+        author_id,
+        reason,
+        autosave,
+        now,
+        now
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(revision)
+}
+
+pub async fn load_revision(
+    pool: &DbPool,
+    doc_id: &str,
+    revision: i64,
+) -> Result<Revision, ServiceError> {
+    let row = query!(
+        r#"
+        SELECT r.id, r.revision, r.title, r.content, r.author_id, u.username AS "author_name?",
+            r.reason, r.autosave, r.created_at, r.updated_at
+        FROM document_revisions r
+        LEFT JOIN users u ON u.id = r.author_id
+        WHERE r.document_id = ? AND r.revision = ?
+        "#,
+        doc_id,
+        revision
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(ServiceError::BadRequest("Revision not found".into()))?;
+
+    Ok(Revision {
+        info: RevisionInfo {
+            id: row.id,
+            revision: row.revision,
+            title: row.title,
+            author_id: row.author_id,
+            author_name: row.author_name,
+            reason: row.reason,
+            autosave: row.autosave,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        },
+        content: row.content,
+    })
+}
+
+/// Lists a document's revisions, newest first, without their content.
+#[get("/documents/{id}/revisions")]
+pub async fn list_revisions(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let (doc, _) = authorize(pool.get_ref(), &id, &user_id, Role::Viewer).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+
+    let revisions = query_as!(
+        RevisionInfo,
+        r#"
+        SELECT r.id, r.revision, r.title, r.author_id, u.username AS "author_name?",
+            r.reason, r.autosave, r.created_at, r.updated_at
+        FROM document_revisions r
+        LEFT JOIN users u ON u.id = r.author_id
+        WHERE r.document_id = ?
+        ORDER BY r.revision DESC
+        "#,
+        doc.id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[get("/documents/{id}/revisions/{revision}")]
+pub async fn get_revision(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i64)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let (doc_id, revision) = path.into_inner();
+    let (doc, _) = authorize(pool.get_ref(), &doc_id, &user_id, Role::Viewer).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+
+    let revision = load_revision(pool.get_ref(), &doc_id, revision).await?;
+    Ok(HttpResponse::Ok().json(revision))
+}
+
+/// Brings back the title and content of an earlier revision. This is saved
+/// as a new revision, so the versions in between stay in the history. An open
+/// collaborative session would bring the replaced content back, so it is
+/// discarded.
+#[post("/documents/{id}/revisions/{revision}/restore")]
+pub async fn restore_revision(
+    pool: web::Data<DbPool>,
+    rooms: web::Data<Rooms>,
+    bus: web::Data<EventBus>,
+    path: web::Path<(String, i64)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (doc_id, revision) = path.into_inner();
+    let (doc, role) = authorize(pool.get_ref(), &doc_id, &user_id, Role::Editor).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+    check_if_match(&req, doc.version)?;
+
+    let restored = load_revision(pool.get_ref(), &doc_id, revision).await?;
+    let now = Utc::now().naive_utc();
+    let reason = format!("Restored revision {}", revision);
+
+    let mut tx = pool.begin().await?;
+    let updated = query!(
+        "UPDATE documents SET title = ?, content = ?, updated_at = ?, version = version + 1 WHERE id = ? AND version = ?",
+        restored.info.title,
+        restored.content,
+        now,
+        doc_id,
+        doc.version
+    )
+    .execute(&mut *tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        // Saved concurrently since it was loaded above.
+        let current = sqlx::query_scalar!("SELECT version FROM documents WHERE id = ?", doc_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        return Err(ServiceError::PreconditionFailed(
+            "Document has been modified".into(),
+            current,
+        ));
+    }
+    record(
+        &mut tx,
+        &doc_id,
+        &user_id,
+        &restored.info.title,
+        restored.content.as_deref(),
+        Some(&reason),
+        false,
+    )
+    .await?;
+    tx.commit().await?;
+    collab::discard_state(pool.get_ref(), &rooms, &doc_id).await?;
+
+    let doc = query_as!(Document, "SELECT * FROM documents WHERE id = ?", doc_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    let tags = query_as!(
+        Tag,
+        r#"
+        SELECT t.id, t.name, t.created_at
+        FROM tags t
+        JOIN document_tags dt ON t.id = dt.tag_id
+        WHERE dt.document_id = ?
+        ORDER BY t.name ASC
+        "#,
+        doc_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    bus.document(pool.get_ref(), "document.updated", &doc).await;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(doc.version)))
+        .json(DocumentWithTags {
+            document: doc,
+            tags,
+            access: role,
+        }))
+}
@@ -1,7 +1,8 @@
-use crate::{db::DbPool, errors::ServiceError};
+use crate::{
+    api_tokens::Scope, auth::get_user_id, db::DbPool, errors::ServiceError, permissions, workspaces,
+};
 use actix_web::{get, web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
-use sqlx::query_as;
 
 #[derive(Debug, Deserialize)]
 pub struct SearchQuery {
@@ -16,32 +17,19 @@ pub struct SearchResult {
     pub rank: f64,
 }
 
-fn get_user_id(req: &HttpRequest) -> Result<String, ServiceError> {
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .ok_or(ServiceError::Unauthorized("No token provided".into()))?
-        .to_str()
-        .map_err(|_| ServiceError::Unauthorized("Invalid token format".into()))?;
-
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or(ServiceError::Unauthorized("Invalid token format".into()))?;
-
-    crate::auth::validate_token(token)
-}
-
 #[get("/search")]
 pub async fn search_docs(
     pool: web::Data<DbPool>,
     req: HttpRequest,
     query_params: web::Query<SearchQuery>,
 ) -> Result<HttpResponse, ServiceError> {
-    let user_id = get_user_id(&req)?;
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let active = workspaces::active(&req, pool.get_ref(), &user_id).await?;
     let q = &query_params.q;
 
     // SQLite FTS5 query with snippet highlighting.
-    // We join with the `documents` table to ensure access control (owner_id) and check deleted_at.
+    // We join with the documents the caller can see in the active workspace
+    // (`permissions::VISIBLE_DOCUMENTS`, as in `docs::list_docs`), which also skips deleted ones.
     // Note: FTS5 rank is used for ordering.
     // snippet(documents_fts, -1, '<b>', '</b>', '...', 64) generates a snippet from any column.
     // highlight(documents_fts, 2, '<b>', '</b>') highlights the content column (index 2).
@@ -54,24 +42,26 @@ pub async fn search_docs(
     // But MATCH param binding works.
 
     // Use runtime query_as to avoid macro type inference issues with FTS functions
-    let results = sqlx::query_as::<_, SearchResult>(
+    let results = sqlx::query_as::<_, SearchResult>(&format!(
         r#"
+        WITH RECURSIVE {}
         SELECT 
             d.id, 
             d.title, 
             snippet(documents_fts, 2, '<mark>', '</mark>', '...', 64) as headline,
             documents_fts.rank as rank
         FROM documents_fts
-        JOIN documents d ON documents_fts.id = d.id
-        WHERE documents_fts MATCH ? 
-          AND d.owner_id = ? 
-          AND d.deleted_at IS NULL
+        JOIN visible d ON documents_fts.id = d.id
+        WHERE documents_fts MATCH ?4
         ORDER BY rank
         LIMIT 20
         "#,
-    )
-    .bind(q)
+        permissions::VISIBLE_DOCUMENTS
+    ))
     .bind(user_id)
+    .bind(&active.id)
+    .bind(active.role.as_str())
+    .bind(q)
     .fetch_all(pool.get_ref())
     .await;
 
@@ -0,0 +1,236 @@
+use actix_web::{delete, get, web, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{client_ip, get_session_claims},
+    db::DbPool,
+    errors::ServiceError,
+};
+
+/// `last_seen_at` is only written when it is older than this, so that busy
+/// clients don't turn every request into a write.
+const LAST_SEEN_RESOLUTION_SECONDS: i64 = 60;
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeAllQuery {
+    #[serde(default)]
+    pub except_current: bool,
+}
+
+fn user_agent(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("User-Agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.chars().take(512).collect())
+}
+
+/// Records a new login and returns its session id.
+pub async fn create_session(
+    pool: &DbPool,
+    user_id: &str,
+    req: &HttpRequest,
+) -> Result<String, ServiceError> {
+    let id = Uuid::new_v4().to_string();
+    let user_agent = user_agent(req);
+    let ip = client_ip(req);
+
+    sqlx::query!(
+        "INSERT INTO sessions (id, user_id, user_agent, ip) VALUES (?, ?, ?, ?)",
+        id,
+        user_id,
+        user_agent,
+        ip
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Updates the last-seen time and address of a session on refresh. Sessions
+/// that predate session tracking are created on the fly; revoked ones stay
+/// revoked.
+pub async fn record_activity(
+    pool: &DbPool,
+    session_id: &str,
+    user_id: &str,
+    req: &HttpRequest,
+) -> Result<(), ServiceError> {
+    let now = Utc::now().naive_utc();
+    let user_agent = user_agent(req);
+    let ip = client_ip(req);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions (id, user_id, user_agent, ip, last_seen_at)
+        VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT(id) DO UPDATE SET ip = excluded.ip, last_seen_at = excluded.last_seen_at
+        "#,
+        session_id,
+        user_id,
+        user_agent,
+        ip,
+        now
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Fails unless `session_id` is a live session of `user_id`.
+pub async fn check_session(
+    pool: &DbPool,
+    session_id: &str,
+    user_id: &str,
+) -> Result<(), ServiceError> {
+    let session = sqlx::query!(
+        "SELECT revoked_at, last_seen_at FROM sessions WHERE id = ? AND user_id = ?",
+        session_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(ServiceError::Unauthorized("Session revoked".into()))?;
+
+    if session.revoked_at.is_some() {
+        return Err(ServiceError::Unauthorized("Session revoked".into()));
+    }
+
+    let now = Utc::now().naive_utc();
+    if now - session.last_seen_at > Duration::seconds(LAST_SEEN_RESOLUTION_SECONDS) {
+        sqlx::query!(
+            "UPDATE sessions SET last_seen_at = ? WHERE id = ?",
+            now,
+            session_id
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Ends a session: its access tokens stop validating and its refresh tokens
+/// are revoked.
+pub async fn revoke_session(pool: &DbPool, session_id: &str) -> Result<(), ServiceError> {
+    let now = Utc::now().naive_utc();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE sessions SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL",
+        now,
+        session_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked_at = ? WHERE family_id = ? AND revoked_at IS NULL",
+        now,
+        session_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[get("/auth/sessions")]
+pub async fn list_sessions(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let claims = get_session_claims(&req, pool.get_ref()).await?;
+
+    let sessions = sqlx::query!(
+        r#"
+        SELECT id, user_agent, ip, created_at, last_seen_at
+        FROM sessions
+        WHERE user_id = ? AND revoked_at IS NULL
+        ORDER BY last_seen_at DESC
+        "#,
+        claims.sub
+    )
+    .fetch_all(pool.get_ref())
+    .await?
+    .into_iter()
+    .map(|s| Session {
+        current: s.id == claims.sid,
+        id: s.id,
+        user_agent: s.user_agent,
+        ip: s.ip,
+        created_at: s.created_at,
+        last_seen_at: s.last_seen_at,
+    })
+    .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[delete("/auth/sessions/{id}")]
+pub async fn revoke(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let claims = get_session_claims(&req, pool.get_ref()).await?;
+    let session_id = id.into_inner();
+
+    sqlx::query_scalar!(
+        "SELECT id FROM sessions WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+        session_id,
+        claims.sub
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::BadRequest("Session not found".into()))?;
+
+    revoke_session(pool.get_ref(), &session_id).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Session revoked"})))
+}
+
+/// Signs out every session of the caller, or every other one with
+/// `?except_current=true`.
+#[delete("/auth/sessions")]
+pub async fn revoke_all(
+    pool: web::Data<DbPool>,
+    query: web::Query<RevokeAllQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let claims = get_session_claims(&req, pool.get_ref()).await?;
+
+    let sessions = sqlx::query_scalar!(
+        "SELECT id FROM sessions WHERE user_id = ? AND revoked_at IS NULL",
+        claims.sub
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let mut revoked = 0;
+    for session_id in sessions {
+        if query.except_current && session_id == claims.sid {
+            continue;
+        }
+        revoke_session(pool.get_ref(), &session_id).await?;
+        revoked += 1;
+    }
+
+    Ok(HttpResponse::Ok()
+        .json(serde_json::json!({"message": "Sessions revoked", "revoked": revoked})))
+}
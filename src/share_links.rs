@@ -0,0 +1,695 @@
+//! Public, read-only share links.
+//!
+//! An owner creates a link to a document or folder; anyone holding it can
+//! read through `GET /s/{token}` without an account, as JSON or as a
+//! standalone HTML page. Links may expire and may require a password. A
+//! folder link covers the documents directly in it, and with
+//! `include_subfolders` everything below it. A link only works while its
+//! creator still owns the document; any current owner can list and revoke
+//! the links on it.
+
+use actix_web::{
+    cookie::{time, Cookie, SameSite},
+    delete, get,
+    http::{header, StatusCode},
+    post, web, HttpRequest, HttpResponse,
+};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::{
+    api_tokens::Scope,
+    audit::{self, Event},
+    auth::{generate_token, get_user_id, hash_password, hash_token, verify_password},
+    cookie_auth::cookie_secure,
+    db::DbPool,
+    errors::ServiceError,
+    models::Document,
+    permissions::{authorize, role_of, Role},
+    throttle, workspaces,
+};
+
+/// Header carrying the password of a protected link for API clients.
+const PASSWORD_HEADER: &str = "X-Share-Password";
+
+/// How long an entered password is remembered by the browser.
+const UNLOCK_COOKIE_HOURS: i64 = 12;
+
+/// Sanitized pages may show images from anywhere but run nothing.
+const PAGE_CSP: &str =
+    "default-src 'none'; img-src * data:; style-src 'unsafe-inline'; form-action 'self'";
+
+const PAGE_STYLE: &str =
+    "body{font-family:system-ui,sans-serif;line-height:1.6;color:#1f2328;margin:0}\
+main{max-width:48rem;margin:0 auto;padding:2rem 1rem}\
+img{max-width:100%}pre{overflow-x:auto;background:#f6f8fa;padding:1rem}\
+.meta{color:#656d76;font-size:.875rem}ul.children{padding-left:1.25rem}\
+form{display:flex;gap:.5rem}.error{color:#cf222e}";
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareLinkRequest {
+    pub expires_at: Option<NaiveDateTime>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub include_subfolders: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareLinkInfo {
+    pub id: String,
+    pub document_id: String,
+    pub document_title: String,
+    pub created_by: String,
+    pub created_by_name: String,
+    pub has_password: bool,
+    pub include_subfolders: bool,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_accessed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedShareLink {
+    #[serde(flatten)]
+    info: ShareLinkInfo,
+    /// Plaintext token, only ever returned once.
+    token: String,
+    /// Path of the public page.
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareLinksQuery {
+    pub document_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormatQuery {
+    /// `json` or `html`; by default browsers get HTML and others JSON.
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordForm {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SharedChild {
+    id: String,
+    title: String,
+    is_folder: bool,
+    updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct SharedPage {
+    id: String,
+    title: String,
+    /// Sanitized HTML.
+    content: Option<String>,
+    is_folder: bool,
+    updated_at: NaiveDateTime,
+    /// Documents (and, with subfolders included, folders) inside a folder.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<SharedChild>>,
+}
+
+struct Link {
+    id: String,
+    document_id: String,
+    created_by: String,
+    password_hash: Option<String>,
+    include_subfolders: bool,
+}
+
+/// Creates a share link for a document or folder the caller owns.
+#[post("/documents/{id}/share-links")]
+pub async fn create_link(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    body: web::Json<CreateShareLinkRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (doc, _) = authorize(pool.get_ref(), &id, &user_id, Role::Owner).await?;
+    workspaces::active(&req, pool.get_ref(), &user_id)
+        .await?
+        .check_document(&doc)?;
+
+    let created_by_name = sqlx::query_scalar!("SELECT username FROM users WHERE id = ?", user_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    let now = Utc::now().naive_utc();
+    let mut errors = BTreeMap::new();
+    if body.expires_at.is_some_and(|at| at <= now) {
+        errors.insert(
+            "expires_at".to_string(),
+            vec!["Expiry must be in the future".to_string()],
+        );
+    }
+    let password = body.password.as_deref().filter(|p| !p.is_empty());
+    if password.is_some_and(|p| p.chars().count() < 4) {
+        errors.insert(
+            "password".to_string(),
+            vec!["Password must be at least 4 characters".to_string()],
+        );
+    }
+    if !errors.is_empty() {
+        return Err(ServiceError::ValidationError(errors));
+    }
+    let password_hash = password.map(hash_password).transpose()?;
+
+    let link_id = Uuid::new_v4().to_string();
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+    sqlx::query!(
+        r#"
+        INSERT INTO share_links
+            (id, document_id, token_hash, created_by, password_hash, include_subfolders, expires_at, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+        link_id,
+        doc.id,
+        token_hash,
+        user_id,
+        password_hash,
+        body.include_subfolders,
+        body.expires_at,
+        now
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("share_link.create")
+            .actor(&user_id)
+            .target("document", &doc.id)
+            .details(serde_json::json!({
+                "link_id": link_id,
+                "has_password": password_hash.is_some(),
+                "include_subfolders": body.include_subfolders,
+                "expires_at": body.expires_at,
+            })),
+    )
+    .await;
+
+    Ok(HttpResponse::Created().json(CreatedShareLink {
+        info: ShareLinkInfo {
+            id: link_id,
+            document_id: doc.id,
+            document_title: doc.title,
+            created_by: user_id,
+            created_by_name,
+            has_password: password_hash.is_some(),
+            include_subfolders: body.include_subfolders,
+            expires_at: body.expires_at,
+            last_accessed_at: None,
+            created_at: now,
+        },
+        url: format!("/s/{}", token),
+        token,
+    }))
+}
+
+/// Lists the share links the caller created or that are on documents they
+/// own, including expired ones, newest first. Ownership is decided as in
+/// `role_of`.
+#[get("/share-links")]
+pub async fn list_links(
+    pool: web::Data<DbPool>,
+    query: web::Query<ShareLinksQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+
+    let links = sqlx::query_as!(
+        ShareLinkInfo,
+        r#"
+        SELECT l.id, l.document_id, d.title AS document_title,
+            l.created_by, u.username AS created_by_name,
+            l.password_hash IS NOT NULL AS "has_password: bool",
+            l.include_subfolders, l.expires_at, l.last_accessed_at, l.created_at
+        FROM share_links l
+        JOIN documents d ON d.id = l.document_id
+        JOIN users u ON u.id = l.created_by
+        WHERE (l.created_by = ?1
+            OR (d.workspace_id IS NULL AND d.owner_id = ?1)
+            OR EXISTS (
+                SELECT 1 FROM workspace_members m
+                WHERE m.workspace_id = d.workspace_id AND m.user_id = ?1
+                  AND (m.role IN ('owner', 'admin') OR (m.role = 'member' AND d.owner_id = ?1))
+            ))
+          AND (?2 IS NULL OR l.document_id = ?2)
+        ORDER BY l.created_at DESC
+        "#,
+        user_id,
+        query.document_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(links))
+}
+
+/// Revokes a link the caller created or that is on a document they own.
+#[delete("/share-links/{id}")]
+pub async fn revoke_link(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let link_id = id.into_inner();
+
+    let document_id = sqlx::query_scalar!(
+        r#"
+        DELETE FROM share_links
+        WHERE id = ?1 AND (created_by = ?2 OR document_id IN (
+            SELECT d.id FROM documents d
+            WHERE (d.workspace_id IS NULL AND d.owner_id = ?2)
+               OR EXISTS (
+                   SELECT 1 FROM workspace_members m
+                   WHERE m.workspace_id = d.workspace_id AND m.user_id = ?2
+                     AND (m.role IN ('owner', 'admin') OR (m.role = 'member' AND d.owner_id = ?2))
+               )
+        ))
+        RETURNING document_id
+        "#,
+        link_id,
+        user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::BadRequest("Share link not found".into()))?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("share_link.revoke")
+            .actor(&user_id)
+            .target("document", &document_id)
+            .details(serde_json::json!({"link_id": link_id})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Share link revoked"})))
+}
+
+#[get("/s/{token}")]
+pub async fn view(
+    pool: web::Data<DbPool>,
+    token: web::Path<String>,
+    query: web::Query<FormatQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let password = header_password(&req);
+    serve(&pool, &req, &query, &token, None, password).await
+}
+
+#[get("/s/{token}/{doc_id}")]
+pub async fn view_child(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FormatQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (token, doc_id) = path.into_inner();
+    let password = header_password(&req);
+    serve(&pool, &req, &query, &token, Some(&doc_id), password).await
+}
+
+/// Submits the password form of a protected link.
+#[post("/s/{token}")]
+pub async fn unlock(
+    pool: web::Data<DbPool>,
+    token: web::Path<String>,
+    query: web::Query<FormatQuery>,
+    form: web::Form<PasswordForm>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let password = Some(form.into_inner().password);
+    serve(&pool, &req, &query, &token, None, password).await
+}
+
+#[post("/s/{token}/{doc_id}")]
+pub async fn unlock_child(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<FormatQuery>,
+    form: web::Form<PasswordForm>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (token, doc_id) = path.into_inner();
+    let password = Some(form.into_inner().password);
+    serve(&pool, &req, &query, &token, Some(&doc_id), password).await
+}
+
+fn header_password(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(PASSWORD_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn wants_html(req: &HttpRequest, query: &FormatQuery) -> bool {
+    match query.format.as_deref() {
+        Some(format) => format == "html",
+        None => req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html")),
+    }
+}
+
+async fn serve(
+    pool: &DbPool,
+    req: &HttpRequest,
+    query: &FormatQuery,
+    token: &str,
+    doc_id: Option<&str>,
+    password: Option<String>,
+) -> HttpResponse {
+    let html = wants_html(req, query);
+    let base = format!("/s/{}", token);
+
+    let link = match find_link(pool, token).await {
+        Ok(link) => link,
+        Err(e) => return error_response(e, html),
+    };
+    let unlock_cookie = match check_password(pool, req, &link, password.as_deref()).await {
+        Ok(cookie) => cookie,
+        Err(ServiceError::Unauthorized(message)) if html => {
+            let error = password.is_some().then_some(message.as_str());
+            return password_page(&base, doc_id, error);
+        }
+        Err(e) => return error_response(e, html),
+    };
+    let page = match load_page(pool, &link, doc_id).await {
+        Ok(page) => page,
+        Err(e) => return error_response(e, html),
+    };
+
+    let now = Utc::now().naive_utc();
+    let _ = sqlx::query!(
+        "UPDATE share_links SET last_accessed_at = ? WHERE id = ?",
+        now,
+        link.id
+    )
+    .execute(pool)
+    .await;
+
+    let mut res = HttpResponse::Ok();
+    res.insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((header::REFERRER_POLICY, "no-referrer"))
+        .insert_header(("X-Robots-Tag", "noindex"));
+    if let Some(value) = unlock_cookie {
+        res.cookie(
+            Cookie::build(unlock_cookie_name(&link), value)
+                .path(base.clone())
+                .http_only(true)
+                .secure(cookie_secure())
+                .same_site(SameSite::Lax)
+                .max_age(time::Duration::hours(UNLOCK_COOKIE_HOURS))
+                .finish(),
+        );
+    }
+    if html {
+        res.insert_header((header::CONTENT_SECURITY_POLICY, PAGE_CSP))
+            .content_type("text/html; charset=utf-8")
+            .body(render_page(&base, &link, &page))
+    } else {
+        res.json(page)
+    }
+}
+
+async fn find_link(pool: &DbPool, token: &str) -> Result<Link, ServiceError> {
+    let token_hash = hash_token(token);
+    let now = Utc::now().naive_utc();
+    sqlx::query_as!(
+        Link,
+        r#"
+        SELECT id, document_id, created_by, password_hash, include_subfolders
+        FROM share_links
+        WHERE token_hash = ? AND (expires_at IS NULL OR expires_at > ?)
+        "#,
+        token_hash,
+        now
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(ServiceError::BadRequest("Share link not found".into()))
+}
+
+fn unlock_cookie_name(link: &Link) -> String {
+    format!("adoc_share_{}", link.id.replace('-', ""))
+}
+
+/// Proof that the link's password was entered, kept in a cookie so visitors
+/// aren't asked again for every page of a shared folder. Only the server
+/// knows the stored hash, so the value can't be made up.
+fn unlock_value(link: &Link, password_hash: &str) -> String {
+    hash_token(&format!("{}:{}", link.id, password_hash))
+}
+
+/// Checks access to a protected link. Returns a new unlock cookie value
+/// when the password was just entered.
+async fn check_password(
+    pool: &DbPool,
+    req: &HttpRequest,
+    link: &Link,
+    password: Option<&str>,
+) -> Result<Option<String>, ServiceError> {
+    let Some(password_hash) = &link.password_hash else {
+        return Ok(None);
+    };
+    let unlocked = unlock_value(link, password_hash);
+    if req
+        .cookie(&unlock_cookie_name(link))
+        .is_some_and(|c| c.value() == unlocked)
+    {
+        return Ok(None);
+    }
+    let Some(password) = password else {
+        return Err(ServiceError::Unauthorized("Password required".into()));
+    };
+
+    throttle::check_share_link(pool, &link.id).await?;
+    if verify_password(password_hash, password).is_err() {
+        throttle::share_link_failed(pool, &link.id).await?;
+        return Err(ServiceError::Unauthorized("Invalid password".into()));
+    }
+    Ok(Some(unlocked))
+}
+
+/// Loads the shared document, or a document below a shared folder. The link
+/// stops working once its creator no longer owns the shared document, say
+/// after leaving the workspace.
+async fn load_page(
+    pool: &DbPool,
+    link: &Link,
+    doc_id: Option<&str>,
+) -> Result<SharedPage, ServiceError> {
+    let not_found = || ServiceError::BadRequest("Document not found".into());
+    let root = live_document(pool, &link.document_id)
+        .await?
+        .ok_or_else(not_found)?;
+    if role_of(pool, &root, &link.created_by).await? < Some(Role::Owner) {
+        return Err(ServiceError::BadRequest("Share link not found".into()));
+    }
+
+    let doc = match doc_id.filter(|id| *id != root.id) {
+        None => root,
+        Some(doc_id) => {
+            let doc = live_document(pool, doc_id).await?.ok_or_else(not_found)?;
+            let depth = depth_below(pool, &root.id, &doc.id).await?;
+            let in_scope = match depth {
+                Some(1) => link.include_subfolders || !doc.is_folder,
+                Some(_) => link.include_subfolders,
+                None => false,
+            };
+            // Only the same space, and in a personal space only the owner's
+            // documents, in case someone else's was filed into the folder.
+            let same_space = doc.workspace_id == root.workspace_id
+                && (root.workspace_id.is_some() || doc.owner_id == root.owner_id);
+            if !in_scope || !same_space {
+                return Err(not_found());
+            }
+            doc
+        }
+    };
+
+    let children = if doc.is_folder {
+        let children = sqlx::query_as!(
+            SharedChild,
+            r#"
+            SELECT id, title, is_folder, updated_at
+            FROM documents
+            WHERE parent_id = ?1 AND workspace_id IS ?2 AND (?2 IS NOT NULL OR owner_id = ?3)
+              AND deleted_at IS NULL AND (?4 OR is_folder = 0)
+            ORDER BY is_folder DESC, title ASC
+            "#,
+            doc.id,
+            doc.workspace_id,
+            doc.owner_id,
+            link.include_subfolders
+        )
+        .fetch_all(pool)
+        .await?;
+        Some(children)
+    } else {
+        None
+    };
+
+    Ok(SharedPage {
+        content: doc.content.as_deref().map(ammonia::clean),
+        id: doc.id,
+        title: doc.title,
+        is_folder: doc.is_folder,
+        updated_at: doc.updated_at,
+        children,
+    })
+}
+
+async fn live_document(pool: &DbPool, id: &str) -> Result<Option<Document>, ServiceError> {
+    Ok(sqlx::query_as!(
+        Document,
+        "SELECT * FROM documents WHERE id = ? AND deleted_at IS NULL",
+        id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// How many levels `doc_id` is below `root_id` through live folders, if it
+/// is below it at all.
+async fn depth_below(
+    pool: &DbPool,
+    root_id: &str,
+    doc_id: &str,
+) -> Result<Option<i64>, ServiceError> {
+    Ok(sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE up(id, parent_id, depth) AS (
+            SELECT id, parent_id, 0 FROM documents WHERE id = ?1
+            UNION ALL
+            SELECT d.id, d.parent_id, up.depth + 1
+            FROM documents d
+            JOIN up ON d.id = up.parent_id
+            WHERE d.deleted_at IS NULL AND up.depth < 100
+        )
+        SELECT depth AS "depth!: i64" FROM up WHERE id = ?2 LIMIT 1
+        "#,
+        doc_id,
+        root_id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+fn error_response(error: ServiceError, html: bool) -> HttpResponse {
+    use actix_web::ResponseError;
+
+    if !html {
+        return error.error_response();
+    }
+    let (status, message) = match &error {
+        ServiceError::BadRequest(_) => (
+            StatusCode::NOT_FOUND,
+            "This link is invalid or has expired.",
+        ),
+        ServiceError::TooManyRequests(..) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many attempts, try again later.",
+        ),
+        _ => (error.status_code(), "Something went wrong."),
+    };
+    let mut res = HttpResponse::build(status);
+    if let ServiceError::TooManyRequests(_, retry_after) = error {
+        res.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+    }
+    res.insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((header::REFERRER_POLICY, "no-referrer"))
+        .insert_header((header::CONTENT_SECURITY_POLICY, PAGE_CSP))
+        .content_type("text/html; charset=utf-8")
+        .body(html_page(
+            "Not available",
+            &format!("<h1>Not available</h1><p>{}</p>", message),
+        ))
+}
+
+fn password_page(base: &str, doc_id: Option<&str>, error: Option<&str>) -> HttpResponse {
+    let action = match doc_id {
+        Some(doc_id) => format!("{}/{}", base, ammonia::clean_text(doc_id)),
+        None => base.to_string(),
+    };
+    let error = error
+        .map(|e| format!("<p class=\"error\">{}</p>", ammonia::clean_text(e)))
+        .unwrap_or_default();
+    let body = format!(
+        "<h1>Password required</h1>{}<form method=\"post\" action=\"{}\">\
+         <input type=\"password\" name=\"password\" autofocus required>\
+         <button type=\"submit\">Open</button></form>",
+        error, action
+    );
+    HttpResponse::Unauthorized()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((header::REFERRER_POLICY, "no-referrer"))
+        .insert_header((header::CONTENT_SECURITY_POLICY, PAGE_CSP))
+        .content_type("text/html; charset=utf-8")
+        .body(html_page("Password required", &body))
+}
+
+fn render_page(base: &str, link: &Link, page: &SharedPage) -> String {
+    let title = ammonia::clean_text(&page.title);
+    let mut body = String::new();
+    if page.id != link.document_id {
+        body.push_str(&format!(
+            "<p class=\"meta\"><a href=\"{}\">↑ Back</a></p>",
+            base
+        ));
+    }
+    body.push_str(&format!(
+        "<h1>{}</h1><p class=\"meta\">Last updated {}</p>",
+        title,
+        page.updated_at.format("%Y-%m-%d %H:%M UTC")
+    ));
+    if let Some(content) = &page.content {
+        body.push_str(content);
+    }
+    if let Some(children) = &page.children {
+        if children.is_empty() {
+            body.push_str("<p class=\"meta\">This folder is empty.</p>");
+        } else {
+            body.push_str("<ul class=\"children\">");
+            for child in children {
+                body.push_str(&format!(
+                    "<li><a href=\"{}/{}\">{}{}</a></li>",
+                    base,
+                    ammonia::clean_text(&child.id),
+                    if child.is_folder { "📁 " } else { "" },
+                    ammonia::clean_text(&child.title)
+                ));
+            }
+            body.push_str("</ul>");
+        }
+    }
+    html_page(&title, &body)
+}
+
+/// Standalone page; `title` must already be escaped.
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <meta name=\"robots\" content=\"noindex\">\n<title>{}</title>\n<style>{}</style>\n\
+         </head>\n<body><main>{}</main></body>\n</html>\n",
+        title, PAGE_STYLE, body
+    )
+}
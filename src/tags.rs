@@ -1,33 +1,58 @@
+use crate::api_tokens::Scope;
+use crate::auth::get_user_id;
+use crate::errors::ServiceError;
+use crate::events::EventBus;
 use crate::models::tag::{CreateTagRequest, Tag};
-use actix_web::{get, post, web, HttpResponse, Responder};
+use crate::workspaces;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use sqlx::SqlitePool;
 use uuid::Uuid;
 
 #[get("/tags")]
-pub async fn list_tags(pool: web::Data<SqlitePool>) -> impl Responder {
+pub async fn list_tags(
+    pool: web::Data<SqlitePool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::TagsRead).await?;
+    let active = workspaces::active(&req, pool.get_ref(), &user_id).await?;
+
     let tags = sqlx::query_as!(
         Tag,
-        "SELECT id, name, created_at FROM tags ORDER BY name ASC"
+        "SELECT id, name, created_at FROM tags WHERE workspace_id IS ? ORDER BY name ASC",
+        active.id
     )
     .fetch_all(pool.get_ref())
     .await;
 
     match tags {
-        Ok(tags) => HttpResponse::Ok().json(tags),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+        Ok(tags) => Ok(HttpResponse::Ok().json(tags)),
+        Err(_) => Ok(HttpResponse::InternalServerError().finish()),
     }
 }
 
 #[post("/tags")]
 pub async fn create_tag(
     pool: web::Data<SqlitePool>,
+    bus: web::Data<EventBus>,
     req: web::Json<CreateTagRequest>,
-) -> impl Responder {
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&http_req, pool.get_ref(), Scope::TagsWrite).await?;
+    let active = workspaces::active(&http_req, pool.get_ref(), &user_id).await?;
+    if !active.can_create() {
+        return Err(ServiceError::Forbidden("Guests can't create tags".into()));
+    }
+
     let id = Uuid::new_v4().to_string();
 
-    let result = sqlx::query!("INSERT INTO tags (id, name) VALUES (?, ?)", id, req.name)
-        .execute(pool.get_ref())
-        .await;
+    let result = sqlx::query!(
+        "INSERT INTO tags (id, name, workspace_id) VALUES (?, ?, ?)",
+        id,
+        req.name,
+        active.id
+    )
+    .execute(pool.get_ref())
+    .await;
 
     match result {
         Ok(_) => {
@@ -40,16 +65,20 @@ pub async fn create_tag(
             .await;
 
             match tag {
-                Ok(tag) => HttpResponse::Ok().json(tag),
-                Err(_) => HttpResponse::InternalServerError().finish(),
+                Ok(tag) => {
+                    bus.tag(pool.get_ref(), "tag.created", &tag, active.id.as_deref())
+                        .await;
+                    Ok(HttpResponse::Ok().json(tag))
+                }
+                Err(_) => Ok(HttpResponse::InternalServerError().finish()),
             }
         }
         Err(e) => {
             // Check for unique constraint violation
             if e.to_string().contains("UNIQUE constraint failed") {
-                HttpResponse::BadRequest().body("Tag already exists")
+                Ok(HttpResponse::BadRequest().body("Tag already exists"))
             } else {
-                HttpResponse::InternalServerError().finish()
+                Ok(HttpResponse::InternalServerError().finish())
             }
         }
     }
@@ -0,0 +1,260 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{self, Event},
+    auth::require_admin,
+    db::DbPool,
+    errors::ServiceError,
+};
+
+/// Failed logins before a username is locked (`LOGIN_LOCKOUT_THRESHOLD`, default 5).
+fn username_threshold() -> i64 {
+    env_or("LOGIN_LOCKOUT_THRESHOLD", 5)
+}
+
+/// Failed logins before a client IP is locked (`LOGIN_IP_LOCKOUT_THRESHOLD`, default 20).
+fn ip_threshold() -> i64 {
+    env_or("LOGIN_IP_LOCKOUT_THRESHOLD", 20)
+}
+
+/// Registrations allowed per client IP within one lockout window
+/// (`REGISTER_RATE_LIMIT`, default 10).
+fn register_limit() -> i64 {
+    env_or("REGISTER_RATE_LIMIT", 10)
+}
+
+/// Password reset requests allowed per client IP within one lockout window
+/// (`PASSWORD_RESET_RATE_LIMIT`, default 5).
+fn password_reset_limit() -> i64 {
+    env_or("PASSWORD_RESET_RATE_LIMIT", 5)
+}
+
+/// How long a lockout lasts, and how long failures are remembered
+/// (`LOGIN_LOCKOUT_MINUTES`, default 15).
+fn lockout_duration() -> Duration {
+    Duration::minutes(env_or("LOGIN_LOCKOUT_MINUTES", 15))
+}
+
+fn env_or(name: &str, default: i64) -> i64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn username_key(username: &str) -> String {
+    format!("user:{}", username.trim().to_lowercase())
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+fn register_key(ip: &str) -> String {
+    format!("register:{}", ip)
+}
+
+fn password_reset_key(ip: &str) -> String {
+    format!("reset:{}", ip)
+}
+
+fn share_link_key(link_id: &str) -> String {
+    format!("share:{}", link_id)
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginAttempt {
+    pub key: String,
+    pub failures: i64,
+    pub last_failure_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClearLockoutRequest {
+    pub username: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Rejects the request with 429 while any of `keys` is backing off or locked.
+async fn check(pool: &DbPool, keys: &[String]) -> Result<(), ServiceError> {
+    let now = Utc::now().naive_utc();
+
+    for key in keys {
+        let locked_until =
+            sqlx::query_scalar!("SELECT locked_until FROM login_attempts WHERE key = ?", key)
+                .fetch_optional(pool)
+                .await?
+                .flatten();
+
+        if let Some(locked_until) = locked_until.filter(|until| *until > now) {
+            return Err(ServiceError::TooManyRequests(
+                "Too many attempts, try again later".into(),
+                (locked_until - now).num_seconds() + 1,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts a failure for `key`. Below `threshold` the next attempt is delayed
+/// exponentially (1s, 2s, 4s, ...); at `threshold` the key is locked for the
+/// lockout duration. Failures older than that window are forgotten.
+async fn record_failure(
+    pool: &DbPool,
+    key: &str,
+    threshold: i64,
+    backoff: bool,
+) -> Result<(), ServiceError> {
+    let now = Utc::now().naive_utc();
+    let window = lockout_duration();
+
+    let previous = sqlx::query!(
+        "SELECT failures, last_failure_at FROM login_attempts WHERE key = ?",
+        key
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let failures = match previous {
+        Some(row) if row.last_failure_at + window > now => row.failures + 1,
+        _ => 1,
+    };
+
+    let locked_until = if failures >= threshold {
+        Some(now + window)
+    } else if backoff {
+        let delay = Duration::seconds(1 << (failures - 1).min(20));
+        Some(now + delay.min(window))
+    } else {
+        None
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO login_attempts (key, failures, last_failure_at, locked_until)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT(key) DO UPDATE SET
+            failures = excluded.failures,
+            last_failure_at = excluded.last_failure_at,
+            locked_until = excluded.locked_until
+        "#,
+        key,
+        failures,
+        now,
+        locked_until
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn check_login(pool: &DbPool, username: &str, ip: &str) -> Result<(), ServiceError> {
+    check(pool, &[username_key(username), ip_key(ip)]).await
+}
+
+pub async fn login_failed(pool: &DbPool, username: &str, ip: &str) -> Result<(), ServiceError> {
+    record_failure(pool, &username_key(username), username_threshold(), true).await?;
+    // Backing off per IP too keeps a client spraying many usernames from
+    // getting every guess at full speed up to the IP threshold.
+    record_failure(pool, &ip_key(ip), ip_threshold(), true).await
+}
+
+/// Clears the username counter. The IP counter is left alone so that one
+/// valid account can't be used to reset the budget for guessing others.
+pub async fn login_succeeded(pool: &DbPool, username: &str) -> Result<(), ServiceError> {
+    let key = username_key(username);
+    sqlx::query!("DELETE FROM login_attempts WHERE key = ?", key)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Rate-limits registrations per client IP.
+pub async fn check_register(pool: &DbPool, ip: &str) -> Result<(), ServiceError> {
+    let key = register_key(ip);
+    check(pool, std::slice::from_ref(&key)).await?;
+    record_failure(pool, &key, register_limit(), false).await
+}
+
+/// Rate-limits password reset requests per client IP.
+pub async fn check_password_reset(pool: &DbPool, ip: &str) -> Result<(), ServiceError> {
+    let key = password_reset_key(ip);
+    check(pool, std::slice::from_ref(&key)).await?;
+    record_failure(pool, &key, password_reset_limit(), false).await
+}
+
+pub async fn check_share_link(pool: &DbPool, link_id: &str) -> Result<(), ServiceError> {
+    check(pool, &[share_link_key(link_id)]).await
+}
+
+/// Counts a wrong password for a share link. Visitors are anonymous, so the
+/// link itself backs off like a username does.
+pub async fn share_link_failed(pool: &DbPool, link_id: &str) -> Result<(), ServiceError> {
+    record_failure(pool, &share_link_key(link_id), username_threshold(), true).await
+}
+
+#[get("/admin/lockouts")]
+pub async fn list_lockouts(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    require_admin(&req, pool.get_ref()).await?;
+
+    let attempts = sqlx::query_as!(
+        LoginAttempt,
+        "SELECT key, failures, last_failure_at, locked_until FROM login_attempts ORDER BY last_failure_at DESC"
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(attempts))
+}
+
+#[post("/admin/lockouts/clear")]
+pub async fn clear_lockout(
+    pool: web::Data<DbPool>,
+    req: web::Json<ClearLockoutRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin_id = require_admin(&http_req, pool.get_ref()).await?;
+
+    let mut keys = Vec::new();
+    if let Some(username) = &req.username {
+        keys.push(username_key(username));
+    }
+    if let Some(ip) = &req.ip {
+        keys.push(ip_key(ip));
+        keys.push(register_key(ip));
+        keys.push(password_reset_key(ip));
+    }
+    if keys.is_empty() {
+        return Err(ServiceError::BadRequest(
+            "Provide a username or an ip".into(),
+        ));
+    }
+
+    let mut cleared = 0;
+    for key in keys {
+        cleared += sqlx::query!("DELETE FROM login_attempts WHERE key = ?", key)
+            .execute(pool.get_ref())
+            .await?
+            .rows_affected();
+    }
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("admin.lockout_clear").actor(&admin_id).details(
+            serde_json::json!({"username": req.username, "ip": req.ip, "cleared": cleared}),
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok()
+        .json(serde_json::json!({"message": "Lockout cleared", "cleared": cleared})))
+}
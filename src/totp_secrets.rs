@@ -0,0 +1,173 @@
+//! Encryption of TOTP secrets at rest.
+//!
+//! Secrets are sealed with AES-256-GCM under `TOTP_ENCRYPTION_KEY` and stored
+//! as `v1:` followed by the base64 nonce and ciphertext. The user id is bound
+//! in as associated data, so a secret copied to another row doesn't open.
+//! Secrets stored before encryption was introduced are plain base32; they
+//! keep working and are sealed at startup.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+use crate::{db::DbPool, errors::ServiceError, jwt_keys::is_production};
+
+const DEV_FALLBACK_KEY: &str = "dev_fallback_totp_key_change_me";
+
+const PREFIX: &str = "v1:";
+
+static KEY: OnceLock<SecretKey> = OnceLock::new();
+
+struct SecretKey(LessSafeKey);
+
+impl SecretKey {
+    fn new(bytes: &[u8]) -> Result<SecretKey, String> {
+        let key = UnboundKey::new(&AES_256_GCM, bytes)
+            .map_err(|_| "TOTP_ENCRYPTION_KEY must be 32 bytes".to_string())?;
+        Ok(SecretKey(LessSafeKey::new(key)))
+    }
+
+    /// The base64 key in `TOTP_ENCRYPTION_KEY`, required with
+    /// `APP_ENV=production`; a fixed development key otherwise.
+    fn from_env() -> Result<SecretKey, String> {
+        match std::env::var("TOTP_ENCRYPTION_KEY") {
+            Ok(encoded) => {
+                let bytes = STANDARD
+                    .decode(encoded.trim())
+                    .map_err(|_| "TOTP_ENCRYPTION_KEY must be base64".to_string())?;
+                SecretKey::new(&bytes)
+            }
+            Err(_) if is_production() => {
+                Err("APP_ENV=production requires TOTP_ENCRYPTION_KEY".into())
+            }
+            Err(_) => {
+                eprintln!(
+                    "⚠️  No TOTP encryption key configured, using the development fallback key"
+                );
+                SecretKey::new(&Sha256::digest(DEV_FALLBACK_KEY.as_bytes()))
+            }
+        }
+    }
+
+    fn seal(&self, user_id: &str, secret: &str) -> String {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let mut data = secret.as_bytes().to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(user_id.as_bytes()),
+                &mut data,
+            )
+            .expect("AES-GCM input fits");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(data);
+        format!("{}{}", PREFIX, STANDARD.encode(sealed))
+    }
+
+    fn open(&self, user_id: &str, stored: &str) -> Option<String> {
+        let Some(encoded) = stored.strip_prefix(PREFIX) else {
+            return Some(stored.to_string());
+        };
+        let mut data = STANDARD.decode(encoded).ok()?;
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let mut ciphertext = data.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&data).ok()?;
+        let plain = self
+            .0
+            .open_in_place(nonce, Aad::from(user_id.as_bytes()), &mut ciphertext)
+            .ok()?;
+        String::from_utf8(plain.to_vec()).ok()
+    }
+}
+
+/// Loads the key from the environment. Called once at startup so that a
+/// misconfiguration stops the server instead of failing every 2FA login.
+pub fn init() -> Result<(), String> {
+    let key = SecretKey::from_env()?;
+    KEY.set(key)
+        .map_err(|_| "TOTP encryption key already initialised".to_string())
+}
+
+fn key() -> &'static SecretKey {
+    KEY.get().expect("totp_secrets::init must run at startup")
+}
+
+/// Encrypts `user_id`'s secret for storage.
+pub fn seal(user_id: &str, secret: &str) -> String {
+    key().seal(user_id, secret)
+}
+
+/// Decrypts a stored secret; plaintext ones are returned as they are.
+pub fn open(user_id: &str, stored: &str) -> Result<String, ServiceError> {
+    key().open(user_id, stored).ok_or_else(|| {
+        eprintln!("Failed to decrypt the TOTP secret of {}", user_id);
+        ServiceError::InternalServerError
+    })
+}
+
+/// Encrypts the secrets stored in plaintext. Returns how many there were.
+pub async fn seal_plaintext(pool: &DbPool) -> Result<usize, ServiceError> {
+    let users = sqlx::query!(
+        r#"SELECT id AS "id!", totp_secret AS "totp_secret!" FROM users WHERE totp_secret IS NOT NULL AND totp_secret NOT LIKE 'v1:%'"#
+    )
+    .fetch_all(pool)
+    .await?;
+    for user in &users {
+        let sealed = seal(&user.id, &user.totp_secret);
+        // Only if it is still the same plaintext, in case of a concurrent
+        // enrollment.
+        sqlx::query!(
+            "UPDATE users SET totp_secret = ? WHERE id = ? AND totp_secret = ?",
+            sealed,
+            user.id,
+            user.totp_secret
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(users.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> SecretKey {
+        SecretKey::new(&[7; 32]).unwrap()
+    }
+
+    #[test]
+    fn sealed_secrets_open_for_their_user_only() {
+        let key = key();
+        let sealed = key.seal("alice", "JBSWY3DPEHPK3PXP");
+        assert!(sealed.starts_with(PREFIX));
+        assert!(!sealed.contains("JBSWY3DPEHPK3PXP"));
+        assert_ne!(sealed, key.seal("alice", "JBSWY3DPEHPK3PXP"));
+
+        assert_eq!(
+            key.open("alice", &sealed).as_deref(),
+            Some("JBSWY3DPEHPK3PXP")
+        );
+        assert_eq!(key.open("bob", &sealed), None);
+        assert_eq!(
+            SecretKey::new(&[8; 32]).unwrap().open("alice", &sealed),
+            None
+        );
+    }
+
+    #[test]
+    fn plaintext_secrets_are_still_read() {
+        assert_eq!(
+            key().open("alice", "JBSWY3DPEHPK3PXP").as_deref(),
+            Some("JBSWY3DPEHPK3PXP")
+        );
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes() {
+        assert!(SecretKey::new(&[7; 16]).is_err());
+    }
+}
@@ -0,0 +1,413 @@
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{
+        client_ip, complete_login, generate_token, get_session_user_id, hash_token, verify_password,
+    },
+    db::DbPool,
+    errors::ServiceError,
+    throttle, totp_secrets,
+};
+
+const TOTP_ISSUER: &str = "actix-doc";
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+const MAX_CHALLENGE_ATTEMPTS: i64 = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactorRequest {
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EnrollResponse {
+    secret: String,
+    otpauth_url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+fn build_totp(secret: &str, username: &str) -> Result<TOTP, ServiceError> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|_| ServiceError::InternalServerError)?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        1,
+        TOTP_STEP,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        username.to_string(),
+    )
+    .map_err(|_| ServiceError::InternalServerError)
+}
+
+/// Returns the time step `code` belongs to, allowing one step of clock skew
+/// in either direction.
+fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let now = Utc::now().timestamp() as u64;
+    let current = now / TOTP_STEP;
+
+    [current - 1, current, current + 1]
+        .into_iter()
+        .find(|step| totp.generate(step * TOTP_STEP) == code)
+        .map(|step| step as i64)
+}
+
+fn generate_recovery_code() -> String {
+    let code = generate_token();
+    format!(
+        "{}-{}-{}-{}",
+        &code[0..4],
+        &code[4..8],
+        &code[8..12],
+        &code[12..16]
+    )
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.trim().replace('-', "").to_lowercase()
+}
+
+pub async fn is_enabled(pool: &DbPool, user_id: &str) -> Result<bool, ServiceError> {
+    let enabled = sqlx::query_scalar!("SELECT totp_enabled FROM users WHERE id = ?", user_id)
+        .fetch_optional(pool)
+        .await?
+        .unwrap_or(false);
+    Ok(enabled)
+}
+
+/// Creates a short-lived challenge for a user that passed the password step.
+pub async fn start_challenge(pool: &DbPool, user_id: &str) -> Result<String, ServiceError> {
+    let id = Uuid::new_v4().to_string();
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+    let expires_at = (Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES)).naive_utc();
+
+    sqlx::query!(
+        "INSERT INTO login_challenges (id, user_id, token_hash, expires_at) VALUES (?, ?, ?, ?)",
+        id,
+        user_id,
+        token_hash,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Checks a TOTP code or an unused recovery code for `user_id`. TOTP codes
+/// cannot be replayed and recovery codes are consumed on success.
+async fn verify_second_factor(
+    pool: &DbPool,
+    user_id: &str,
+    code: &str,
+) -> Result<bool, ServiceError> {
+    let user = sqlx::query!(
+        "SELECT username, totp_secret FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let secret = match user.totp_secret {
+        Some(secret) => totp_secrets::open(user_id, &secret)?,
+        None => return Ok(false),
+    };
+
+    let code = code.trim();
+    if code.len() == TOTP_DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        let totp = build_totp(&secret, &user.username)?;
+        let step = match matching_step(&totp, code) {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+        // Only move forward, so a code cannot be used twice even by two
+        // concurrent requests.
+        let accepted = sqlx::query!(
+            "UPDATE users SET totp_last_step = ? WHERE id = ? AND (totp_last_step IS NULL OR totp_last_step < ?)",
+            step,
+            user_id,
+            step
+        )
+        .execute(pool)
+        .await?;
+        return Ok(accepted.rows_affected() == 1);
+    }
+
+    let code_hash = hash_token(&normalize_recovery_code(code));
+    let now = Utc::now().naive_utc();
+    let used = sqlx::query!(
+        "UPDATE recovery_codes SET used_at = ? WHERE user_id = ? AND code_hash = ? AND used_at IS NULL",
+        now,
+        user_id,
+        code_hash
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(used.rows_affected() == 1)
+}
+
+#[post("/auth/2fa/enroll")]
+pub async fn enroll(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+
+    let user = sqlx::query!(
+        "SELECT username, totp_enabled FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    if user.totp_enabled {
+        return Err(ServiceError::BadRequest(
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+
+    // The secret stays pending until it is confirmed with a first code.
+    let secret = Secret::generate_secret().to_encoded().to_string();
+    let totp = build_totp(&secret, &user.username)?;
+    let sealed = totp_secrets::seal(&user_id, &secret);
+
+    sqlx::query!(
+        "UPDATE users SET totp_secret = ?, totp_last_step = NULL WHERE id = ?",
+        sealed,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(EnrollResponse {
+        otpauth_url: totp.get_url(),
+        secret,
+    }))
+}
+
+#[post("/auth/2fa/confirm")]
+pub async fn confirm(
+    pool: web::Data<DbPool>,
+    req: web::Json<TotpCodeRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&http_req, pool.get_ref()).await?;
+
+    let user = sqlx::query!(
+        "SELECT username, totp_secret, totp_enabled FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    if user.totp_enabled {
+        return Err(ServiceError::BadRequest(
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+    let secret = user.totp_secret.ok_or(ServiceError::BadRequest(
+        "Start enrollment before confirming".into(),
+    ))?;
+    let secret = totp_secrets::open(&user_id, &secret)?;
+
+    let totp = build_totp(&secret, &user.username)?;
+    let step = matching_step(&totp, req.code.trim())
+        .ok_or(ServiceError::BadRequest("Invalid code".into()))?;
+
+    let mut recovery_codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE users SET totp_enabled = 1, totp_last_step = ? WHERE id = ?",
+        step,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code = generate_recovery_code();
+        let id = Uuid::new_v4().to_string();
+        let code_hash = hash_token(&normalize_recovery_code(&code));
+        sqlx::query!(
+            "INSERT INTO recovery_codes (id, user_id, code_hash) VALUES (?, ?, ?)",
+            id,
+            user_id,
+            code_hash
+        )
+        .execute(&mut *tx)
+        .await?;
+        recovery_codes.push(code);
+    }
+
+    tx.commit().await?;
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("auth.2fa_enable")
+            .actor(&user_id)
+            .target("user", &user_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[post("/auth/2fa/disable")]
+pub async fn disable(
+    pool: web::Data<DbPool>,
+    req: web::Json<DisableTwoFactorRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&http_req, pool.get_ref()).await?;
+    let ip = client_ip(&http_req);
+
+    let user = sqlx::query!(
+        "SELECT username, password_hash FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+    // Guessing the password here counts like guessing it at login.
+    throttle::check_login(pool.get_ref(), &user.username, &ip).await?;
+    if let Err(e) = verify_password(&user.password_hash, &req.password) {
+        throttle::login_failed(pool.get_ref(), &user.username, &ip).await?;
+        return Err(e);
+    }
+
+    if !is_enabled(pool.get_ref(), &user_id).await? {
+        return Err(ServiceError::BadRequest(
+            "Two-factor authentication is not enabled".into(),
+        ));
+    }
+    if !verify_second_factor(pool.get_ref(), &user_id, &req.code).await? {
+        return Err(ServiceError::BadRequest("Invalid code".into()));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE users SET totp_secret = NULL, totp_enabled = 0, totp_last_step = NULL WHERE id = ?",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("auth.2fa_disable")
+            .actor(&user_id)
+            .target("user", &user_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok()
+        .json(serde_json::json!({"message": "Two-factor authentication disabled"})))
+}
+
+#[post("/auth/login/2fa")]
+pub async fn login_2fa(
+    pool: web::Data<DbPool>,
+    req: web::Json<TwoFactorLoginRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let ip = client_ip(&http_req);
+    let token_hash = hash_token(&req.challenge_token);
+    let now = Utc::now().naive_utc();
+
+    let challenge = sqlx::query!(
+        r#"
+        SELECT c.id, c.user_id, c.attempts, c.expires_at, u.username
+        FROM login_challenges c
+        JOIN users u ON u.id = c.user_id
+        WHERE c.token_hash = ?
+        "#,
+        token_hash
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::Unauthorized("Invalid challenge".into()))?;
+
+    if challenge.expires_at < now || challenge.attempts >= MAX_CHALLENGE_ATTEMPTS {
+        sqlx::query!("DELETE FROM login_challenges WHERE id = ?", challenge.id)
+            .execute(pool.get_ref())
+            .await?;
+        return Err(ServiceError::Unauthorized("Challenge expired".into()));
+    }
+
+    throttle::check_login(pool.get_ref(), &challenge.username, &ip).await?;
+
+    if !verify_second_factor(pool.get_ref(), &challenge.user_id, &req.code).await? {
+        sqlx::query!(
+            "UPDATE login_challenges SET attempts = attempts + 1 WHERE id = ?",
+            challenge.id
+        )
+        .execute(pool.get_ref())
+        .await?;
+        audit::record(
+            pool.get_ref(),
+            &http_req,
+            Event::new("auth.login_failed")
+                .target("user", &challenge.user_id)
+                .details(serde_json::json!({
+                    "username": challenge.username,
+                    "reason": "invalid_second_factor",
+                })),
+        )
+        .await;
+        throttle::login_failed(pool.get_ref(), &challenge.username, &ip).await?;
+        return Err(ServiceError::BadRequest("Invalid code".into()));
+    }
+    sqlx::query!("DELETE FROM login_challenges WHERE id = ?", challenge.id)
+        .execute(pool.get_ref())
+        .await?;
+
+    complete_login(
+        pool.get_ref(),
+        &http_req,
+        &challenge.user_id,
+        challenge.username,
+        "totp",
+    )
+    .await
+}
@@ -0,0 +1,637 @@
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, NaiveDateTime, Utc};
+use ciborium::value::Value;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Event},
+    auth::{complete_login, get_session_user_id},
+    db::DbPool,
+    errors::ServiceError,
+};
+
+const RP_NAME: &str = "actix-doc";
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+
+const CEREMONY_REGISTRATION: &str = "registration";
+const CEREMONY_AUTHENTICATION: &str = "authentication";
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+const COSE_ALG_ES256: i64 = -7;
+const COSE_ALG_EDDSA: i64 = -8;
+const COSE_ALG_RS256: i64 = -257;
+
+/// Relying party id credentials are scoped to (`WEBAUTHN_RP_ID`, default `localhost`).
+fn rp_id() -> String {
+    std::env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// Origin the browser has to report in client data (`WEBAUTHN_ORIGIN`).
+fn rp_origin() -> String {
+    std::env::var("WEBAUTHN_ORIGIN").unwrap_or_else(|_| "http://localhost:8080".to_string())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FinishRegistrationRequest {
+    pub challenge_id: String,
+    pub name: Option<String>,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(rename = "userHandle")]
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartLoginRequest {
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FinishLoginRequest {
+    pub challenge_id: String,
+    pub credential: AssertionCredential,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialInfo {
+    pub id: String,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Parsed `authenticatorData` as laid out in the WebAuthn spec, section 6.1.
+pub struct AuthenticatorData {
+    pub rp_id_hash: Vec<u8>,
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential: Option<AttestedCredential>,
+}
+
+pub struct AttestedCredential {
+    pub credential_id: Vec<u8>,
+    /// COSE_Key encoded public key.
+    pub public_key: Vec<u8>,
+}
+
+fn b64_decode(value: &str) -> Result<Vec<u8>, ServiceError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| ServiceError::BadRequest("Invalid base64url data".into()))
+}
+
+fn invalid(message: &str) -> ServiceError {
+    ServiceError::BadRequest(message.into())
+}
+
+fn verify_client_data(
+    raw: &[u8],
+    expected_type: &str,
+    challenge: &str,
+) -> Result<(), ServiceError> {
+    let client_data: ClientData =
+        serde_json::from_slice(raw).map_err(|_| invalid("Invalid client data"))?;
+
+    if client_data.ceremony_type != expected_type {
+        return Err(invalid("Unexpected ceremony type"));
+    }
+    if client_data.challenge.trim_end_matches('=') != challenge {
+        return Err(invalid("Challenge mismatch"));
+    }
+    if client_data.origin != rp_origin() {
+        return Err(invalid("Origin mismatch"));
+    }
+    Ok(())
+}
+
+pub fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData, ServiceError> {
+    if data.len() < 37 {
+        return Err(invalid("Authenticator data too short"));
+    }
+
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        let rest = &data[37..];
+        if rest.len() < 18 {
+            return Err(invalid("Attested credential data too short"));
+        }
+        // 16 bytes of AAGUID, then a big-endian length and the credential id.
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        let rest = &rest[18..];
+        if rest.len() < id_len {
+            return Err(invalid("Attested credential data too short"));
+        }
+        let credential_id = rest[..id_len].to_vec();
+
+        let key: Value = ciborium::de::from_reader(&rest[id_len..])
+            .map_err(|_| invalid("Invalid credential public key"))?;
+        let mut public_key = Vec::new();
+        ciborium::ser::into_writer(&key, &mut public_key)
+            .map_err(|_| ServiceError::InternalServerError)?;
+
+        Some(AttestedCredential {
+            credential_id,
+            public_key,
+        })
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: data[..32].to_vec(),
+        flags,
+        sign_count,
+        attested_credential,
+    })
+}
+
+fn check_relying_party(auth_data: &AuthenticatorData) -> Result<(), ServiceError> {
+    if auth_data.rp_id_hash != Sha256::digest(rp_id().as_bytes()).as_slice() {
+        return Err(invalid("Relying party mismatch"));
+    }
+    if auth_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err(invalid("User presence is required"));
+    }
+    Ok(())
+}
+
+fn cose_param(key: &[(Value, Value)], label: i64) -> Option<&Value> {
+    key.iter()
+        .find(|(k, _)| {
+            k.as_integer()
+                .is_some_and(|k| i128::from(k) == i128::from(label))
+        })
+        .map(|(_, v)| v)
+}
+
+fn cose_bytes(key: &[(Value, Value)], label: i64) -> Result<&[u8], ServiceError> {
+    cose_param(key, label)
+        .and_then(Value::as_bytes)
+        .map(Vec::as_slice)
+        .ok_or_else(|| invalid("Malformed credential public key"))
+}
+
+fn cose_algorithm(key: &[(Value, Value)]) -> Option<i64> {
+    cose_param(key, 3)
+        .and_then(Value::as_integer)
+        .and_then(|alg| i64::try_from(alg).ok())
+}
+
+fn decode_cose_key(cose_key: &[u8]) -> Result<Vec<(Value, Value)>, ServiceError> {
+    let value: Value = ciborium::de::from_reader(cose_key)
+        .map_err(|_| invalid("Malformed credential public key"))?;
+    value
+        .into_map()
+        .map_err(|_| invalid("Malformed credential public key"))
+}
+
+/// Verifies `signature` over `message` with a COSE_Key encoded public key.
+/// Supports ES256, EdDSA (Ed25519) and RS256.
+pub fn verify_signature(
+    cose_key: &[u8],
+    message: &[u8],
+    signature_bytes: &[u8],
+) -> Result<(), ServiceError> {
+    let key = decode_cose_key(cose_key)?;
+    let bad_signature = |_| ServiceError::Unauthorized("Invalid signature".into());
+
+    match cose_algorithm(&key) {
+        Some(COSE_ALG_ES256) => {
+            let mut point = vec![0x04];
+            point.extend_from_slice(cose_bytes(&key, -2)?);
+            point.extend_from_slice(cose_bytes(&key, -3)?);
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                .verify(message, signature_bytes)
+                .map_err(bad_signature)
+        }
+        Some(COSE_ALG_EDDSA) => UnparsedPublicKey::new(&signature::ED25519, cose_bytes(&key, -2)?)
+            .verify(message, signature_bytes)
+            .map_err(bad_signature),
+        Some(COSE_ALG_RS256) => RsaPublicKeyComponents {
+            n: cose_bytes(&key, -1)?,
+            e: cose_bytes(&key, -2)?,
+        }
+        .verify(
+            &signature::RSA_PKCS1_2048_8192_SHA256,
+            message,
+            signature_bytes,
+        )
+        .map_err(bad_signature),
+        _ => Err(invalid("Unsupported credential algorithm")),
+    }
+}
+
+async fn store_challenge(
+    pool: &DbPool,
+    user_id: Option<&str>,
+    ceremony: &str,
+) -> Result<(String, String), ServiceError> {
+    let id = Uuid::new_v4().to_string();
+    let challenge = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    let expires_at = (Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES)).naive_utc();
+
+    sqlx::query!(
+        "INSERT INTO webauthn_challenges (id, user_id, challenge, ceremony, expires_at) VALUES (?, ?, ?, ?, ?)",
+        id,
+        user_id,
+        challenge,
+        ceremony,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+
+    Ok((id, challenge))
+}
+
+/// Consumes a pending challenge, returning the user it was bound to (if any)
+/// and the challenge value.
+async fn take_challenge(
+    pool: &DbPool,
+    id: &str,
+    ceremony: &str,
+) -> Result<(Option<String>, String), ServiceError> {
+    let stored = sqlx::query!(
+        "SELECT user_id, challenge, expires_at FROM webauthn_challenges WHERE id = ? AND ceremony = ?",
+        id,
+        ceremony
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(invalid("Unknown challenge"))?;
+
+    sqlx::query!("DELETE FROM webauthn_challenges WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+
+    if stored.expires_at < Utc::now().naive_utc() {
+        return Err(invalid("Challenge expired"));
+    }
+
+    Ok((stored.user_id, stored.challenge))
+}
+
+fn public_key_params() -> serde_json::Value {
+    serde_json::json!([
+        {"type": "public-key", "alg": COSE_ALG_ES256},
+        {"type": "public-key", "alg": COSE_ALG_EDDSA},
+        {"type": "public-key", "alg": COSE_ALG_RS256},
+    ])
+}
+
+#[post("/auth/webauthn/register/start")]
+pub async fn start_registration(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+
+    let username = sqlx::query_scalar!("SELECT username FROM users WHERE id = ?", user_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    let existing = sqlx::query_scalar!(
+        "SELECT credential_id FROM webauthn_credentials WHERE user_id = ?",
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let (challenge_id, challenge) =
+        store_challenge(pool.get_ref(), Some(&user_id), CEREMONY_REGISTRATION).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "challenge_id": challenge_id,
+        "publicKey": {
+            "challenge": challenge,
+            "rp": {"id": rp_id(), "name": RP_NAME},
+            "user": {
+                "id": URL_SAFE_NO_PAD.encode(user_id.as_bytes()),
+                "name": username,
+                "displayName": username,
+            },
+            "pubKeyCredParams": public_key_params(),
+            "timeout": CHALLENGE_TTL_MINUTES * 60 * 1000,
+            "attestation": "none",
+            "excludeCredentials": existing
+                .iter()
+                .map(|id| serde_json::json!({"type": "public-key", "id": id}))
+                .collect::<Vec<_>>(),
+            "authenticatorSelection": {
+                "residentKey": "preferred",
+                "userVerification": "preferred",
+            },
+        },
+    })))
+}
+
+#[post("/auth/webauthn/register/finish")]
+pub async fn finish_registration(
+    pool: web::Data<DbPool>,
+    req: web::Json<FinishRegistrationRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&http_req, pool.get_ref()).await?;
+
+    let (challenge_user, challenge) =
+        take_challenge(pool.get_ref(), &req.challenge_id, CEREMONY_REGISTRATION).await?;
+    if challenge_user.as_deref() != Some(user_id.as_str()) {
+        return Err(invalid("Unknown challenge"));
+    }
+
+    let client_data = b64_decode(&req.credential.response.client_data_json)?;
+    verify_client_data(&client_data, "webauthn.create", &challenge)?;
+
+    // Only "none" attestation is requested, so the attestation statement is
+    // not verified; the authenticator data carries everything we keep.
+    let attestation: Value = ciborium::de::from_reader(
+        b64_decode(&req.credential.response.attestation_object)?.as_slice(),
+    )
+    .map_err(|_| invalid("Invalid attestation object"))?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|map| {
+            map.iter()
+                .find(|(k, _)| k.as_text() == Some("authData"))
+                .and_then(|(_, v)| v.as_bytes())
+        })
+        .ok_or(invalid("Invalid attestation object"))?;
+
+    let auth_data = parse_authenticator_data(auth_data)?;
+    check_relying_party(&auth_data)?;
+    let attested = auth_data
+        .attested_credential
+        .ok_or(invalid("Missing attested credential data"))?;
+
+    let credential_id = URL_SAFE_NO_PAD.encode(&attested.credential_id);
+    if credential_id != req.credential.id.trim_end_matches('=') {
+        return Err(invalid("Credential id mismatch"));
+    }
+    let algorithm = cose_algorithm(&decode_cose_key(&attested.public_key)?);
+    if !matches!(
+        algorithm,
+        Some(COSE_ALG_ES256 | COSE_ALG_EDDSA | COSE_ALG_RS256)
+    ) {
+        return Err(invalid("Unsupported credential algorithm"));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let name = req
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("Passkey")
+        .to_string();
+    let sign_count = auth_data.sign_count as i64;
+
+    let result = sqlx::query!(
+        "INSERT INTO webauthn_credentials (id, user_id, credential_id, public_key, sign_count, name) VALUES (?, ?, ?, ?, ?, ?)",
+        id,
+        user_id,
+        credential_id,
+        attested.public_key,
+        sign_count,
+        name
+    )
+    .execute(pool.get_ref())
+    .await;
+
+    if let Err(e) = result {
+        if e.to_string().contains("UNIQUE constraint failed") {
+            return Err(invalid("Credential already registered"));
+        }
+        return Err(e.into());
+    }
+
+    let created = sqlx::query_as!(
+        CredentialInfo,
+        "SELECT id, name, created_at, last_used_at FROM webauthn_credentials WHERE id = ?",
+        id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &http_req,
+        Event::new("webauthn.register")
+            .actor(&user_id)
+            .target("webauthn_credential", &id)
+            .details(serde_json::json!({"name": name})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(created))
+}
+
+#[post("/auth/webauthn/login/start")]
+pub async fn start_login(
+    pool: web::Data<DbPool>,
+    req: web::Json<StartLoginRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    // Without a username the browser offers discoverable credentials. An
+    // unknown username simply yields an empty list so accounts can't be probed.
+    let allowed = match &req.username {
+        Some(username) => {
+            sqlx::query_scalar!(
+                r#"
+                SELECT c.credential_id
+                FROM webauthn_credentials c
+                JOIN users u ON u.id = c.user_id
+                WHERE u.username = ?
+                "#,
+                username
+            )
+            .fetch_all(pool.get_ref())
+            .await?
+        }
+        None => Vec::new(),
+    };
+
+    let (challenge_id, challenge) =
+        store_challenge(pool.get_ref(), None, CEREMONY_AUTHENTICATION).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "challenge_id": challenge_id,
+        "publicKey": {
+            "challenge": challenge,
+            "rpId": rp_id(),
+            "timeout": CHALLENGE_TTL_MINUTES * 60 * 1000,
+            "userVerification": "preferred",
+            "allowCredentials": allowed
+                .iter()
+                .map(|id| serde_json::json!({"type": "public-key", "id": id}))
+                .collect::<Vec<_>>(),
+        },
+    })))
+}
+
+#[post("/auth/webauthn/login/finish")]
+pub async fn finish_login(
+    pool: web::Data<DbPool>,
+    req: web::Json<FinishLoginRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let (_, challenge) =
+        take_challenge(pool.get_ref(), &req.challenge_id, CEREMONY_AUTHENTICATION).await?;
+
+    let credential_id = URL_SAFE_NO_PAD.encode(b64_decode(&req.credential.id)?);
+    let credential = sqlx::query!(
+        r#"
+        SELECT c.id, c.user_id, c.public_key, c.sign_count, u.username
+        FROM webauthn_credentials c
+        JOIN users u ON u.id = c.user_id
+        WHERE c.credential_id = ?
+        "#,
+        credential_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::Unauthorized("Unknown credential".into()))?;
+
+    let response = &req.credential.response;
+    let client_data = b64_decode(&response.client_data_json)?;
+    verify_client_data(&client_data, "webauthn.get", &challenge)?;
+
+    let raw_auth_data = b64_decode(&response.authenticator_data)?;
+    let auth_data = parse_authenticator_data(&raw_auth_data)?;
+    check_relying_party(&auth_data)?;
+
+    if let Some(user_handle) = &response.user_handle {
+        if b64_decode(user_handle)? != credential.user_id.as_bytes() {
+            return Err(ServiceError::Unauthorized("User handle mismatch".into()));
+        }
+    }
+
+    let mut message = raw_auth_data.clone();
+    message.extend_from_slice(&Sha256::digest(&client_data));
+    verify_signature(
+        &credential.public_key,
+        &message,
+        &b64_decode(&response.signature)?,
+    )?;
+
+    // A counter that doesn't move forward hints at a cloned authenticator.
+    // Authenticators that don't implement counters always report zero.
+    let sign_count = auth_data.sign_count as i64;
+    if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+        return Err(ServiceError::Unauthorized(
+            "Credential counter did not increase".into(),
+        ));
+    }
+
+    let now = Utc::now().naive_utc();
+    sqlx::query!(
+        "UPDATE webauthn_credentials SET sign_count = ?, last_used_at = ? WHERE id = ?",
+        sign_count,
+        now,
+        credential.id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    complete_login(
+        pool.get_ref(),
+        &http_req,
+        &credential.user_id,
+        credential.username,
+        "webauthn",
+    )
+    .await
+}
+
+#[get("/auth/webauthn/credentials")]
+pub async fn list_credentials(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+
+    let credentials = sqlx::query_as!(
+        CredentialInfo,
+        "SELECT id, name, created_at, last_used_at FROM webauthn_credentials WHERE user_id = ? ORDER BY created_at DESC",
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(credentials))
+}
+
+#[delete("/auth/webauthn/credentials/{id}")]
+pub async fn delete_credential(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_session_user_id(&req, pool.get_ref()).await?;
+    let credential_id = id.into_inner();
+
+    let result = sqlx::query!(
+        "DELETE FROM webauthn_credentials WHERE id = ? AND user_id = ?",
+        credential_id,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::BadRequest("Credential not found".into()));
+    }
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("webauthn.remove")
+            .actor(&user_id)
+            .target("webauthn_credential", &credential_id),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Credential removed"})))
+}
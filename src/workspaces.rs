@@ -0,0 +1,927 @@
+//! Team workspaces.
+//!
+//! A workspace holds documents, folders and tags that belong to a team
+//! rather than to one user. Members have a workspace role; owners and admins
+//! manage everything in it, members can read and edit all of its documents,
+//! and guests only see what is shared with them through `permissions`.
+//! Documents and tags without a workspace make up each user's personal
+//! space.
+//!
+//! Each user has an active workspace, stored when they switch and
+//! overridable per request with the `X-Workspace-Id` header. Listing,
+//! search, trash and new documents are scoped to it.
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, Transaction};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::{
+    api_tokens::Scope,
+    audit::{self, Event},
+    auth::get_user_id,
+    db::DbPool,
+    errors::ServiceError,
+    models::Document,
+    notifier::{Notification, Notifier},
+};
+
+/// Selects the workspace for a single request; `personal` (or empty) is the
+/// personal space.
+const WORKSPACE_HEADER: &str = "X-Workspace-Id";
+
+const NAME_MAX_LENGTH: usize = 100;
+
+/// A member's role in a workspace, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceRole {
+    /// Sees only the documents shared with them.
+    Guest,
+    /// Can read, create and edit all documents and tags.
+    Member,
+    /// Can also move, delete and share any document, and manage members.
+    Admin,
+    /// Can also rename or delete the workspace and manage other owners.
+    Owner,
+}
+
+impl WorkspaceRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WorkspaceRole::Guest => "guest",
+            WorkspaceRole::Member => "member",
+            WorkspaceRole::Admin => "admin",
+            WorkspaceRole::Owner => "owner",
+        }
+    }
+
+    fn parse(role: &str) -> Option<WorkspaceRole> {
+        match role {
+            "guest" => Some(WorkspaceRole::Guest),
+            "member" => Some(WorkspaceRole::Member),
+            "admin" => Some(WorkspaceRole::Admin),
+            "owner" => Some(WorkspaceRole::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// The space a request works in.
+#[derive(Debug, Clone)]
+pub struct ActiveWorkspace {
+    /// `None` for the personal space.
+    pub id: Option<String>,
+    /// The caller's role; they own their personal space.
+    pub role: WorkspaceRole,
+}
+
+impl ActiveWorkspace {
+    /// Whether the caller can add documents and tags here.
+    pub fn can_create(&self) -> bool {
+        self.role >= WorkspaceRole::Member
+    }
+
+    /// Checks that a document belongs to this space.
+    pub fn check_document(&self, doc: &Document) -> Result<(), ServiceError> {
+        if doc.workspace_id != self.id {
+            return Err(ServiceError::BadRequest(
+                "Document is in another workspace".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The user's role in a workspace, if they are a member.
+pub async fn membership(
+    pool: &DbPool,
+    workspace_id: &str,
+    user_id: &str,
+) -> Result<Option<WorkspaceRole>, ServiceError> {
+    let role = sqlx::query_scalar!(
+        "SELECT role FROM workspace_members WHERE workspace_id = ? AND user_id = ?",
+        workspace_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(role.as_deref().and_then(WorkspaceRole::parse))
+}
+
+/// Members of a workspace, optionally leaving out guests.
+pub async fn members(
+    pool: &DbPool,
+    workspace_id: &str,
+    include_guests: bool,
+) -> Result<Vec<String>, ServiceError> {
+    Ok(sqlx::query_scalar!(
+        "SELECT user_id FROM workspace_members WHERE workspace_id = ? AND (? OR role <> 'guest')",
+        workspace_id,
+        include_guests
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Resolves the caller's active workspace: the `X-Workspace-Id` header if
+/// given, otherwise the one they last switched to. A stored workspace they
+/// are no longer a member of falls back to the personal space.
+pub async fn active(
+    req: &HttpRequest,
+    pool: &DbPool,
+    user_id: &str,
+) -> Result<ActiveWorkspace, ServiceError> {
+    let personal = ActiveWorkspace {
+        id: None,
+        role: WorkspaceRole::Owner,
+    };
+
+    if let Some(header) = req.headers().get(WORKSPACE_HEADER) {
+        let id = header.to_str().unwrap_or("").trim();
+        if id.is_empty() || id == "personal" {
+            return Ok(personal);
+        }
+        let role = membership(pool, id, user_id)
+            .await?
+            .ok_or(ServiceError::Forbidden(
+                "Not a member of this workspace".into(),
+            ))?;
+        return Ok(ActiveWorkspace {
+            id: Some(id.to_string()),
+            role,
+        });
+    }
+
+    let stored = sqlx::query_scalar!(
+        "SELECT active_workspace_id FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+    let Some(id) = stored else {
+        return Ok(personal);
+    };
+    Ok(match membership(pool, &id, user_id).await? {
+        Some(role) => ActiveWorkspace { id: Some(id), role },
+        None => personal,
+    })
+}
+
+/// Loads a workspace, checking that the caller has at least `required` role
+/// in it. Returns the workspace name and the caller's role.
+async fn authorize(
+    pool: &DbPool,
+    workspace_id: &str,
+    user_id: &str,
+    required: WorkspaceRole,
+) -> Result<(String, WorkspaceRole), ServiceError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT w.name, m.role AS "role?"
+        FROM workspaces w
+        LEFT JOIN workspace_members m ON m.workspace_id = w.id AND m.user_id = ?
+        WHERE w.id = ?
+        "#,
+        user_id,
+        workspace_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    // Non-members can't tell whether a workspace exists.
+    let Some((name, Some(role))) =
+        row.map(|r| (r.name, r.role.as_deref().and_then(WorkspaceRole::parse)))
+    else {
+        return Err(ServiceError::BadRequest("Workspace not found".into()));
+    };
+    if role < required {
+        return Err(ServiceError::Forbidden("Permission denied".into()));
+    }
+    Ok((name, role))
+}
+
+fn validate_name(name: &str) -> Result<String, ServiceError> {
+    let name = name.trim();
+    let message = if name.is_empty() {
+        "Name is required".to_string()
+    } else if name.chars().count() > NAME_MAX_LENGTH {
+        format!("Name must be at most {} characters", NAME_MAX_LENGTH)
+    } else {
+        return Ok(name.to_string());
+    };
+    Err(ServiceError::ValidationError(BTreeMap::from([(
+        "name".to_string(),
+        vec![message],
+    )])))
+}
+
+/// Counts the owners within the transaction that just changed a member, so
+/// that two owners demoting each other can't both succeed.
+async fn owner_count(
+    tx: &mut Transaction<'_, Sqlite>,
+    workspace_id: &str,
+) -> Result<i64, ServiceError> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM workspace_members WHERE workspace_id = ? AND role = 'owner'"#,
+        workspace_id
+    )
+    .fetch_one(&mut **tx)
+    .await?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkspaceRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SwitchRequest {
+    /// `null` switches to the personal space.
+    pub workspace_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MemberRequest {
+    pub role: WorkspaceRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteRequest {
+    pub username: String,
+    pub role: WorkspaceRole,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkspaceInfo {
+    pub id: String,
+    pub name: String,
+    pub role: String,
+    pub member_count: i64,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Member {
+    pub user_id: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub role: String,
+    pub joined_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Invitation {
+    pub id: String,
+    pub workspace_id: String,
+    pub workspace_name: String,
+    pub user_id: String,
+    pub username: String,
+    pub role: String,
+    pub invited_by: Option<String>,
+    pub invited_by_name: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Lists the caller's workspaces, marking the active one.
+#[get("/workspaces")]
+pub async fn list_workspaces(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let active = active(&req, pool.get_ref(), &user_id).await?;
+
+    let mut workspaces = sqlx::query_as!(
+        WorkspaceInfo,
+        r#"
+        SELECT w.id, w.name, m.role,
+            (SELECT COUNT(*) FROM workspace_members c WHERE c.workspace_id = w.id) AS "member_count!: i64",
+            0 AS "active!: bool", w.created_at
+        FROM workspaces w
+        JOIN workspace_members m ON m.workspace_id = w.id
+        WHERE m.user_id = ?
+        ORDER BY w.name ASC
+        "#,
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    for workspace in &mut workspaces {
+        workspace.active = active.id.as_ref() == Some(&workspace.id);
+    }
+
+    Ok(HttpResponse::Ok().json(workspaces))
+}
+
+/// Creates a workspace owned by the caller.
+#[post("/workspaces")]
+pub async fn create_workspace(
+    pool: web::Data<DbPool>,
+    body: web::Json<WorkspaceRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let name = validate_name(&body.name)?;
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now().naive_utc();
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO workspaces (id, name, created_by, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4)",
+        id,
+        name,
+        user_id,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO workspace_members (workspace_id, user_id, role, created_at, updated_at) VALUES (?1, ?2, 'owner', ?3, ?3)",
+        id,
+        user_id,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("workspace.create")
+            .actor(&user_id)
+            .target("workspace", &id)
+            .details(serde_json::json!({"name": name})),
+    )
+    .await;
+
+    Ok(HttpResponse::Created().json(WorkspaceInfo {
+        id,
+        name,
+        role: WorkspaceRole::Owner.as_str().to_string(),
+        member_count: 1,
+        active: false,
+        created_at: now,
+    }))
+}
+
+/// The caller's active workspace; `id` is `null` in the personal space.
+#[get("/workspaces/active")]
+pub async fn get_active(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    let active = active(&req, pool.get_ref(), &user_id).await?;
+
+    let name = match &active.id {
+        Some(id) => {
+            sqlx::query_scalar!("SELECT name FROM workspaces WHERE id = ?", id)
+                .fetch_optional(pool.get_ref())
+                .await?
+        }
+        None => None,
+    };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": active.id,
+        "name": name,
+        "role": active.role.as_str(),
+    })))
+}
+
+/// Switches the caller's active workspace.
+#[put("/workspaces/active")]
+pub async fn switch_workspace(
+    pool: web::Data<DbPool>,
+    body: web::Json<SwitchRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+
+    let (name, role) = match &body.workspace_id {
+        Some(id) => {
+            let (name, role) =
+                authorize(pool.get_ref(), id, &user_id, WorkspaceRole::Guest).await?;
+            (Some(name), role)
+        }
+        None => (None, WorkspaceRole::Owner),
+    };
+    sqlx::query!(
+        "UPDATE users SET active_workspace_id = ? WHERE id = ?",
+        body.workspace_id,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": body.workspace_id,
+        "name": name,
+        "role": role.as_str(),
+    })))
+}
+
+/// Renames a workspace.
+#[put("/workspaces/{id}")]
+pub async fn update_workspace(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    body: web::Json<WorkspaceRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (previous, _) = authorize(pool.get_ref(), &id, &user_id, WorkspaceRole::Owner).await?;
+    let name = validate_name(&body.name)?;
+    let now = Utc::now().naive_utc();
+
+    sqlx::query!(
+        "UPDATE workspaces SET name = ?, updated_at = ? WHERE id = ?",
+        name,
+        now,
+        *id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("workspace.update")
+            .actor(&user_id)
+            .target("workspace", &id)
+            .details(serde_json::json!({"from": previous, "to": name})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"id": *id, "name": name})))
+}
+
+/// Deletes a workspace with all of its documents and tags.
+#[delete("/workspaces/{id}")]
+pub async fn delete_workspace(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (name, _) = authorize(pool.get_ref(), &id, &user_id, WorkspaceRole::Owner).await?;
+
+    sqlx::query!("DELETE FROM workspaces WHERE id = ?", *id)
+        .execute(pool.get_ref())
+        .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("workspace.delete")
+            .actor(&user_id)
+            .target("workspace", &id)
+            .details(serde_json::json!({"name": name})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Workspace deleted"})))
+}
+
+#[get("/workspaces/{id}/members")]
+pub async fn list_members(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    authorize(pool.get_ref(), &id, &user_id, WorkspaceRole::Guest).await?;
+
+    let members = sqlx::query_as!(
+        Member,
+        r#"
+        SELECT m.user_id, u.username, u.display_name, m.role, m.created_at AS joined_at
+        FROM workspace_members m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.workspace_id = ?
+        ORDER BY u.username ASC
+        "#,
+        *id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(members))
+}
+
+/// Changes a member's role. Only owners can promote to admin or owner and
+/// demote from owner, and the last owner can't be demoted.
+#[put("/workspaces/{id}/members/{user_id}")]
+pub async fn update_member(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    body: web::Json<MemberRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (workspace_id, member_id) = path.into_inner();
+    let (_, caller_role) = authorize(
+        pool.get_ref(),
+        &workspace_id,
+        &user_id,
+        WorkspaceRole::Admin,
+    )
+    .await?;
+
+    let current = membership(pool.get_ref(), &workspace_id, &member_id)
+        .await?
+        .ok_or(ServiceError::BadRequest("Member not found".into()))?;
+    if (current == WorkspaceRole::Owner || body.role == WorkspaceRole::Owner)
+        && caller_role < WorkspaceRole::Owner
+    {
+        return Err(ServiceError::Forbidden(
+            "Only owners can manage owners".into(),
+        ));
+    }
+    if body.role == WorkspaceRole::Admin
+        && current < WorkspaceRole::Admin
+        && caller_role < WorkspaceRole::Owner
+    {
+        return Err(ServiceError::Forbidden(
+            "Only owners can promote admins".into(),
+        ));
+    }
+
+    let role = body.role.as_str();
+    let now = Utc::now().naive_utc();
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE workspace_members SET role = ?, updated_at = ? WHERE workspace_id = ? AND user_id = ?",
+        role,
+        now,
+        workspace_id,
+        member_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if owner_count(&mut tx, &workspace_id).await? == 0 {
+        return Err(ServiceError::BadRequest(
+            "A workspace needs at least one owner".into(),
+        ));
+    }
+    tx.commit().await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("workspace.member_role")
+            .actor(&user_id)
+            .target("workspace", &workspace_id)
+            .details(serde_json::json!({
+                "user_id": member_id,
+                "from": current.as_str(),
+                "to": role,
+            })),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"user_id": member_id, "role": role})))
+}
+
+/// Removes a member, or leaves the workspace when it is the caller. Admins
+/// can't remove owners, and the last owner can't leave. The member's direct
+/// grants in the workspace go with them; documents they created stay.
+#[delete("/workspaces/{id}/members/{user_id}")]
+pub async fn remove_member(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (workspace_id, member_id) = path.into_inner();
+    let leaving = member_id == user_id;
+    let required = if leaving {
+        WorkspaceRole::Guest
+    } else {
+        WorkspaceRole::Admin
+    };
+    let (_, caller_role) = authorize(pool.get_ref(), &workspace_id, &user_id, required).await?;
+
+    let current = membership(pool.get_ref(), &workspace_id, &member_id)
+        .await?
+        .ok_or(ServiceError::BadRequest("Member not found".into()))?;
+    if current == WorkspaceRole::Owner && !leaving && caller_role < WorkspaceRole::Owner {
+        return Err(ServiceError::Forbidden(
+            "Only owners can manage owners".into(),
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "DELETE FROM workspace_members WHERE workspace_id = ? AND user_id = ?",
+        workspace_id,
+        member_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if owner_count(&mut tx, &workspace_id).await? == 0 {
+        return Err(ServiceError::BadRequest(
+            "A workspace needs at least one owner".into(),
+        ));
+    }
+    sqlx::query!(
+        r#"
+        DELETE FROM document_permissions
+        WHERE user_id = ? AND document_id IN (SELECT id FROM documents WHERE workspace_id = ?)
+        "#,
+        member_id,
+        workspace_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new(if leaving {
+            "workspace.leave"
+        } else {
+            "workspace.member_remove"
+        })
+        .actor(&user_id)
+        .target("workspace", &workspace_id)
+        .details(serde_json::json!({"user_id": member_id, "role": current.as_str()})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Member removed"})))
+}
+
+/// Pending invitations to a workspace.
+#[get("/workspaces/{id}/invitations")]
+pub async fn list_workspace_invitations(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+    authorize(pool.get_ref(), &id, &user_id, WorkspaceRole::Admin).await?;
+
+    let invitations = sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT i.id, i.workspace_id, w.name AS workspace_name, i.user_id, u.username, i.role,
+            i.invited_by, b.username AS "invited_by_name?", i.created_at
+        FROM workspace_invitations i
+        JOIN workspaces w ON w.id = i.workspace_id
+        JOIN users u ON u.id = i.user_id
+        LEFT JOIN users b ON b.id = i.invited_by
+        WHERE i.workspace_id = ?
+        ORDER BY i.created_at DESC
+        "#,
+        *id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(invitations))
+}
+
+/// Invites a user by username. Inviting them again replaces the role.
+#[post("/workspaces/{id}/invitations")]
+pub async fn invite(
+    pool: web::Data<DbPool>,
+    notifier: web::Data<dyn Notifier>,
+    id: web::Path<String>,
+    body: web::Json<InviteRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (workspace_name, caller_role) =
+        authorize(pool.get_ref(), &id, &user_id, WorkspaceRole::Admin).await?;
+
+    if body.role == WorkspaceRole::Owner {
+        return Err(ServiceError::ValidationError(BTreeMap::from([(
+            "role".to_string(),
+            vec!["Role must be admin, member or guest".to_string()],
+        )])));
+    }
+    if body.role == WorkspaceRole::Admin && caller_role < WorkspaceRole::Owner {
+        return Err(ServiceError::Forbidden(
+            "Only owners can invite admins".into(),
+        ));
+    }
+    let invitee = sqlx::query!(
+        "SELECT id, username FROM users WHERE username = ? AND disabled_at IS NULL",
+        body.username
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::BadRequest("User not found".into()))?;
+    if membership(pool.get_ref(), &id, &invitee.id)
+        .await?
+        .is_some()
+    {
+        return Err(ServiceError::BadRequest("User is already a member".into()));
+    }
+
+    let invitation_id = Uuid::new_v4().to_string();
+    let role = body.role.as_str();
+    let now = Utc::now().naive_utc();
+    sqlx::query!(
+        r#"
+        INSERT INTO workspace_invitations (id, workspace_id, user_id, role, invited_by, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (workspace_id, user_id) DO UPDATE SET role = ?4, invited_by = ?5, created_at = ?6
+        "#,
+        invitation_id,
+        *id,
+        invitee.id,
+        role,
+        user_id,
+        now
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("workspace.invite")
+            .actor(&user_id)
+            .target("workspace", &id)
+            .details(serde_json::json!({
+                "user_id": invitee.id,
+                "username": invitee.username,
+                "role": role,
+            })),
+    )
+    .await;
+
+    // The invitation stands even if the notice can't be delivered.
+    if let Err(e) = notifier
+        .send(&Notification {
+            user_id: invitee.id.clone(),
+            username: invitee.username.clone(),
+            subject: format!("You're invited to {}", workspace_name),
+            body: format!(
+                "You have been invited to join the workspace \"{}\" as {}. \
+                 Accept or decline the invitation in the workspace switcher.",
+                workspace_name, role
+            ),
+        })
+        .await
+    {
+        eprintln!("Failed to send workspace invitation: {:?}", e);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "user_id": invitee.id,
+        "username": invitee.username,
+        "role": role,
+    })))
+}
+
+#[delete("/workspaces/{id}/invitations/{invitation_id}")]
+pub async fn cancel_invitation(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let (workspace_id, invitation_id) = path.into_inner();
+    authorize(
+        pool.get_ref(),
+        &workspace_id,
+        &user_id,
+        WorkspaceRole::Admin,
+    )
+    .await?;
+
+    let invitation = sqlx::query!(
+        "DELETE FROM workspace_invitations WHERE id = ? AND workspace_id = ? RETURNING user_id, role",
+        invitation_id,
+        workspace_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or(ServiceError::BadRequest("Invitation not found".into()))?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("workspace.invitation.cancel")
+            .actor(&user_id)
+            .target("workspace", &workspace_id)
+            .details(serde_json::json!({
+                "invitation_id": invitation_id,
+                "user_id": invitation.user_id,
+                "role": invitation.role,
+            })),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Invitation cancelled"})))
+}
+
+/// Invitations waiting for the caller.
+#[get("/invitations")]
+pub async fn my_invitations(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsRead).await?;
+
+    let invitations = sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT i.id, i.workspace_id, w.name AS workspace_name, i.user_id, u.username, i.role,
+            i.invited_by, b.username AS "invited_by_name?", i.created_at
+        FROM workspace_invitations i
+        JOIN workspaces w ON w.id = i.workspace_id
+        JOIN users u ON u.id = i.user_id
+        LEFT JOIN users b ON b.id = i.invited_by
+        WHERE i.user_id = ?
+        ORDER BY i.created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(invitations))
+}
+
+/// Joins the workspace with the invited role.
+#[post("/invitations/{id}/accept")]
+pub async fn accept_invitation(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+    let now = Utc::now().naive_utc();
+
+    let mut tx = pool.begin().await?;
+    let invitation = sqlx::query!(
+        "DELETE FROM workspace_invitations WHERE id = ? AND user_id = ? RETURNING workspace_id, role",
+        *id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServiceError::BadRequest("Invitation not found".into()))?;
+    sqlx::query!(
+        r#"
+        INSERT INTO workspace_members (workspace_id, user_id, role, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?4)
+        ON CONFLICT (workspace_id, user_id) DO NOTHING
+        "#,
+        invitation.workspace_id,
+        user_id,
+        invitation.role,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    audit::record(
+        pool.get_ref(),
+        &req,
+        Event::new("workspace.join")
+            .actor(&user_id)
+            .target("workspace", &invitation.workspace_id)
+            .details(serde_json::json!({"role": invitation.role})),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "workspace_id": invitation.workspace_id,
+        "role": invitation.role,
+    })))
+}
+
+#[post("/invitations/{id}/decline")]
+pub async fn decline_invitation(
+    pool: web::Data<DbPool>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = get_user_id(&req, pool.get_ref(), Scope::DocsWrite).await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM workspace_invitations WHERE id = ? AND user_id = ?",
+        *id,
+        user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::BadRequest("Invitation not found".into()));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Invitation declined"})))
+}
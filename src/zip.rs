@@ -0,0 +1,193 @@
+//! Minimal ZIP archive writer for data exports.
+//!
+//! Entries are deflated one at a time and handed back as byte chunks so an
+//! archive can be streamed to the client as it is built. Only what exports
+//! need is supported: UTF-8 names, deflate, and archives below 4 GiB /
+//! 65535 entries (no ZIP64).
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use flate2::{write::DeflateEncoder, Compression};
+use std::io::{self, Write};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+const VERSION: u16 = 20;
+/// Bit 11: file names are UTF-8.
+const FLAGS: u16 = 1 << 11;
+const METHOD_DEFLATE: u16 = 8;
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    time: u16,
+    date: u16,
+    offset: u32,
+}
+
+#[derive(Default)]
+pub struct ZipWriter {
+    entries: Vec<CentralEntry>,
+    offset: u64,
+}
+
+fn too_large() -> io::Error {
+    io::Error::other("archive too large for ZIP")
+}
+
+/// MS-DOS time and date; timestamps before 1980 are clamped.
+fn dos_timestamp(at: NaiveDateTime) -> (u16, u16) {
+    if at.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = (at.hour() << 11) | (at.minute() << 5) | (at.second() / 2);
+    let date = (((at.year() - 1980) as u32) << 9) | (at.month() << 5) | at.day();
+    (time as u16, date as u16)
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compresses `data` into a new entry and returns the bytes to emit.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        modified: NaiveDateTime,
+    ) -> io::Result<Vec<u8>> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+        let (time, date) = dos_timestamp(modified);
+        let entry = CentralEntry {
+            name: name.to_string(),
+            crc: crc32fast::hash(data),
+            compressed_size: u32::try_from(compressed.len()).map_err(|_| too_large())?,
+            size: u32::try_from(data.len()).map_err(|_| too_large())?,
+            time,
+            date,
+            offset,
+        };
+        if self.entries.len() >= u16::MAX as usize {
+            return Err(too_large());
+        }
+
+        let mut out = Vec::with_capacity(30 + name.len() + compressed.len());
+        out.extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&FLAGS.to_le_bytes());
+        out.extend_from_slice(&METHOD_DEFLATE.to_le_bytes());
+        out.extend_from_slice(&entry.time.to_le_bytes());
+        out.extend_from_slice(&entry.date.to_le_bytes());
+        out.extend_from_slice(&entry.crc.to_le_bytes());
+        out.extend_from_slice(&entry.compressed_size.to_le_bytes());
+        out.extend_from_slice(&entry.size.to_le_bytes());
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&compressed);
+
+        self.offset += out.len() as u64;
+        self.entries.push(entry);
+        Ok(out)
+    }
+
+    /// Returns the central directory that closes the archive.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        let directory_offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+        let mut out = Vec::new();
+
+        for entry in &self.entries {
+            out.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            out.extend_from_slice(&VERSION.to_le_bytes()); // made by
+            out.extend_from_slice(&VERSION.to_le_bytes()); // needed to extract
+            out.extend_from_slice(&FLAGS.to_le_bytes());
+            out.extend_from_slice(&METHOD_DEFLATE.to_le_bytes());
+            out.extend_from_slice(&entry.time.to_le_bytes());
+            out.extend_from_slice(&entry.date.to_le_bytes());
+            out.extend_from_slice(&entry.crc.to_le_bytes());
+            out.extend_from_slice(&entry.compressed_size.to_le_bytes());
+            out.extend_from_slice(&entry.size.to_le_bytes());
+            out.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&[0; 12]); // extra, comment, disk, attributes
+            out.extend_from_slice(&entry.offset.to_le_bytes());
+            out.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let directory_size = u32::try_from(out.len()).map_err(|_| too_large())?;
+        let count = self.entries.len() as u16;
+        out.extend_from_slice(&END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        out.extend_from_slice(&[0; 4]); // disk numbers
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&directory_size.to_le_bytes());
+        out.extend_from_slice(&directory_offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ZipWriter;
+    use chrono::NaiveDate;
+    use std::io::{Cursor, Read};
+
+    fn build(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let modified = NaiveDate::from_ymd_opt(2024, 1, 21)
+            .unwrap()
+            .and_hms_opt(12, 30, 44)
+            .unwrap();
+        let mut writer = ZipWriter::new();
+        let mut archive = Vec::new();
+        for (name, data) in files {
+            archive.extend(writer.add_file(name, data, modified).unwrap());
+        }
+        archive.extend(writer.finish().unwrap());
+        archive
+    }
+
+    #[test]
+    fn round_trips_through_a_zip_reader() {
+        let files: &[(&str, &[u8])] = &[
+            ("account.json", b"{\"username\":\"alice\"}"),
+            ("documents/Notes/Hello (0123abcd).html", b"<p>hello</p>"),
+            (
+                "documents/文档/会议记录 (45670123).html",
+                "<p>你好 🌍</p>".as_bytes(),
+            ),
+            ("documents/Empty (89abcdef).html", b""),
+        ];
+        let mut archive = ::zip::ZipArchive::new(Cursor::new(build(files))).unwrap();
+
+        assert_eq!(archive.len(), files.len());
+        for (i, (name, data)) in files.iter().enumerate() {
+            let mut entry = archive.by_index(i).unwrap();
+            assert_eq!(entry.name(), *name);
+            let modified = entry.last_modified().unwrap();
+            assert_eq!(
+                (modified.year(), modified.month(), modified.day()),
+                (2024, 1, 21)
+            );
+            assert_eq!(
+                (modified.hour(), modified.minute(), modified.second()),
+                (12, 30, 44)
+            );
+            let mut read = Vec::new();
+            entry.read_to_end(&mut read).unwrap();
+            assert_eq!(read, *data);
+        }
+    }
+
+    #[test]
+    fn empty_archive_is_readable() {
+        let archive = ::zip::ZipArchive::new(Cursor::new(build(&[]))).unwrap();
+        assert_eq!(archive.len(), 0);
+    }
+}
@@ -0,0 +1,397 @@
+//! Collaborative editing against the server, with yrs standing in for the
+//! editors' Yjs documents.
+
+mod common;
+
+use actix_web::web::Bytes;
+use awc::ws::{Frame, Message};
+use common::{client, TestServer};
+use futures::{SinkExt, StreamExt};
+use std::time::{Duration, Instant};
+use yrs::{
+    updates::{decoder::Decode, encoder::Encode},
+    Doc, GetString, ReadTxn, StateVector, Transact, Update, XmlElementPrelim, XmlFragment,
+    XmlTextPrelim,
+};
+
+const MESSAGE_SYNC: u64 = 0;
+const SYNC_STEP1: u64 = 0;
+const SYNC_STEP2: u64 = 1;
+const SYNC_UPDATE: u64 = 2;
+
+/// An editor bound to the `default` fragment, like Tiptap's Collaboration
+/// extension.
+struct Editor {
+    doc: Doc,
+    ws: actix_codec::Framed<awc::BoxedSocket, awc::ws::Codec>,
+}
+
+impl Editor {
+    /// Connects and completes the initial sync in both directions.
+    async fn connect(server: &TestServer, token: &str, doc_id: &str) -> Editor {
+        let url =
+            format!("{}/collab/{}?token={}", server.url, doc_id, token).replacen("http", "ws", 1);
+        let (_, ws) = awc::Client::new().ws(url).connect().await.unwrap();
+        let mut editor = Editor {
+            doc: Doc::new(),
+            ws,
+        };
+
+        let (step, state_vector) = editor.receive_sync().await;
+        assert_eq!(step, SYNC_STEP1);
+        let state_vector = StateVector::decode_v1(&state_vector).unwrap();
+        let missing = editor
+            .doc
+            .transact()
+            .encode_state_as_update_v1(&state_vector);
+        editor.send(SYNC_STEP2, &missing).await;
+
+        let own = editor.doc.transact().state_vector().encode_v1();
+        editor.send(SYNC_STEP1, &own).await;
+        let (step, update) = editor.receive_sync().await;
+        assert_eq!(step, SYNC_STEP2);
+        editor.apply(&update);
+        editor
+    }
+
+    /// Appends a paragraph and sends the change.
+    async fn type_paragraph(&mut self, text: &str) {
+        let fragment = self.doc.get_or_insert_xml_fragment("default");
+        let update = {
+            let mut txn = self.doc.transact_mut();
+            let paragraph = fragment.push_back(&mut txn, XmlElementPrelim::empty("paragraph"));
+            paragraph.push_back(&mut txn, XmlTextPrelim::new(text));
+            txn.encode_update_v1()
+        };
+        self.send(SYNC_UPDATE, &update).await;
+    }
+
+    /// Waits for a change relayed from another editor.
+    async fn receive_update(&mut self) {
+        let (step, update) = self.receive_sync().await;
+        assert_eq!(step, SYNC_UPDATE);
+        self.apply(&update);
+    }
+
+    fn text(&self) -> String {
+        let fragment = self.doc.get_or_insert_xml_fragment("default");
+        fragment.get_string(&self.doc.transact())
+    }
+
+    fn apply(&self, update: &[u8]) {
+        self.doc
+            .transact_mut()
+            .apply_update(Update::decode_v1(update).unwrap())
+            .unwrap();
+    }
+
+    async fn send(&mut self, step: u64, payload: &[u8]) {
+        let mut message = Vec::new();
+        write_var_uint(&mut message, MESSAGE_SYNC);
+        write_var_uint(&mut message, step);
+        write_var_uint(&mut message, payload.len() as u64);
+        message.extend_from_slice(payload);
+        self.ws
+            .send(Message::Binary(Bytes::from(message)))
+            .await
+            .unwrap();
+    }
+
+    /// The next sync message, skipping awareness.
+    async fn receive_sync(&mut self) -> (u64, Vec<u8>) {
+        loop {
+            let frame = actix_web::rt::time::timeout(Duration::from_secs(10), self.ws.next())
+                .await
+                .expect("no message from the server")
+                .expect("connection closed")
+                .unwrap();
+            let Frame::Binary(data) = frame else {
+                continue;
+            };
+            let mut data = &data[..];
+            if read_var_uint(&mut data) != MESSAGE_SYNC {
+                continue;
+            }
+            let step = read_var_uint(&mut data);
+            let len = read_var_uint(&mut data) as usize;
+            return (step, data[..len].to_vec());
+        }
+    }
+
+    /// Waits for the server to close the connection and returns the code.
+    async fn close_code(&mut self) -> Option<u16> {
+        let reason = actix_web::rt::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match self.ws.next().await {
+                    Some(Ok(Frame::Close(reason))) => return reason,
+                    Some(Ok(_)) => continue,
+                    other => panic!("unexpected {:?}", other.map(|f| f.map(|_| ()))),
+                }
+            }
+        })
+        .await
+        .expect("connection was not closed");
+        reason.map(|r| u16::from(r.code))
+    }
+
+    async fn close(mut self) {
+        self.ws.send(Message::Close(None)).await.unwrap();
+    }
+}
+
+fn write_var_uint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_var_uint(data: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    for i in 0.. {
+        let byte = data[i];
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            break;
+        }
+    }
+    value
+}
+
+async fn create_document(server: &TestServer, token: &str) -> String {
+    create_document_with(server, token, None).await
+}
+
+async fn create_document_with(server: &TestServer, token: &str, content: Option<&str>) -> String {
+    let body: serde_json::Value = client()
+        .post(format!("{}/documents", server.url))
+        .bearer_auth(token)
+        .json(&serde_json::json!({"title": "Notes", "content": content, "is_folder": false}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["id"].as_str().unwrap().to_string()
+}
+
+async fn content(server: &TestServer, token: &str, doc_id: &str) -> Option<String> {
+    let body: serde_json::Value = client()
+        .get(format!("{}/documents/{}", server.url, doc_id))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["content"].as_str().map(str::to_string)
+}
+
+/// Polls until the document's content is `expected`.
+async fn wait_for_content(server: &TestServer, token: &str, doc_id: &str, expected: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let current = content(server, token, doc_id).await;
+        if current.as_deref() == Some(expected) {
+            return;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "content is {:?}, expected {:?}",
+            current,
+            expected
+        );
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+async fn stored_updates(server: &TestServer, doc_id: &str) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM document_updates WHERE document_id = ?")
+        .bind(doc_id)
+        .fetch_one(&server.pool)
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn saves_the_document_once_editing_pauses() {
+    let server = TestServer::start(&[("COLLAB_SAVE_DELAY_SECONDS", "1")]).await;
+    let token = server.register("alice").await;
+    let doc_id = create_document(&server, &token).await;
+
+    let mut editor = Editor::connect(&server, &token, &doc_id).await;
+    editor.type_paragraph("Hello <world>").await;
+    editor.type_paragraph("Again").await;
+
+    wait_for_content(
+        &server,
+        &token,
+        &doc_id,
+        "<p>Hello &lt;world&gt;</p><p>Again</p>",
+    )
+    .await;
+    assert_eq!(stored_updates(&server, &doc_id).await, 1);
+
+    let revisions: Vec<serde_json::Value> = client()
+        .get(format!("{}/documents/{}/revisions", server.url, doc_id))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let latest = &revisions[0];
+    assert_eq!(latest["autosave"], true);
+    assert_eq!(latest["author_name"], "alice");
+    editor.close().await;
+}
+
+#[actix_web::test]
+async fn saves_and_compacts_when_the_last_editor_leaves() {
+    let server = TestServer::start(&[("COLLAB_SAVE_DELAY_SECONDS", "600")]).await;
+    let token = server.register("alice").await;
+    let doc_id = create_document(&server, &token).await;
+
+    let mut editor = Editor::connect(&server, &token, &doc_id).await;
+    editor.type_paragraph("one").await;
+    editor.type_paragraph("two").await;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while stored_updates(&server, &doc_id).await < 2 {
+        assert!(Instant::now() < deadline, "updates were not stored");
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(content(&server, &token, &doc_id).await, None);
+
+    editor.close().await;
+    wait_for_content(&server, &token, &doc_id, "<p>one</p><p>two</p>").await;
+    assert_eq!(stored_updates(&server, &doc_id).await, 1);
+}
+
+#[actix_web::test]
+async fn editors_sync_through_the_server() {
+    let server = TestServer::start(&[("COLLAB_SAVE_DELAY_SECONDS", "600")]).await;
+    let alice = server.register("alice").await;
+    let doc_id = create_document(&server, &alice).await;
+
+    let mut first = Editor::connect(&server, &alice, &doc_id).await;
+    first.type_paragraph("from the first").await;
+    let mut second = Editor::connect(&server, &alice, &doc_id).await;
+    assert_eq!(second.text(), "<paragraph>from the first</paragraph>");
+
+    second.type_paragraph("from the second").await;
+    first.receive_update().await;
+    assert_eq!(first.text(), second.text());
+    first.close().await;
+    second.close().await;
+    wait_for_content(
+        &server,
+        &alice,
+        &doc_id,
+        "<p>from the first</p><p>from the second</p>",
+    )
+    .await;
+
+    // A later session starts from the compacted state.
+    let later = Editor::connect(&server, &alice, &doc_id).await;
+    assert_eq!(
+        later.text(),
+        "<paragraph>from the first</paragraph><paragraph>from the second</paragraph>"
+    );
+    later.close().await;
+}
+
+#[actix_web::test]
+async fn restoring_a_revision_discards_the_session() {
+    let server = TestServer::start(&[("COLLAB_SAVE_DELAY_SECONDS", "1")]).await;
+    let token = server.register("alice").await;
+    let doc_id = create_document(&server, &token).await;
+
+    let mut editor = Editor::connect(&server, &token, &doc_id).await;
+    editor.type_paragraph("draft").await;
+    wait_for_content(&server, &token, &doc_id, "<p>draft</p>").await;
+    editor.type_paragraph("unsaved").await;
+
+    let res = client()
+        .post(format!(
+            "{}/documents/{}/revisions/1/restore",
+            server.url, doc_id
+        ))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    assert_eq!(editor.close_code().await, Some(4001));
+
+    // The pending save of the discarded state must not bring it back.
+    actix_web::rt::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(content(&server, &token, &doc_id).await, None);
+    assert_eq!(stored_updates(&server, &doc_id).await, 0);
+}
+
+#[actix_web::test]
+async fn saving_over_rest_discards_the_session() {
+    let server = TestServer::start(&[("COLLAB_SAVE_DELAY_SECONDS", "1")]).await;
+    let token = server.register("alice").await;
+    let doc_id = create_document(&server, &token).await;
+
+    let mut editor = Editor::connect(&server, &token, &doc_id).await;
+    editor.type_paragraph("draft").await;
+    wait_for_content(&server, &token, &doc_id, "<p>draft</p>").await;
+    editor.type_paragraph("unsaved").await;
+
+    let res = client()
+        .put(format!("{}/documents/{}", server.url, doc_id))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({"content": "<p>from rest</p>"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(editor.close_code().await, Some(4001));
+
+    // Neither the pending save nor a new session brings the old state back.
+    let later = Editor::connect(&server, &token, &doc_id).await;
+    assert_eq!(later.text(), "<paragraph>from rest</paragraph>");
+    actix_web::rt::time::sleep(Duration::from_secs(2)).await;
+    later.close().await;
+    actix_web::rt::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(
+        content(&server, &token, &doc_id).await.as_deref(),
+        Some("<p>from rest</p>")
+    );
+}
+
+#[actix_web::test]
+async fn sessions_start_from_the_saved_content() {
+    let server = TestServer::start(&[("COLLAB_SAVE_DELAY_SECONDS", "1")]).await;
+    let token = server.register("alice").await;
+    let doc_id = create_document_with(&server, &token, Some("<h1>Title</h1><p>Body</p>")).await;
+
+    // Editors opening the document together get one copy of it.
+    let (mut first, second) = futures::join!(
+        Editor::connect(&server, &token, &doc_id),
+        Editor::connect(&server, &token, &doc_id)
+    );
+    let seeded = r#"<heading level="1">Title</heading><paragraph>Body</paragraph>"#;
+    assert_eq!(first.text(), seeded);
+    assert_eq!(second.text(), seeded);
+    assert_eq!(stored_updates(&server, &doc_id).await, 1);
+
+    first.type_paragraph("more").await;
+    wait_for_content(
+        &server,
+        &token,
+        &doc_id,
+        "<h1>Title</h1><p>Body</p><p>more</p>",
+    )
+    .await;
+    first.close().await;
+    second.close().await;
+}
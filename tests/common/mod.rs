@@ -0,0 +1,175 @@
+//! Runs the server binary against a fresh, migrated database so tests can
+//! drive it over HTTP like a browser would.
+
+#![allow(dead_code)]
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use std::{
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+pub const PASSWORD: &str = "Password123!";
+
+pub struct TestServer {
+    pub url: String,
+    pub pool: SqlitePool,
+    /// Where the server keeps uploaded files.
+    pub data_dir: PathBuf,
+    child: Child,
+    db_path: PathBuf,
+}
+
+impl TestServer {
+    /// Starts the server with `env` on top of a database of its own.
+    pub async fn start(env: &[(&str, &str)]) -> TestServer {
+        let name = format!("actix-doc-test-{}", uuid::Uuid::new_v4());
+        let db_path = std::env::temp_dir().join(format!("{}.db", name));
+        let data_dir = std::env::temp_dir().join(name);
+        let pool = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(&db_path)
+                .create_if_missing(true),
+        )
+        .await
+        .expect("create test database");
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .expect("run migrations");
+
+        let addr = free_addr();
+        let child = Command::new(env!("CARGO_BIN_EXE_actix-doc"))
+            .env("DATABASE_URL", format!("sqlite:{}", db_path.display()))
+            .env("BIND_ADDR", &addr)
+            .env("AVATAR_DIR", data_dir.join("avatars"))
+            .env("RUST_LOG", "warn")
+            // Cheap password hashes keep the debug build quick.
+            .env("ARGON2_MEMORY_KIB", "1024")
+            .env("ARGON2_ITERATIONS", "1")
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("start server");
+
+        let mut server = TestServer {
+            url: format!("http://{}", addr),
+            pool,
+            data_dir,
+            child,
+            db_path,
+        };
+        wait_for(&mut server.child, &addr);
+        server
+    }
+
+    /// Registers `username` and returns an access token for it.
+    pub async fn register(&self, username: &str) -> String {
+        let res = client()
+            .post(format!("{}/auth/register", self.url))
+            .json(&serde_json::json!({"username": username, "password": PASSWORD}))
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success(), "register {}", username);
+        self.login(username).await
+    }
+
+    pub async fn login(&self, username: &str) -> String {
+        let body: serde_json::Value = client()
+            .post(format!("{}/auth/login", self.url))
+            .json(&serde_json::json!({"username": username, "password": PASSWORD}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        body["token"].as_str().expect("access token").to_string()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.db_path.display(), suffix));
+        }
+    }
+}
+
+/// Runs the `mock_idp` example, which `cargo test` builds alongside the tests.
+pub struct MockIdp {
+    pub issuer: String,
+    child: Child,
+}
+
+impl MockIdp {
+    pub fn start() -> MockIdp {
+        let binary = Path::new(env!("CARGO_BIN_EXE_actix-doc"))
+            .with_file_name("examples")
+            .join(format!("mock_idp{}", std::env::consts::EXE_SUFFIX));
+        assert!(
+            binary.exists(),
+            "{} is missing; run `cargo build --example mock_idp`",
+            binary.display()
+        );
+
+        let addr = free_addr();
+        let mut child = Command::new(binary)
+            .env("MOCK_IDP_ADDR", &addr)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("start mock_idp");
+        wait_for(&mut child, &addr);
+        MockIdp {
+            issuer: format!("http://{}", addr),
+            child,
+        }
+    }
+}
+
+impl Drop for MockIdp {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A client that reports redirects instead of following them.
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+/// The `error` message of a failed response.
+pub async fn error_of(res: reqwest::Response) -> (u16, String) {
+    let status = res.status().as_u16();
+    let body: serde_json::Value = res.json().await.unwrap();
+    (
+        status,
+        body["error"].as_str().unwrap_or_default().to_string(),
+    )
+}
+
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn wait_for(child: &mut Child, addr: &str) {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while TcpStream::connect(addr).is_err() {
+        if let Some(status) = child.try_wait().unwrap() {
+            panic!("process exited before listening on {}: {}", addr, status);
+        }
+        assert!(Instant::now() < deadline, "nothing listening on {}", addr);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,294 @@
+//! End-to-end single sign-on against the `mock_idp` example.
+
+mod common;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use common::{client, error_of, MockIdp, TestServer};
+use reqwest::{header, Url};
+use sha2::{Digest, Sha256};
+
+const REDIRECT_URI: &str = "http://localhost:3000/auth/callback";
+
+async fn start(extra: &[(&str, &str)]) -> (TestServer, MockIdp) {
+    let idp = MockIdp::start();
+    let mut env = vec![
+        ("OIDC_ISSUER", idp.issuer.as_str()),
+        ("OIDC_CLIENT_ID", "actix-doc"),
+        ("OIDC_REDIRECT_URI", REDIRECT_URI),
+    ];
+    env.extend_from_slice(extra);
+    (TestServer::start(&env).await, idp)
+}
+
+/// A flow started by `/auth/oidc/authorize`, as the browser holds it.
+struct Flow {
+    authorization_url: Url,
+    state: String,
+    /// The `Cookie` header value binding the flow to this browser.
+    cookie: String,
+}
+
+impl Flow {
+    async fn start(server: &TestServer, token: Option<&str>) -> Flow {
+        let mut req = client().get(format!("{}/auth/oidc/authorize", server.url));
+        if let Some(token) = token {
+            req = req.bearer_auth(token);
+        }
+        let res = req.send().await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let cookie = res
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find(|v| v.starts_with("adoc_oidc_state="))
+            .expect("state cookie")
+            .to_string();
+        assert!(cookie.contains("HttpOnly") && cookie.contains("SameSite=Lax"));
+        let cookie = cookie.split(';').next().unwrap().to_string();
+
+        let body: serde_json::Value = res.json().await.unwrap();
+        let authorization_url = Url::parse(body["authorization_url"].as_str().unwrap()).unwrap();
+        let state = param(&authorization_url, "state").unwrap();
+        Flow {
+            authorization_url,
+            state,
+            cookie,
+        }
+    }
+
+    /// Has the provider approve the flow for `subject`, with query
+    /// parameters optionally replaced, and returns the code it redirects with.
+    async fn approve(&self, subject: &str, replace: &[(&str, &str)]) -> String {
+        let mut url = self.authorization_url.clone();
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(k, v)| {
+                let v = replace
+                    .iter()
+                    .find(|(name, _)| *name == k)
+                    .map_or(v.to_string(), |(_, value)| value.to_string());
+                (k.to_string(), v)
+            })
+            .collect();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(pairs)
+            .append_pair("login_hint", subject);
+
+        let res = client().get(url).send().await.unwrap();
+        assert_eq!(res.status(), 302);
+        let location = Url::parse(res.headers()[header::LOCATION].to_str().unwrap()).unwrap();
+        assert!(location.as_str().starts_with(REDIRECT_URI));
+        assert_eq!(
+            param(&location, "state").as_deref(),
+            Some(self.state.as_str())
+        );
+        param(&location, "code").unwrap()
+    }
+
+    async fn callback(&self, server: &TestServer, code: &str) -> reqwest::Response {
+        callback(server, code, &self.state, Some(&self.cookie)).await
+    }
+}
+
+async fn callback(
+    server: &TestServer,
+    code: &str,
+    state: &str,
+    cookie: Option<&str>,
+) -> reqwest::Response {
+    let mut req = client()
+        .post(format!("{}/auth/oidc/callback", server.url))
+        .json(&serde_json::json!({"code": code, "state": state}));
+    if let Some(cookie) = cookie {
+        req = req.header(header::COOKIE, cookie);
+    }
+    req.send().await.unwrap()
+}
+
+fn param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.to_string())
+}
+
+async fn sign_in(server: &TestServer, subject: &str) -> serde_json::Value {
+    let flow = Flow::start(server, None).await;
+    let code = flow.approve(subject, &[]).await;
+    let res = flow.callback(server, &code).await;
+    assert_eq!(res.status(), 200);
+    res.json().await.unwrap()
+}
+
+#[actix_web::test]
+async fn provisions_a_user_on_first_login_with_a_verified_id_token() {
+    let (server, _idp) = start(&[]).await;
+
+    let flow = Flow::start(&server, None).await;
+    // The challenge sent to the provider is derived from the stored verifier.
+    let verifier: String =
+        sqlx::query_scalar("SELECT code_verifier FROM oidc_states WHERE state = ?")
+            .bind(&flow.state)
+            .fetch_one(&server.pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        param(&flow.authorization_url, "code_challenge_method").as_deref(),
+        Some("S256")
+    );
+    assert_eq!(
+        param(&flow.authorization_url, "code_challenge"),
+        Some(URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())))
+    );
+
+    let code = flow.approve("carol", &[]).await;
+    let res = flow.callback(&server, &code).await;
+    assert_eq!(res.status(), 200);
+    let removal = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    assert!(removal.starts_with("adoc_oidc_state=;") && removal.contains("Max-Age=0"));
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["username"], "carol");
+    assert!(body["token"].is_string());
+
+    // Signing in again uses the same account.
+    let again = sign_in(&server, "carol").await;
+    assert_eq!(again["username"], "carol");
+    let (users, identities): (i64, i64) = sqlx::query_as(
+        "SELECT (SELECT COUNT(*) FROM users WHERE username LIKE 'carol%'),
+            (SELECT COUNT(*) FROM oidc_identities WHERE subject = 'carol')",
+    )
+    .fetch_one(&server.pool)
+    .await
+    .unwrap();
+    assert_eq!((users, identities), (1, 1));
+}
+
+#[actix_web::test]
+async fn provisioned_usernames_avoid_local_ones() {
+    let (server, _idp) = start(&[]).await;
+    server.register("dave").await;
+
+    assert_eq!(sign_in(&server, "dave").await["username"], "dave-2");
+}
+
+#[actix_web::test]
+async fn refuses_unknown_identities_without_auto_provisioning() {
+    let (server, _idp) = start(&[("OIDC_AUTO_PROVISION", "false")]).await;
+
+    let flow = Flow::start(&server, None).await;
+    let code = flow.approve("mallory", &[]).await;
+    assert_eq!(
+        error_of(flow.callback(&server, &code).await).await,
+        (403, "No account is linked to this identity".into())
+    );
+}
+
+#[actix_web::test]
+async fn links_an_identity_to_the_signed_in_user() {
+    let (server, _idp) = start(&[]).await;
+    let token = server.register("erin").await;
+
+    let flow = Flow::start(&server, Some(&token)).await;
+    let code = flow.approve("erin-at-idp", &[]).await;
+    let res = flow.callback(&server, &code).await;
+    assert_eq!(res.status(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["message"], "Identity linked");
+
+    let identities: Vec<serde_json::Value> = client()
+        .get(format!("{}/auth/oidc/identities", server.url))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0]["subject"], "erin-at-idp");
+
+    assert_eq!(sign_in(&server, "erin-at-idp").await["username"], "erin");
+}
+
+#[actix_web::test]
+async fn token_exchange_fails_when_the_challenge_does_not_match() {
+    let (server, _idp) = start(&[]).await;
+
+    let flow = Flow::start(&server, None).await;
+    let forged = URL_SAFE_NO_PAD.encode(Sha256::digest(b"someone else's verifier"));
+    let code = flow
+        .approve("carol", &[("code_challenge", forged.as_str())])
+        .await;
+    assert_eq!(
+        error_of(flow.callback(&server, &code).await).await,
+        (401, "Single sign-on failed".into())
+    );
+}
+
+#[actix_web::test]
+async fn rejects_an_id_token_with_another_nonce() {
+    let (server, _idp) = start(&[]).await;
+
+    let flow = Flow::start(&server, None).await;
+    let code = flow.approve("carol", &[("nonce", "replayed")]).await;
+    assert_eq!(
+        error_of(flow.callback(&server, &code).await).await,
+        (401, "Single sign-on failed".into())
+    );
+}
+
+#[actix_web::test]
+async fn state_can_only_be_used_once() {
+    let (server, _idp) = start(&[]).await;
+
+    let flow = Flow::start(&server, None).await;
+    let code = flow.approve("carol", &[]).await;
+    assert_eq!(flow.callback(&server, &code).await.status(), 200);
+    assert_eq!(
+        error_of(flow.callback(&server, &code).await).await,
+        (400, "Unknown or expired state".into())
+    );
+}
+
+#[actix_web::test]
+async fn rejects_an_expired_state() {
+    let (server, _idp) = start(&[]).await;
+
+    let flow = Flow::start(&server, None).await;
+    let code = flow.approve("carol", &[]).await;
+    let past = chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1);
+    sqlx::query("UPDATE oidc_states SET expires_at = ? WHERE state = ?")
+        .bind(past)
+        .bind(&flow.state)
+        .execute(&server.pool)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        error_of(flow.callback(&server, &code).await).await,
+        (400, "Unknown or expired state".into())
+    );
+}
+
+#[actix_web::test]
+async fn state_must_come_from_the_same_browser() {
+    let (server, _idp) = start(&[]).await;
+
+    let flow = Flow::start(&server, None).await;
+    let other = Flow::start(&server, None).await;
+    let code = flow.approve("carol", &[]).await;
+
+    let expected = (400, "State does not belong to this browser".to_string());
+    assert_eq!(
+        error_of(callback(&server, &code, &flow.state, None).await).await,
+        expected
+    );
+    assert_eq!(
+        error_of(callback(&server, &code, &flow.state, Some(&other.cookie)).await).await,
+        expected
+    );
+    // A rejected attempt doesn't use up the state.
+    assert_eq!(flow.callback(&server, &code).await.status(), 200);
+}
@@ -0,0 +1,191 @@
+//! Listings, search and the tree agree with the access checks on single
+//! documents.
+
+mod common;
+
+use common::{client, TestServer};
+use serde_json::Value;
+
+async fn create(
+    server: &TestServer,
+    token: &str,
+    workspace: &str,
+    title: &str,
+    parent_id: Option<&str>,
+    is_folder: bool,
+) -> String {
+    let body: Value = client()
+        .post(format!("{}/documents", server.url))
+        .bearer_auth(token)
+        .header("X-Workspace-Id", workspace)
+        .json(&serde_json::json!({
+            "title": title,
+            "content": format!("<p>{} findme</p>", title),
+            "parent_id": parent_id,
+            "is_folder": is_folder,
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["id"].as_str().unwrap().to_string()
+}
+
+async fn get(server: &TestServer, token: &str, workspace: &str, path: &str) -> Value {
+    client()
+        .get(format!("{}{}", server.url, path))
+        .bearer_auth(token)
+        .header("X-Workspace-Id", workspace)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// Titles and access levels from `GET /documents`.
+async fn listing(server: &TestServer, token: &str, workspace: &str) -> Vec<(String, String)> {
+    let docs = get(server, token, workspace, "/documents").await;
+    let mut docs: Vec<(String, String)> = docs
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| {
+            (
+                d["title"].as_str().unwrap().to_string(),
+                d["access"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    docs.sort();
+    docs
+}
+
+async fn searched(server: &TestServer, token: &str, workspace: &str) -> Vec<String> {
+    let results = get(server, token, workspace, "/search?q=findme").await;
+    let mut titles: Vec<String> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["title"].as_str().unwrap().to_string())
+        .collect();
+    titles.sort();
+    titles
+}
+
+fn tree_titles(nodes: &Value, out: &mut Vec<String>) {
+    for node in nodes.as_array().unwrap() {
+        out.push(node["title"].as_str().unwrap().to_string());
+        tree_titles(&node["children"], out);
+    }
+}
+
+async fn tree(server: &TestServer, token: &str, workspace: &str) -> Vec<String> {
+    let mut titles = Vec::new();
+    tree_titles(
+        &get(server, token, workspace, "/documents/tree").await,
+        &mut titles,
+    );
+    titles.sort();
+    titles
+}
+
+async fn access(server: &TestServer, token: &str, workspace: &str, doc_id: &str) -> String {
+    let doc = get(server, token, workspace, &format!("/documents/{}", doc_id)).await;
+    doc["access"].as_str().unwrap_or_default().to_string()
+}
+
+fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+    items
+        .iter()
+        .map(|(a, b)| (a.to_string(), b.to_string()))
+        .collect()
+}
+
+#[actix_web::test]
+async fn listings_show_the_role_of_each_document() {
+    let server = TestServer::start(&[]).await;
+    let alice = server.register("alice").await;
+    let bob = server.register("bob").await;
+    let carol = server.register("carol").await;
+
+    let body: Value = client()
+        .post(format!("{}/workspaces", server.url))
+        .bearer_auth(&alice)
+        .json(&serde_json::json!({"name": "Team"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let ws = body["id"].as_str().unwrap().to_string();
+    for (username, role) in [("bob", "member"), ("carol", "guest")] {
+        sqlx::query(
+            "INSERT INTO workspace_members (workspace_id, user_id, role) \
+             SELECT ?, id, ? FROM users WHERE username = ?",
+        )
+        .bind(&ws)
+        .bind(role)
+        .bind(username)
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    }
+
+    let folder = create(&server, &alice, &ws, "Folder", None, true).await;
+    let inner = create(&server, &alice, &ws, "Inner", Some(&folder), false).await;
+    let other = create(&server, &alice, &ws, "Other", None, false).await;
+    create(&server, &bob, &ws, "Bobs", None, false).await;
+
+    // Carol gets the folder, and with it what is inside, plus a stronger
+    // grant on one document in it.
+    for (doc_id, role) in [(&folder, "viewer"), (&inner, "editor")] {
+        let res = client()
+            .post(format!("{}/documents/{}/permissions", server.url, doc_id))
+            .bearer_auth(&alice)
+            .header("X-Workspace-Id", &ws)
+            .json(&serde_json::json!({"username": "carol", "role": role}))
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+    }
+
+    assert_eq!(
+        listing(&server, &alice, &ws).await,
+        pairs(&[
+            ("Bobs", "owner"),
+            ("Folder", "owner"),
+            ("Inner", "owner"),
+            ("Other", "owner"),
+        ])
+    );
+    assert_eq!(
+        listing(&server, &bob, &ws).await,
+        pairs(&[
+            ("Bobs", "owner"),
+            ("Folder", "editor"),
+            ("Inner", "editor"),
+            ("Other", "editor"),
+        ])
+    );
+    assert_eq!(
+        listing(&server, &carol, &ws).await,
+        pairs(&[("Folder", "viewer"), ("Inner", "editor")])
+    );
+    assert_eq!(access(&server, &carol, &ws, &folder).await, "viewer");
+    assert_eq!(access(&server, &carol, &ws, &inner).await, "editor");
+    assert_eq!(access(&server, &carol, &ws, &other).await, "");
+
+    assert_eq!(
+        searched(&server, &carol, &ws).await,
+        vec!["Folder", "Inner"]
+    );
+    assert_eq!(tree(&server, &carol, &ws).await, vec!["Folder", "Inner"]);
+    assert_eq!(searched(&server, &bob, &ws).await.len(), 4);
+    assert_eq!(tree(&server, &bob, &ws).await.len(), 4);
+}
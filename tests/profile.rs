@@ -0,0 +1,90 @@
+//! Avatar uploads, which the server keeps in the test's own data directory.
+
+mod common;
+
+use common::{client, error_of, TestServer};
+
+/// Enough of a PNG for the server to recognise it.
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+async fn upload(server: &TestServer, token: &str, body: &'static [u8]) -> reqwest::Response {
+    client()
+        .put(format!("{}/me/avatar", server.url))
+        .bearer_auth(token)
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn stored_files(server: &TestServer) -> Vec<String> {
+    match std::fs::read_dir(server.data_dir.join("avatars")) {
+        Ok(entries) => entries
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[actix_web::test]
+async fn uploads_replaces_and_deletes_avatars() {
+    let server = TestServer::start(&[]).await;
+    let token = server.register("alice").await;
+
+    let res = upload(&server, &token, PNG).await;
+    assert_eq!(res.status(), 200);
+    let profile: serde_json::Value = res.json().await.unwrap();
+    let url = profile["avatar_url"].as_str().unwrap().to_string();
+    let file = url.rsplit('/').next().unwrap().to_string();
+    assert!(file.ends_with(".png"));
+    assert_eq!(stored_files(&server), vec![file.clone()]);
+
+    let res = client()
+        .get(format!("{}/avatars/{}", server.url, file))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    assert_eq!(res.bytes().await.unwrap(), PNG);
+
+    // A new upload removes the previous file.
+    let res = upload(&server, &token, PNG).await;
+    let profile: serde_json::Value = res.json().await.unwrap();
+    let replaced = profile["avatar_url"]
+        .as_str()
+        .unwrap()
+        .rsplit('/')
+        .next()
+        .unwrap()
+        .to_string();
+    assert_ne!(replaced, file);
+    assert_eq!(stored_files(&server), vec![replaced.clone()]);
+    let res = client()
+        .get(format!("{}/avatars/{}", server.url, file))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(error_of(res).await, (400, "Avatar not found".into()));
+
+    let res = client()
+        .delete(format!("{}/me/avatar", server.url))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert!(stored_files(&server).is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_files_that_are_not_images() {
+    let server = TestServer::start(&[]).await;
+    let token = server.register("alice").await;
+
+    assert_eq!(
+        error_of(upload(&server, &token, b"<svg></svg>").await).await,
+        (400, "Avatar must be a PNG, JPEG, GIF or WebP image".into())
+    );
+    assert!(stored_files(&server).is_empty());
+}
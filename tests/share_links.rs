@@ -0,0 +1,163 @@
+//! Share links on workspace documents, whose owners can change over time.
+
+mod common;
+
+use common::{client, error_of, TestServer};
+
+struct Workspace {
+    id: String,
+    alice: String,
+    bob: String,
+}
+
+/// A workspace owned by alice with bob as a member.
+async fn workspace(server: &TestServer) -> Workspace {
+    let alice = server.register("alice").await;
+    let bob = server.register("bob").await;
+    let body: serde_json::Value = client()
+        .post(format!("{}/workspaces", server.url))
+        .bearer_auth(&alice)
+        .json(&serde_json::json!({"name": "Team"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = body["id"].as_str().unwrap().to_string();
+    sqlx::query(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) \
+         SELECT ?, id, 'member' FROM users WHERE username = 'bob'",
+    )
+    .bind(&id)
+    .execute(&server.pool)
+    .await
+    .unwrap();
+    Workspace { id, alice, bob }
+}
+
+async fn bob_id(server: &TestServer) -> String {
+    sqlx::query_scalar("SELECT id FROM users WHERE username = 'bob'")
+        .fetch_one(&server.pool)
+        .await
+        .unwrap()
+}
+
+/// Bob creates a document in the workspace and shares it; returns the link
+/// id and token.
+async fn bobs_link(server: &TestServer, ws: &Workspace) -> (String, String) {
+    let doc: serde_json::Value = client()
+        .post(format!("{}/documents", server.url))
+        .bearer_auth(&ws.bob)
+        .header("X-Workspace-Id", &ws.id)
+        .json(&serde_json::json!({"title": "Plan", "content": "<p>hi</p>", "is_folder": false}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let res = client()
+        .post(format!(
+            "{}/documents/{}/share-links",
+            server.url,
+            doc["id"].as_str().unwrap()
+        ))
+        .bearer_auth(&ws.bob)
+        .header("X-Workspace-Id", &ws.id)
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 201);
+    let link: serde_json::Value = res.json().await.unwrap();
+    (
+        link["id"].as_str().unwrap().to_string(),
+        link["token"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn open(server: &TestServer, token: &str) -> reqwest::Response {
+    client()
+        .get(format!("{}/s/{}?format=json", server.url, token))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn listed(server: &TestServer, token: &str) -> Vec<String> {
+    let links: Vec<serde_json::Value> = client()
+        .get(format!("{}/share-links", server.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    links
+        .iter()
+        .map(|l| l["id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[actix_web::test]
+async fn links_stop_working_when_the_creator_loses_ownership() {
+    let server = TestServer::start(&[]).await;
+    let ws = workspace(&server).await;
+    let (_, token) = bobs_link(&server, &ws).await;
+
+    let res = open(&server, &token).await;
+    assert_eq!(res.status(), 200);
+    let page: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(page["title"], "Plan");
+
+    let res = client()
+        .put(format!(
+            "{}/workspaces/{}/members/{}",
+            server.url,
+            ws.id,
+            bob_id(&server).await
+        ))
+        .bearer_auth(&ws.alice)
+        .json(&serde_json::json!({"role": "guest"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    assert_eq!(
+        error_of(open(&server, &token).await).await,
+        (400, "Share link not found".into())
+    );
+}
+
+#[actix_web::test]
+async fn owners_list_and_revoke_links_they_did_not_create() {
+    let server = TestServer::start(&[]).await;
+    let ws = workspace(&server).await;
+    let carol = server.register("carol").await;
+    let (link_id, token) = bobs_link(&server, &ws).await;
+
+    assert_eq!(listed(&server, &ws.bob).await, vec![link_id.clone()]);
+    assert_eq!(listed(&server, &ws.alice).await, vec![link_id.clone()]);
+    assert!(listed(&server, &carol).await.is_empty());
+
+    let revoke = |token: String| {
+        client()
+            .delete(format!("{}/share-links/{}", server.url, link_id))
+            .bearer_auth(token)
+            .send()
+    };
+    assert_eq!(
+        error_of(revoke(carol).await.unwrap()).await,
+        (400, "Share link not found".into())
+    );
+    assert_eq!(revoke(ws.alice.clone()).await.unwrap().status(), 200);
+
+    assert!(listed(&server, &ws.bob).await.is_empty());
+    assert_eq!(
+        error_of(open(&server, &token).await).await,
+        (400, "Share link not found".into())
+    );
+}
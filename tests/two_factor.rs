@@ -0,0 +1,112 @@
+//! TOTP enrollment with secrets encrypted at rest.
+
+mod common;
+
+use common::{client, error_of, TestServer, PASSWORD};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+fn code(secret: &str, step: i64) -> String {
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        Secret::Encoded(secret.to_string()).to_bytes().unwrap(),
+        None,
+        "alice".to_string(),
+    )
+    .unwrap();
+    let now = chrono::Utc::now().timestamp() + step * 30;
+    totp.generate(now as u64)
+}
+
+async fn post(
+    server: &TestServer,
+    token: &str,
+    path: &str,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    client()
+        .post(format!("{}{}", server.url, path))
+        .bearer_auth(token)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn stored_secret(server: &TestServer) -> String {
+    sqlx::query_scalar("SELECT totp_secret FROM users WHERE username = 'alice'")
+        .fetch_one(&server.pool)
+        .await
+        .unwrap()
+}
+
+/// Enrolls alice and returns the plaintext secret.
+async fn enroll(server: &TestServer, token: &str) -> String {
+    let res = post(server, token, "/auth/2fa/enroll", serde_json::json!({})).await;
+    assert_eq!(res.status(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    let secret = body["secret"].as_str().unwrap().to_string();
+    let res = post(
+        server,
+        token,
+        "/auth/2fa/confirm",
+        serde_json::json!({"code": code(&secret, 0)}),
+    )
+    .await;
+    assert_eq!(res.status(), 200);
+    secret
+}
+
+#[actix_web::test]
+async fn secrets_are_stored_encrypted() {
+    let server = TestServer::start(&[]).await;
+    let token = server.register("alice").await;
+    let secret = enroll(&server, &token).await;
+
+    let stored = stored_secret(&server).await;
+    assert!(stored.starts_with("v1:"), "{}", stored);
+    assert!(!stored.contains(&secret));
+
+    // A secret from before encryption still verifies.
+    sqlx::query("UPDATE users SET totp_secret = ? WHERE username = 'alice'")
+        .bind(&secret)
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    let res = post(
+        &server,
+        &token,
+        "/auth/2fa/disable",
+        serde_json::json!({"password": PASSWORD, "code": code(&secret, 1)}),
+    )
+    .await;
+    assert_eq!(res.status(), 200);
+}
+
+#[actix_web::test]
+async fn disabling_counts_wrong_passwords_like_logins() {
+    let server = TestServer::start(&[]).await;
+    let token = server.register("alice").await;
+    let secret = enroll(&server, &token).await;
+
+    let res = post(
+        &server,
+        &token,
+        "/auth/2fa/disable",
+        serde_json::json!({"password": "wrong", "code": code(&secret, 1)}),
+    )
+    .await;
+    assert_eq!(error_of(res).await, (400, "Invalid credentials".into()));
+
+    // The failure backs off before the next attempt, even a correct one.
+    let res = post(
+        &server,
+        &token,
+        "/auth/2fa/disable",
+        serde_json::json!({"password": PASSWORD, "code": code(&secret, 1)}),
+    )
+    .await;
+    assert_eq!(res.status(), 429);
+}
@@ -0,0 +1,424 @@
+//! Passkey registration and sign-in with a software authenticator holding a
+//! P-256 key, as a browser would relay it.
+
+mod common;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value;
+use common::{client, error_of, TestServer};
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use sha2::{Digest, Sha256};
+
+const RP_ID: &str = "docs.example";
+const ORIGIN: &str = "https://docs.example";
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+fn b64(data: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+fn cbor(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    ciborium::ser::into_writer(value, &mut out).unwrap();
+    out
+}
+
+/// Where a ceremony claims to have happened; `HONEST` is the configured relying party.
+struct Claims<'a> {
+    origin: &'a str,
+    rp_id: &'a str,
+}
+
+const HONEST: Claims = Claims {
+    origin: ORIGIN,
+    rp_id: RP_ID,
+};
+
+struct Authenticator {
+    key: SigningKey,
+    credential_id: Vec<u8>,
+    counter: u32,
+}
+
+impl Authenticator {
+    fn new() -> Authenticator {
+        Authenticator {
+            key: SigningKey::random(&mut rand::rngs::OsRng),
+            credential_id: rand::random::<[u8; 16]>().to_vec(),
+            counter: 0,
+        }
+    }
+
+    fn id(&self) -> String {
+        b64(&self.credential_id)
+    }
+
+    /// The public key as an ES256 COSE_Key.
+    fn cose_key(&self) -> Vec<u8> {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        let int = |n: i64| Value::Integer(n.into());
+        cbor(&Value::Map(vec![
+            (int(1), int(2)),
+            (int(3), int(-7)),
+            (int(-1), int(1)),
+            (int(-2), Value::Bytes(point.x().unwrap().to_vec())),
+            (int(-3), Value::Bytes(point.y().unwrap().to_vec())),
+        ]))
+    }
+
+    fn client_data(ceremony: &str, challenge: &str, claims: &Claims) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "type": ceremony,
+            "challenge": challenge,
+            "origin": claims.origin,
+        }))
+        .unwrap()
+    }
+
+    fn authenticator_data(&self, flags: u8, counter: u32, claims: &Claims) -> Vec<u8> {
+        let mut data = Sha256::digest(claims.rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&counter.to_be_bytes());
+        if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+            data.extend_from_slice(&[0; 16]); // AAGUID
+            data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(&self.credential_id);
+            data.extend_from_slice(&self.cose_key());
+        }
+        data
+    }
+
+    /// `navigator.credentials.create()` with "none" attestation.
+    fn create(&self, challenge: &str, claims: &Claims) -> serde_json::Value {
+        let auth_data = self.authenticator_data(
+            FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA,
+            self.counter,
+            claims,
+        );
+        let attestation = Value::Map(vec![
+            (Value::Text("fmt".into()), Value::Text("none".into())),
+            (Value::Text("attStmt".into()), Value::Map(Vec::new())),
+            (Value::Text("authData".into()), Value::Bytes(auth_data)),
+        ]);
+        serde_json::json!({
+            "id": self.id(),
+            "response": {
+                "clientDataJSON": b64(&Self::client_data("webauthn.create", challenge, claims)),
+                "attestationObject": b64(&cbor(&attestation)),
+            },
+        })
+    }
+
+    /// `navigator.credentials.get()`, signing with `key` at `counter`.
+    fn get_with(
+        &self,
+        challenge: &str,
+        user_handle: &str,
+        claims: &Claims,
+        counter: u32,
+        key: &SigningKey,
+    ) -> serde_json::Value {
+        let client_data = Self::client_data("webauthn.get", challenge, claims);
+        let auth_data = self.authenticator_data(FLAG_USER_PRESENT, counter, claims);
+        let mut message = auth_data.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data));
+        let signature: Signature = key.sign(&message);
+        serde_json::json!({
+            "id": self.id(),
+            "response": {
+                "clientDataJSON": b64(&client_data),
+                "authenticatorData": b64(&auth_data),
+                "signature": b64(signature.to_der().as_bytes()),
+                "userHandle": user_handle,
+            },
+        })
+    }
+
+    fn get(&mut self, challenge: &str, user_handle: &str) -> serde_json::Value {
+        self.counter += 1;
+        self.get_with(challenge, user_handle, &HONEST, self.counter, &self.key)
+    }
+}
+
+async fn start() -> TestServer {
+    TestServer::start(&[("WEBAUTHN_RP_ID", RP_ID), ("WEBAUTHN_ORIGIN", ORIGIN)]).await
+}
+
+async fn post(
+    server: &TestServer,
+    path: &str,
+    token: Option<&str>,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    let mut req = client().post(format!("{}{}", server.url, path)).json(&body);
+    if let Some(token) = token {
+        req = req.bearer_auth(token);
+    }
+    req.send().await.unwrap()
+}
+
+async fn json(res: reqwest::Response) -> serde_json::Value {
+    assert_eq!(res.status(), 200);
+    res.json().await.unwrap()
+}
+
+/// Starts a registration; returns the challenge id, the challenge and the
+/// user handle.
+async fn start_registration(server: &TestServer, token: &str) -> (String, String, String) {
+    let options = json(
+        post(
+            server,
+            "/auth/webauthn/register/start",
+            Some(token),
+            serde_json::json!({}),
+        )
+        .await,
+    )
+    .await;
+    assert_eq!(options["publicKey"]["rp"]["id"], RP_ID);
+    (
+        options["challenge_id"].as_str().unwrap().to_string(),
+        options["publicKey"]["challenge"]
+            .as_str()
+            .unwrap()
+            .to_string(),
+        options["publicKey"]["user"]["id"]
+            .as_str()
+            .unwrap()
+            .to_string(),
+    )
+}
+
+async fn finish_registration(
+    server: &TestServer,
+    token: &str,
+    challenge_id: &str,
+    credential: serde_json::Value,
+) -> reqwest::Response {
+    post(
+        server,
+        "/auth/webauthn/register/finish",
+        Some(token),
+        serde_json::json!({
+            "challenge_id": challenge_id,
+            "name": "Software key",
+            "credential": credential,
+        }),
+    )
+    .await
+}
+
+/// Registers a fresh authenticator for a new user; returns it with the
+/// user handle.
+async fn enrolled(server: &TestServer, username: &str) -> (Authenticator, String) {
+    let token = server.register(username).await;
+    let authenticator = Authenticator::new();
+    let (challenge_id, challenge, user_handle) = start_registration(server, &token).await;
+    let res = finish_registration(
+        server,
+        &token,
+        &challenge_id,
+        authenticator.create(&challenge, &HONEST),
+    )
+    .await;
+    assert_eq!(json(res).await["name"], "Software key");
+    (authenticator, user_handle)
+}
+
+/// Starts a sign-in for `username`; returns the challenge id, the challenge
+/// and the allowed credential ids.
+async fn start_login(server: &TestServer, username: &str) -> (String, String, Vec<String>) {
+    let options = json(
+        post(
+            server,
+            "/auth/webauthn/login/start",
+            None,
+            serde_json::json!({"username": username}),
+        )
+        .await,
+    )
+    .await;
+    assert_eq!(options["publicKey"]["rpId"], RP_ID);
+    let allowed = options["publicKey"]["allowCredentials"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["id"].as_str().unwrap().to_string())
+        .collect();
+    (
+        options["challenge_id"].as_str().unwrap().to_string(),
+        options["publicKey"]["challenge"]
+            .as_str()
+            .unwrap()
+            .to_string(),
+        allowed,
+    )
+}
+
+async fn finish_login(
+    server: &TestServer,
+    challenge_id: &str,
+    credential: serde_json::Value,
+) -> reqwest::Response {
+    post(
+        server,
+        "/auth/webauthn/login/finish",
+        None,
+        serde_json::json!({"challenge_id": challenge_id, "credential": credential}),
+    )
+    .await
+}
+
+#[actix_web::test]
+async fn registers_a_passkey_and_signs_in_with_it() {
+    let server = start().await;
+    let (mut authenticator, user_handle) = enrolled(&server, "alice").await;
+
+    for _ in 0..2 {
+        let (challenge_id, challenge, allowed) = start_login(&server, "alice").await;
+        assert_eq!(allowed, vec![authenticator.id()]);
+        let res = finish_login(
+            &server,
+            &challenge_id,
+            authenticator.get(&challenge, &user_handle),
+        )
+        .await;
+        let body = json(res).await;
+        assert_eq!(body["username"], "alice");
+        assert!(body["token"].is_string());
+    }
+
+    let sign_count: i64 = sqlx::query_scalar("SELECT sign_count FROM webauthn_credentials")
+        .fetch_one(&server.pool)
+        .await
+        .unwrap();
+    assert_eq!(sign_count, 2);
+}
+
+#[actix_web::test]
+async fn registration_rejects_another_origin_or_relying_party() {
+    let server = start().await;
+    let token = server.register("alice").await;
+    let authenticator = Authenticator::new();
+
+    let cases = [
+        (
+            Claims {
+                origin: "https://evil.example",
+                rp_id: RP_ID,
+            },
+            "Origin mismatch",
+        ),
+        (
+            Claims {
+                origin: ORIGIN,
+                rp_id: "evil.example",
+            },
+            "Relying party mismatch",
+        ),
+    ];
+    for (claims, error) in cases {
+        let (challenge_id, challenge, _) = start_registration(&server, &token).await;
+        let res = finish_registration(
+            &server,
+            &token,
+            &challenge_id,
+            authenticator.create(&challenge, &claims),
+        )
+        .await;
+        assert_eq!(error_of(res).await, (400, error.to_string()));
+    }
+
+    let registered: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM webauthn_credentials")
+        .fetch_one(&server.pool)
+        .await
+        .unwrap();
+    assert_eq!(registered, 0);
+}
+
+#[actix_web::test]
+async fn sign_in_rejects_another_origin_or_relying_party() {
+    let server = start().await;
+    let (authenticator, user_handle) = enrolled(&server, "alice").await;
+
+    let cases = [
+        (
+            Claims {
+                origin: "https://evil.example",
+                rp_id: RP_ID,
+            },
+            "Origin mismatch",
+        ),
+        (
+            Claims {
+                origin: ORIGIN,
+                rp_id: "evil.example",
+            },
+            "Relying party mismatch",
+        ),
+    ];
+    for (claims, error) in cases {
+        let (challenge_id, challenge, _) = start_login(&server, "alice").await;
+        let credential =
+            authenticator.get_with(&challenge, &user_handle, &claims, 1, &authenticator.key);
+        let res = finish_login(&server, &challenge_id, credential).await;
+        assert_eq!(error_of(res).await, (400, error.to_string()));
+    }
+}
+
+#[actix_web::test]
+async fn sign_in_rejects_a_counter_that_does_not_increase() {
+    let server = start().await;
+    let (authenticator, user_handle) = enrolled(&server, "alice").await;
+
+    let (challenge_id, challenge, _) = start_login(&server, "alice").await;
+    let credential =
+        authenticator.get_with(&challenge, &user_handle, &HONEST, 5, &authenticator.key);
+    assert_eq!(
+        finish_login(&server, &challenge_id, credential)
+            .await
+            .status(),
+        200
+    );
+
+    // A cloned authenticator would replay or fall behind the stored count.
+    for counter in [5, 4] {
+        let (challenge_id, challenge, _) = start_login(&server, "alice").await;
+        let credential = authenticator.get_with(
+            &challenge,
+            &user_handle,
+            &HONEST,
+            counter,
+            &authenticator.key,
+        );
+        let res = finish_login(&server, &challenge_id, credential).await;
+        assert_eq!(
+            error_of(res).await,
+            (401, "Credential counter did not increase".into())
+        );
+    }
+}
+
+#[actix_web::test]
+async fn sign_in_rejects_a_bad_signature() {
+    let server = start().await;
+    let (authenticator, user_handle) = enrolled(&server, "alice").await;
+
+    // Signed by a key other than the registered one.
+    let (challenge_id, challenge, _) = start_login(&server, "alice").await;
+    let other = SigningKey::random(&mut rand::rngs::OsRng);
+    let credential = authenticator.get_with(&challenge, &user_handle, &HONEST, 1, &other);
+    let res = finish_login(&server, &challenge_id, credential).await;
+    assert_eq!(error_of(res).await, (401, "Invalid signature".into()));
+
+    // Authenticator data changed after signing.
+    let (challenge_id, challenge, _) = start_login(&server, "alice").await;
+    let mut credential =
+        authenticator.get_with(&challenge, &user_handle, &HONEST, 1, &authenticator.key);
+    let tampered = authenticator.authenticator_data(FLAG_USER_PRESENT, 100, &HONEST);
+    credential["response"]["authenticatorData"] = b64(&tampered).into();
+    let res = finish_login(&server, &challenge_id, credential).await;
+    assert_eq!(error_of(res).await, (401, "Invalid signature".into()));
+}
@@ -0,0 +1,186 @@
+//! Workspace member management.
+
+mod common;
+
+use common::{client, error_of, TestServer};
+
+/// Creates a workspace owned by `owner` and adds the others with a role.
+async fn workspace(server: &TestServer, owner: &str, members: &[(&str, &str)]) -> String {
+    let body: serde_json::Value = client()
+        .post(format!("{}/workspaces", server.url))
+        .bearer_auth(owner)
+        .json(&serde_json::json!({"name": "Team"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = body["id"].as_str().unwrap().to_string();
+    for (username, role) in members {
+        sqlx::query(
+            "INSERT INTO workspace_members (workspace_id, user_id, role) \
+             SELECT ?, id, ? FROM users WHERE username = ?",
+        )
+        .bind(&id)
+        .bind(role)
+        .bind(username)
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    }
+    id
+}
+
+async fn user_id(server: &TestServer, username: &str) -> String {
+    sqlx::query_scalar("SELECT id FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_one(&server.pool)
+        .await
+        .unwrap()
+}
+
+async fn set_role(
+    server: &TestServer,
+    token: &str,
+    workspace_id: &str,
+    member_id: &str,
+    role: &str,
+) -> reqwest::Response {
+    client()
+        .put(format!(
+            "{}/workspaces/{}/members/{}",
+            server.url, workspace_id, member_id
+        ))
+        .bearer_auth(token)
+        .json(&serde_json::json!({"role": role}))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn only_owners_promote_admins() {
+    let server = TestServer::start(&[]).await;
+    let alice = server.register("alice").await;
+    let bob = server.register("bob").await;
+    server.register("carol").await;
+    let ws = workspace(&server, &alice, &[("bob", "admin"), ("carol", "member")]).await;
+    let carol = user_id(&server, "carol").await;
+
+    assert_eq!(
+        error_of(set_role(&server, &bob, &ws, &carol, "admin").await).await,
+        (403, "Only owners can promote admins".into())
+    );
+    assert_eq!(
+        set_role(&server, &bob, &ws, &carol, "guest").await.status(),
+        200
+    );
+    assert_eq!(
+        set_role(&server, &alice, &ws, &carol, "admin")
+            .await
+            .status(),
+        200
+    );
+}
+
+#[actix_web::test]
+async fn the_last_owner_stays() {
+    let server = TestServer::start(&[]).await;
+    let alice = server.register("alice").await;
+    let ws = workspace(&server, &alice, &[]).await;
+    let alice_id = user_id(&server, "alice").await;
+
+    assert_eq!(
+        error_of(set_role(&server, &alice, &ws, &alice_id, "admin").await).await,
+        (400, "A workspace needs at least one owner".into())
+    );
+    let res = client()
+        .delete(format!(
+            "{}/workspaces/{}/members/{}",
+            server.url, ws, alice_id
+        ))
+        .bearer_auth(&alice)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        error_of(res).await,
+        (400, "A workspace needs at least one owner".into())
+    );
+    let role: String = sqlx::query_scalar(
+        "SELECT role FROM workspace_members WHERE workspace_id = ? AND user_id = ?",
+    )
+    .bind(&ws)
+    .bind(&alice_id)
+    .fetch_one(&server.pool)
+    .await
+    .unwrap();
+    assert_eq!(role, "owner");
+}
+
+#[actix_web::test]
+async fn renames_and_cancelled_invitations_are_audited() {
+    let server = TestServer::start(&[]).await;
+    let alice = server.register("alice").await;
+    server.register("bob").await;
+    let ws = workspace(&server, &alice, &[]).await;
+
+    let res = client()
+        .put(format!("{}/workspaces/{}", server.url, ws))
+        .bearer_auth(&alice)
+        .json(&serde_json::json!({"name": "Platform"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let res = client()
+        .post(format!("{}/workspaces/{}/invitations", server.url, ws))
+        .bearer_auth(&alice)
+        .json(&serde_json::json!({"username": "bob", "role": "member"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let invitation_id: String =
+        sqlx::query_scalar("SELECT id FROM workspace_invitations WHERE workspace_id = ?")
+            .bind(&ws)
+            .fetch_one(&server.pool)
+            .await
+            .unwrap();
+    let res = client()
+        .delete(format!(
+            "{}/workspaces/{}/invitations/{}",
+            server.url, ws, invitation_id
+        ))
+        .bearer_auth(&alice)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let events: Vec<(String, String)> = sqlx::query_as(
+        "SELECT action, details FROM audit_events \
+         WHERE target_id = ? AND action IN ('workspace.update', 'workspace.invitation.cancel') \
+         ORDER BY created_at, action DESC",
+    )
+    .bind(&ws)
+    .fetch_all(&server.pool)
+    .await
+    .unwrap();
+    let events: Vec<(String, serde_json::Value)> = events
+        .into_iter()
+        .map(|(action, details)| (action, serde_json::from_str(&details).unwrap()))
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].0, "workspace.update");
+    assert_eq!(
+        events[0].1,
+        serde_json::json!({"from": "Team", "to": "Platform"})
+    );
+    assert_eq!(events[1].0, "workspace.invitation.cancel");
+    assert_eq!(events[1].1["invitation_id"], invitation_id);
+    assert_eq!(events[1].1["user_id"], user_id(&server, "bob").await);
+    assert_eq!(events[1].1["role"], "member");
+}